@@ -0,0 +1,1434 @@
+use anyhow::{bail, Result};
+use std::path::PathBuf;
+
+use crate::{config::TimeSpan, environment, import, init, units};
+
+pub const USAGE: &str = "\
+Usage: restic-generator [OPTIONS] <normal-dir> [<early-dir> <late-dir>]
+       restic-generator [OPTIONS] --stdout
+       restic-generator [OPTIONS] <command> [<args>]
+
+Generate systemd units running restic from a configuration file.
+
+Arguments:
+  <normal-dir>  Directory where the units are written, timers are enabled
+                in its timers.target.wants
+  <early-dir>   Directory for units overriding /etc (unused)
+  <late-dir>    Directory for units with low priority (unused)
+
+Options:
+  -c, --config <path>  Read the configuration from <path>
+      --user           Generate user units
+      --system         Generate system units
+      --stdout         Print the units instead of writing them
+      --manifest json  Print the manifest of the generated files instead of
+                       writing it next to the units
+  -h, --help           Print this help and exit
+  -V, --version        Print the version and exit
+
+Commands:
+  render (--out-dir <dir> | --stdout) [--force | --skip-existing]
+         [--format systemd|crontab|launchd|scripts]
+      Write the units to an arbitrary directory, or print them. Existing
+      files are an error unless --force (overwrite) or --skip-existing
+      (keep) is given. The other formats render the same commands as a
+      crontab, launchd jobs or shell scripts instead of systemd units;
+      crontab and launchd only take the hourly, daily, weekly, monthly and
+      yearly calendars.
+
+  validate
+      Check the configuration and list the problems found. Exits with a
+      non-zero status if there are any. Generating or rendering units fails
+      on the same problems unless the configuration sets on-problems = \"warn\".
+
+  verify
+      Run systemd-analyze verify on each generated unit and report what it
+      finds. Exits with a non-zero status if a unit fails verification.
+
+  diff [--against <dir>]
+      Compare the units with the ones systemd currently uses (in the
+      generator directory, or <dir>), and show what would change after a
+      daemon-reload. Exits with a non-zero status if anything would.
+
+  list [--json]
+      List the units that would be generated for each repository, and why
+      some of them are skipped.
+
+  env <repository> [--format shell|systemd|dotenv]
+      Print the environment variables giving access to a repository, as
+      shell exports (the default), systemd Environment= lines or a dotenv
+      file.
+
+  exec <repository> [--] <restic-args>...
+      Run restic with the environment giving access to a repository.
+
+  preview-forget <repository>
+      Show which snapshots the generated forget unit would remove, by running
+      it with --dry-run.
+
+  stats <repository> [--output <path>]
+        [--resolved [--option <name=value>]...]
+      Print the raw size, restore size and snapshot count of a repository as
+      JSON, or write them to <path>. Run by the generated stats units with
+      --resolved, as freshness.
+
+  freshness <repository>
+  freshness <repository> --resolved --max-age <span> (--host <host>)...
+            [--option <name=value>]...
+      Fail if the latest snapshot of one of the hosts of the backups of
+      this machine in a repository is older than its max-age. Run by the
+      generated freshness units with --resolved: the repository is then
+      the one of the restic environment variables, with the settings given
+      as options, and the configuration is not read.
+
+  snapshot-diff <repository> [--job <name>]
+  snapshot-diff <repository> --resolved --host <host> (--path <path>)...
+                [--option <name=value>]...
+      Show what changed between the last two snapshots of a job (by default
+      the top-level one) in a repository. Run by the generated diff units
+      with --resolved, comparing the snapshots of the paths by the host, as
+      freshness.
+
+  init [--source <path>] [--name <name>] [--location <location>]
+       [--password-file <path> | --password-command <command>] [--force]
+      Write a commented starter configuration to the configuration path,
+      asking for the values not given as options when run interactively.
+
+  status [--json]
+      Show when the generated units last ran and whether they succeeded,
+      per repository.
+
+  next-runs
+      Show when the timers of the generated units elapse next.
+
+  completions bash|zsh|fish
+      Print a completion script for the given shell.
+
+  schema
+      Print a JSON Schema of the configuration, for editors and CI.
+
+  import --from resticprofile|autorestic <path>
+      Print a configuration converted from a resticprofile or autorestic
+      configuration file, and warn about the settings it leaves out.
+
+  doctor [--check-credentials]
+      Check that restic is installed and recent enough, that password
+      files exist and are private and that local repositories exist. With
+      --check-credentials, also try to open every repository. Exits with 0
+      if all is well, 2 if there are warnings and 1 if a check failed.
+
+The configuration is read from the --config option if given, otherwise from
+$RESTIC_GENERATOR_CONFIG, otherwise from the first file that exists among
+$XDG_CONFIG_HOME/restic-generator/config.toml and
+~/.config/restic-generator/config.toml (user units only), and
+/etc/restic-generator/config.toml. Files ending in .yaml, .yml or .json are
+read as YAML or JSON, and any other as TOML. The files listed in its include
+setting, then the .toml, .yaml, .yml and .json files of the conf.d directory
+next to it, then the repositories of the repositories.d directory next to it,
+one per file and named after the file by default, are merged into it in
+order: tables are merged, repositories with the same name too, and other lists
+are appended to. If the path is a directory, the files in it are merged
+instead. ${VAR} in paths, locations and credentials is replaced with the value
+of the environment variable VAR; write $${VAR} for a literal ${VAR}.
+[[repository]], [[job]] and [[copy]] are the same as [[repositories]],
+[[jobs]] and [[copies]], and the keep-* settings of a job override the ones of
+its repositories. A copy (from, to and calendar) replicates the snapshots of a
+repository to another one with restic copy. Files
+encrypted with sops, entirely or only some of their values, are decrypted
+with sops --decrypt when they are read.";
+
+/// Whether we generate units for the system or for a user manager.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    System,
+    User,
+}
+
+/// What to do with files that already exist in the output directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overwrite {
+    Fail,
+    Force,
+    Skip,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RenderTarget {
+    Stdout,
+    Dir { path: PathBuf, overwrite: Overwrite },
+}
+
+/// The settings of a repository the generated units give the commands they
+/// run, which then don't read the configuration: it may need variables or
+/// keys the environment of the units lacks
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Resolved {
+    /// The backend options, e.g. `s3.region=eu-west-1`
+    pub options: Vec<String>,
+    pub hosts: Vec<String>,
+    pub max_age: Option<TimeSpan>,
+    /// The paths of the snapshots to compare
+    pub paths: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    Help,
+    Version,
+    /// Run as a systemd generator
+    Generate {
+        normal_dir: PathBuf,
+        early_dir: Option<PathBuf>,
+        late_dir: Option<PathBuf>,
+        print_manifest: bool,
+    },
+    Render {
+        target: RenderTarget,
+        format: units::Format,
+    },
+    Validate,
+    Verify,
+    Diff {
+        against: Option<PathBuf>,
+    },
+    List {
+        json: bool,
+    },
+    Doctor {
+        check_credentials: bool,
+    },
+    Env {
+        repository: String,
+        format: environment::Format,
+    },
+    Exec {
+        repository: String,
+        args: Vec<String>,
+    },
+    PreviewForget {
+        repository: String,
+    },
+    Stats {
+        repository: String,
+        output: Option<PathBuf>,
+        resolved: Option<Resolved>,
+    },
+    Freshness {
+        repository: String,
+        resolved: Option<Resolved>,
+    },
+    SnapshotDiff {
+        repository: String,
+        job: Option<String>,
+        resolved: Option<Resolved>,
+    },
+    Init(init::Options),
+    Status {
+        json: bool,
+    },
+    NextRuns,
+    Completions {
+        shell: String,
+    },
+    Schema,
+    Import {
+        from: import::Tool,
+        path: PathBuf,
+    },
+    /// Print the names of the repositories, for completion scripts
+    ListRepositories,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Args {
+    pub config: Option<PathBuf>,
+    pub scope: Option<Scope>,
+    pub command: Command,
+}
+
+/// What an option or command takes as value, for completions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value {
+    None,
+    Path,
+    Text,
+    Choice(&'static [&'static str]),
+    /// The name of a repository from the configuration
+    Repository,
+}
+
+#[derive(Debug)]
+pub struct OptionSpec {
+    pub long: &'static str,
+    pub short: Option<char>,
+    pub value: Value,
+}
+
+#[derive(Debug)]
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub about: &'static str,
+    pub options: &'static [OptionSpec],
+    /// The first positional argument
+    pub argument: Value,
+}
+
+const fn flag(long: &'static str) -> OptionSpec {
+    OptionSpec {
+        long,
+        short: None,
+        value: Value::None,
+    }
+}
+
+const fn option(long: &'static str, value: Value) -> OptionSpec {
+    OptionSpec {
+        long,
+        short: None,
+        value,
+    }
+}
+
+/// The options accepted by all commands (and generator mode)
+pub const GLOBAL_OPTIONS: &[OptionSpec] = &[
+    OptionSpec {
+        long: "--config",
+        short: Some('c'),
+        value: Value::Path,
+    },
+    flag("--user"),
+    flag("--system"),
+    OptionSpec {
+        long: "--help",
+        short: Some('h'),
+        value: Value::None,
+    },
+    OptionSpec {
+        long: "--version",
+        short: Some('V'),
+        value: Value::None,
+    },
+];
+
+/// The options of generator mode
+pub const GENERATOR_OPTIONS: &[OptionSpec] = &[
+    flag("--stdout"),
+    option("--manifest", Value::Choice(&["json"])),
+];
+
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish"];
+
+/// The output formats of `render`
+const FORMATS: &[&str] = &["systemd", "crontab", "launchd", "scripts"];
+
+/// The tools `import` reads the configuration of
+const IMPORT_TOOLS: &[&str] = &["resticprofile", "autorestic"];
+
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "render",
+        about: "Write the units to a directory, or print them",
+        options: &[
+            OptionSpec {
+                long: "--out-dir",
+                short: Some('o'),
+                value: Value::Path,
+            },
+            flag("--stdout"),
+            flag("--force"),
+            flag("--skip-existing"),
+            option("--format", Value::Choice(FORMATS)),
+        ],
+        argument: Value::None,
+    },
+    CommandSpec {
+        name: "validate",
+        about: "Check the configuration",
+        options: &[],
+        argument: Value::None,
+    },
+    CommandSpec {
+        name: "verify",
+        about: "Check the units with systemd-analyze",
+        options: &[],
+        argument: Value::None,
+    },
+    CommandSpec {
+        name: "diff",
+        about: "Show how the units would change",
+        options: &[option("--against", Value::Path)],
+        argument: Value::None,
+    },
+    CommandSpec {
+        name: "list",
+        about: "List the units generated for each repository",
+        options: &[flag("--json")],
+        argument: Value::None,
+    },
+    CommandSpec {
+        name: "doctor",
+        about: "Check the runtime environment",
+        options: &[flag("--check-credentials")],
+        argument: Value::None,
+    },
+    CommandSpec {
+        name: "env",
+        about: "Print the environment of a repository",
+        options: &[option(
+            "--format",
+            Value::Choice(&["shell", "systemd", "dotenv"]),
+        )],
+        argument: Value::Repository,
+    },
+    CommandSpec {
+        name: "exec",
+        about: "Run restic on a repository",
+        options: &[],
+        argument: Value::Repository,
+    },
+    CommandSpec {
+        name: "preview-forget",
+        about: "Show what forget would remove",
+        options: &[],
+        argument: Value::Repository,
+    },
+    CommandSpec {
+        name: "stats",
+        about: "Print the size of a repository",
+        options: &[
+            option("--output", Value::Path),
+            flag("--resolved"),
+            option("--option", Value::Text),
+        ],
+        argument: Value::Repository,
+    },
+    CommandSpec {
+        name: "freshness",
+        about: "Check that the latest snapshot is recent",
+        options: &[
+            flag("--resolved"),
+            option("--max-age", Value::Text),
+            option("--host", Value::Text),
+            option("--option", Value::Text),
+        ],
+        argument: Value::Repository,
+    },
+    CommandSpec {
+        name: "snapshot-diff",
+        about: "Show what the last backup changed",
+        options: &[
+            option("--job", Value::Text),
+            flag("--resolved"),
+            option("--host", Value::Text),
+            option("--path", Value::Text),
+            option("--option", Value::Text),
+        ],
+        argument: Value::Repository,
+    },
+    CommandSpec {
+        name: "init",
+        about: "Write a starter configuration",
+        options: &[
+            option("--source", Value::Path),
+            option("--name", Value::Text),
+            option("--location", Value::Path),
+            option("--password-file", Value::Path),
+            option("--password-command", Value::Text),
+            flag("--force"),
+        ],
+        argument: Value::None,
+    },
+    CommandSpec {
+        name: "status",
+        about: "Show the last runs of the units",
+        options: &[flag("--json")],
+        argument: Value::None,
+    },
+    CommandSpec {
+        name: "next-runs",
+        about: "Show the next timer activations",
+        options: &[],
+        argument: Value::None,
+    },
+    CommandSpec {
+        name: "completions",
+        about: "Print a shell completion script",
+        options: &[],
+        argument: Value::Choice(SHELLS),
+    },
+    CommandSpec {
+        name: "schema",
+        about: "Print a JSON Schema of the configuration",
+        options: &[],
+        argument: Value::None,
+    },
+    CommandSpec {
+        name: "import",
+        about: "Convert a resticprofile or autorestic configuration",
+        options: &[option("--from", Value::Choice(IMPORT_TOOLS))],
+        argument: Value::Path,
+    },
+];
+
+/// Used by the completion scripts to complete repository names
+const LIST_REPOSITORIES: &str = "_repositories";
+
+fn is_command(name: &str) -> bool {
+    name == LIST_REPOSITORIES || COMMANDS.iter().any(|command| command.name == name)
+}
+
+/// Parse the command line arguments (without the program name.)
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Args> {
+    let mut parser = Parser::new(args);
+    let mut config = None;
+    let mut scope = None;
+    let mut command: Option<String> = None;
+    let mut positional = Vec::new();
+    let mut stdout = false;
+    let mut out_dir = None;
+    let mut overwrite = None;
+    let mut check_credentials = false;
+    let mut json = false;
+    let mut manifest = false;
+    let mut against = None;
+    let mut output = None;
+    let mut job = None;
+    let mut is_resolved = false;
+    let mut resolved = Resolved::default();
+    let mut format = None;
+    let mut render_format = None;
+    let mut import_from = None;
+    let mut init = init::Options::default();
+    while let Some(arg) = parser.next()? {
+        let flag = match arg {
+            Arg::Positional(value) => {
+                if command.is_none() && positional.is_empty() && is_command(&value) {
+                    command = Some(value);
+                } else {
+                    positional.push(value);
+                }
+                if command.as_deref() == Some("exec") && positional.len() == 1 {
+                    // Everything after the repository is for restic
+                    positional.extend(parser.rest());
+                }
+                continue;
+            }
+            Arg::Flag(flag) => flag,
+        };
+        match (command.as_deref(), flag.as_str()) {
+            (_, "-h" | "--help") => return Ok(Args::command(Command::Help)),
+            (_, "-V" | "--version") => return Ok(Args::command(Command::Version)),
+            (_, "-c" | "--config") => config = Some(PathBuf::from(parser.value(&flag)?)),
+            (_, "--user" | "--system") => {
+                let new = if flag == "--user" {
+                    Scope::User
+                } else {
+                    Scope::System
+                };
+                if scope.is_some_and(|old| old != new) {
+                    bail!("--user and --system are mutually exclusive");
+                }
+                scope = Some(new);
+            }
+            (None | Some("render"), "--stdout") => stdout = true,
+            (None, "--manifest") => {
+                let format = parser.value(&flag)?;
+                if format != "json" {
+                    bail!("unknown manifest format {} (expected json)", format);
+                }
+                manifest = true;
+            }
+            (Some("render"), "-o" | "--out-dir") => {
+                out_dir = Some(PathBuf::from(parser.value(&flag)?))
+            }
+            (Some("render"), "--force" | "--skip-existing") => {
+                let new = if flag == "--force" {
+                    Overwrite::Force
+                } else {
+                    Overwrite::Skip
+                };
+                if overwrite.is_some_and(|old| old != new) {
+                    bail!("--force and --skip-existing are mutually exclusive");
+                }
+                overwrite = Some(new);
+            }
+            (Some("render"), "--format") => render_format = Some(parser.value(&flag)?.parse()?),
+            (Some("diff"), "--against") => against = Some(PathBuf::from(parser.value(&flag)?)),
+            (Some("stats"), "--output") => output = Some(PathBuf::from(parser.value(&flag)?)),
+            (Some("snapshot-diff"), "--job") => job = Some(parser.value(&flag)?),
+            (Some("stats" | "freshness" | "snapshot-diff"), "--resolved") => is_resolved = true,
+            (Some("stats" | "freshness" | "snapshot-diff"), "--option") => {
+                resolved.options.push(parser.value(&flag)?)
+            }
+            (Some("freshness" | "snapshot-diff"), "--host") => {
+                resolved.hosts.push(parser.value(&flag)?)
+            }
+            (Some("snapshot-diff"), "--path") => resolved.paths.push(parser.value(&flag)?),
+            (Some("freshness"), "--max-age") => {
+                resolved.max_age = Some(parser.value(&flag)?.parse()?)
+            }
+            (Some("list" | "status"), "--json") => json = true,
+            (Some("env"), "--format") => format = Some(parser.value(&flag)?.parse()?),
+            (Some("init"), "--source") => init.source = Some(parser.value(&flag)?),
+            (Some("init"), "--name") => init.name = Some(parser.value(&flag)?),
+            (Some("init"), "--location") => init.location = Some(parser.value(&flag)?),
+            (Some("init"), "--password-file") => init.password_file = Some(parser.value(&flag)?),
+            (Some("init"), "--password-command") => {
+                init.password_command = Some(parser.value(&flag)?)
+            }
+            (Some("init"), "--force") => init.force = true,
+            (Some("doctor"), "--check-credentials") => check_credentials = true,
+            (Some("import"), "--from") => import_from = Some(parser.value(&flag)?.parse()?),
+            _ => bail!("unknown option {}", flag),
+        }
+    }
+
+    let command = match command.as_deref() {
+        None if stdout => {
+            no_arguments(&positional, "--stdout")?;
+            if manifest {
+                bail!("--manifest and --stdout are mutually exclusive");
+            }
+            Command::Render {
+                target: RenderTarget::Stdout,
+                format: units::Format::Systemd,
+            }
+        }
+        None => {
+            let mut positional = positional.into_iter().map(PathBuf::from);
+            let Some(normal_dir) = positional.next() else {
+                bail!("missing <normal-dir> argument");
+            };
+            let early_dir = positional.next();
+            let late_dir = positional.next();
+            if positional.next().is_some() {
+                bail!("too many arguments");
+            }
+            Command::Generate {
+                normal_dir,
+                early_dir,
+                late_dir,
+                print_manifest: manifest,
+            }
+        }
+        Some("render") => {
+            no_arguments(&positional, "render")?;
+            let target = match (out_dir, stdout) {
+                (Some(_), true) => bail!("--out-dir and --stdout are mutually exclusive"),
+                (None, false) => bail!("render requires --out-dir or --stdout"),
+                (None, true) if overwrite.is_some() => {
+                    bail!("--force and --skip-existing require --out-dir")
+                }
+                (None, true) => RenderTarget::Stdout,
+                (Some(path), false) => RenderTarget::Dir {
+                    path,
+                    overwrite: overwrite.unwrap_or(Overwrite::Fail),
+                },
+            };
+            Command::Render {
+                target,
+                format: render_format.unwrap_or(units::Format::Systemd),
+            }
+        }
+        Some("validate") => {
+            no_arguments(&positional, "validate")?;
+            Command::Validate
+        }
+        Some("verify") => {
+            no_arguments(&positional, "verify")?;
+            Command::Verify
+        }
+        Some("diff") => {
+            no_arguments(&positional, "diff")?;
+            Command::Diff { against }
+        }
+        Some("list") => {
+            no_arguments(&positional, "list")?;
+            Command::List { json }
+        }
+        Some("doctor") => {
+            no_arguments(&positional, "doctor")?;
+            Command::Doctor { check_credentials }
+        }
+        Some("env") => Command::Env {
+            repository: single_argument(positional, "env", "<repository>")?,
+            format: format.unwrap_or(environment::Format::Shell),
+        },
+        Some("exec") => {
+            let mut positional = positional.into_iter();
+            let Some(repository) = positional.next() else {
+                bail!("exec requires a <repository> argument");
+            };
+            let args: Vec<_> = positional.collect();
+            if args.is_empty() {
+                bail!("exec requires arguments for restic");
+            }
+            Command::Exec { repository, args }
+        }
+        Some("preview-forget") => Command::PreviewForget {
+            repository: single_argument(positional, "preview-forget", "<repository>")?,
+        },
+        Some("stats") => Command::Stats {
+            repository: single_argument(positional, "stats", "<repository>")?,
+            output,
+            resolved: resolved_settings(is_resolved, resolved)?,
+        },
+        Some("freshness") => {
+            let resolved = resolved_settings(is_resolved, resolved)?;
+            let complete =
+                |resolved: &Resolved| resolved.max_age.is_some() && !resolved.hosts.is_empty();
+            if resolved
+                .as_ref()
+                .is_some_and(|resolved| !complete(resolved))
+            {
+                bail!("freshness --resolved requires --max-age and --host");
+            }
+            Command::Freshness {
+                repository: single_argument(positional, "freshness", "<repository>")?,
+                resolved,
+            }
+        }
+        Some("snapshot-diff") => {
+            let resolved = resolved_settings(is_resolved, resolved)?;
+            if let Some(resolved) = &resolved {
+                if job.is_some() {
+                    bail!("--job and --resolved are mutually exclusive");
+                }
+                if resolved.hosts.len() != 1 || resolved.paths.is_empty() {
+                    bail!("snapshot-diff --resolved requires one --host and --path");
+                }
+            }
+            Command::SnapshotDiff {
+                repository: single_argument(positional, "snapshot-diff", "<repository>")?,
+                job,
+                resolved,
+            }
+        }
+        Some("init") => {
+            no_arguments(&positional, "init")?;
+            if init.password_file.is_some() && init.password_command.is_some() {
+                bail!("--password-file and --password-command are mutually exclusive");
+            }
+            Command::Init(init)
+        }
+        Some("status") => {
+            no_arguments(&positional, "status")?;
+            Command::Status { json }
+        }
+        Some("next-runs") => {
+            no_arguments(&positional, "next-runs")?;
+            Command::NextRuns
+        }
+        Some("completions") => {
+            let shell = single_argument(positional, "completions", "<shell>")?;
+            if !SHELLS.contains(&shell.as_str()) {
+                bail!(
+                    "unsupported shell {} (expected {})",
+                    shell,
+                    SHELLS.join(", ")
+                );
+            }
+            Command::Completions { shell }
+        }
+        Some("schema") => {
+            no_arguments(&positional, "schema")?;
+            Command::Schema
+        }
+        Some("import") => {
+            let path = single_argument(positional, "import", "<path>")?;
+            let Some(from) = import_from else {
+                bail!("import requires --from {}", IMPORT_TOOLS.join("|"));
+            };
+            Command::Import {
+                from,
+                path: path.into(),
+            }
+        }
+        Some(LIST_REPOSITORIES) => {
+            no_arguments(&positional, LIST_REPOSITORIES)?;
+            Command::ListRepositories
+        }
+        Some(other) => unreachable!("unhandled command {}", other),
+    };
+    Ok(Args {
+        config,
+        scope,
+        command,
+    })
+}
+
+fn no_arguments(positional: &[String], what: &str) -> Result<()> {
+    if let Some(arg) = positional.first() {
+        bail!("{} does not take an argument (got {})", what, arg);
+    }
+    Ok(())
+}
+
+/// The settings given with --resolved, which the options giving them require
+fn resolved_settings(is_resolved: bool, resolved: Resolved) -> Result<Option<Resolved>> {
+    if is_resolved {
+        return Ok(Some(resolved));
+    }
+    if resolved != Resolved::default() {
+        bail!("--option, --host, --max-age and --path require --resolved");
+    }
+    Ok(None)
+}
+
+fn single_argument(positional: Vec<String>, command: &str, what: &str) -> Result<String> {
+    let mut positional = positional.into_iter();
+    match (positional.next(), positional.next()) {
+        (Some(value), None) => Ok(value),
+        (None, _) => bail!("{} requires a {} argument", command, what),
+        (Some(_), Some(extra)) => bail!("unexpected argument {}", extra),
+    }
+}
+
+impl Args {
+    fn command(command: Command) -> Args {
+        Args {
+            config: None,
+            scope: None,
+            command,
+        }
+    }
+}
+
+enum Arg {
+    Flag(String),
+    Positional(String),
+}
+
+/// Splits the command line into flags and positional arguments. Values can be
+/// given either inline (`--flag=value`) or as the next argument.
+struct Parser<I> {
+    args: I,
+    /// The flag returned last by `next`, if its value was given inline
+    inline_value: Option<(String, String)>,
+    only_positional: bool,
+}
+
+impl<I: Iterator<Item = String>> Parser<I> {
+    fn new<T: IntoIterator<IntoIter = I>>(args: T) -> Self {
+        Parser {
+            args: args.into_iter(),
+            inline_value: None,
+            only_positional: false,
+        }
+    }
+
+    fn next(&mut self) -> Result<Option<Arg>> {
+        if let Some((flag, _)) = self.inline_value.take() {
+            bail!("{} does not take a value", flag);
+        }
+        let Some(arg) = self.args.next() else {
+            return Ok(None);
+        };
+        if self.only_positional || !arg.starts_with('-') || arg == "-" {
+            return Ok(Some(Arg::Positional(arg)));
+        }
+        if arg == "--" {
+            self.only_positional = true;
+            return self.next();
+        }
+        match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => {
+                self.inline_value = Some((flag.to_string(), value.to_string()));
+                Ok(Some(Arg::Flag(flag.to_string())))
+            }
+            _ => Ok(Some(Arg::Flag(arg))),
+        }
+    }
+
+    /// All the remaining arguments, without a leading `--`
+    fn rest(&mut self) -> Vec<String> {
+        let mut rest: Vec<String> = self.args.by_ref().collect();
+        if !self.only_positional && rest.first().map(String::as_str) == Some("--") {
+            rest.remove(0);
+        }
+        rest
+    }
+
+    /// The value of the flag that was just returned by `next`
+    fn value(&mut self, flag: &str) -> Result<String> {
+        let inline_value = self.inline_value.take().map(|(_, value)| value);
+        match inline_value.or_else(|| self.args.next()) {
+            Some(value) => Ok(value),
+            None => bail!("{} requires a value", flag),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(args: &[&str]) -> Result<Args> {
+        parse(args.iter().map(|s| s.to_string()))
+    }
+
+    fn generate(normal_dir: &str) -> Command {
+        Command::Generate {
+            normal_dir: normal_dir.into(),
+            early_dir: None,
+            late_dir: None,
+            print_manifest: false,
+        }
+    }
+
+    #[test]
+    fn generator_dirs() {
+        let args = parse_str(&["/run/normal", "/run/early", "/run/late"]).unwrap();
+        assert_eq!(
+            args.command,
+            Command::Generate {
+                normal_dir: "/run/normal".into(),
+                early_dir: Some("/run/early".into()),
+                late_dir: Some("/run/late".into()),
+                print_manifest: false,
+            }
+        );
+        assert_eq!(args.config, None);
+        assert_eq!(args.scope, None);
+    }
+
+    #[test]
+    fn print_manifest() {
+        let args = parse_str(&["--manifest", "json", "out"]).unwrap();
+        assert_eq!(
+            args.command,
+            Command::Generate {
+                normal_dir: "out".into(),
+                early_dir: None,
+                late_dir: None,
+                print_manifest: true,
+            }
+        );
+        assert!(parse_str(&["--manifest", "yaml", "out"]).is_err());
+        assert!(parse_str(&["--manifest=json", "--stdout"]).is_err());
+        assert!(parse_str(&["list", "--manifest", "json"]).is_err());
+    }
+
+    #[test]
+    fn single_dir() {
+        let args = parse_str(&["out"]).unwrap();
+        assert_eq!(args.command, generate("out"));
+    }
+
+    #[test]
+    fn config_short() {
+        let args = parse_str(&["-c", "my.toml", "out"]).unwrap();
+        assert_eq!(args.config, Some("my.toml".into()));
+        assert_eq!(args.command, generate("out"));
+    }
+
+    #[test]
+    fn config_long_inline() {
+        let args = parse_str(&["out", "--config=my.toml"]).unwrap();
+        assert_eq!(args.config, Some("my.toml".into()));
+    }
+
+    #[test]
+    fn config_without_value() {
+        assert!(parse_str(&["out", "--config"]).is_err());
+    }
+
+    #[test]
+    fn stdout() {
+        let args = parse_str(&["--stdout"]).unwrap();
+        assert_eq!(
+            args.command,
+            Command::Render {
+                target: RenderTarget::Stdout,
+                format: units::Format::Systemd
+            }
+        );
+    }
+
+    #[test]
+    fn stdout_with_dir() {
+        assert!(parse_str(&["--stdout", "out"]).is_err());
+    }
+
+    #[test]
+    fn flag_with_unexpected_value() {
+        assert!(parse_str(&["--user=yes", "out"]).is_err());
+    }
+
+    #[test]
+    fn render_out_dir() {
+        let args = parse_str(&["render", "--out-dir", "/tmp/units"]).unwrap();
+        assert_eq!(
+            args.command,
+            Command::Render {
+                target: RenderTarget::Dir {
+                    path: "/tmp/units".into(),
+                    overwrite: Overwrite::Fail,
+                },
+                format: units::Format::Systemd
+            }
+        );
+    }
+
+    #[test]
+    fn render_force() {
+        let args = parse_str(&["-c", "my.toml", "render", "-o", "out", "--force"]).unwrap();
+        assert_eq!(args.config, Some("my.toml".into()));
+        assert_eq!(
+            args.command,
+            Command::Render {
+                target: RenderTarget::Dir {
+                    path: "out".into(),
+                    overwrite: Overwrite::Force,
+                },
+                format: units::Format::Systemd
+            }
+        );
+    }
+
+    #[test]
+    fn render_skip_existing() {
+        let args = parse_str(&["render", "--skip-existing", "--out-dir=out"]).unwrap();
+        assert_eq!(
+            args.command,
+            Command::Render {
+                target: RenderTarget::Dir {
+                    path: "out".into(),
+                    overwrite: Overwrite::Skip,
+                },
+                format: units::Format::Systemd
+            }
+        );
+    }
+
+    #[test]
+    fn render_crontab() {
+        let args = parse_str(&["render", "--stdout", "--format", "crontab"]).unwrap();
+        assert_eq!(
+            args.command,
+            Command::Render {
+                target: RenderTarget::Stdout,
+                format: units::Format::Crontab
+            }
+        );
+        assert!(parse_str(&["render", "--stdout", "--format", "upstart"]).is_err());
+    }
+
+    #[test]
+    fn render_stdout() {
+        let args = parse_str(&["render", "--stdout"]).unwrap();
+        assert_eq!(
+            args.command,
+            Command::Render {
+                target: RenderTarget::Stdout,
+                format: units::Format::Systemd
+            }
+        );
+    }
+
+    #[test]
+    fn render_requires_target() {
+        assert!(parse_str(&["render"]).is_err());
+        assert!(parse_str(&["render", "--stdout", "-o", "out"]).is_err());
+    }
+
+    #[test]
+    fn render_conflicting_overwrite() {
+        assert!(parse_str(&["render", "-o", "out", "--force", "--skip-existing"]).is_err());
+    }
+
+    #[test]
+    fn render_options_are_not_global() {
+        assert!(parse_str(&["--force", "out"]).is_err());
+    }
+
+    #[test]
+    fn validate() {
+        let args = parse_str(&["validate", "-c", "my.toml"]).unwrap();
+        assert_eq!(args.command, Command::Validate);
+        assert_eq!(args.config, Some("my.toml".into()));
+        assert!(parse_str(&["validate", "extra"]).is_err());
+    }
+
+    #[test]
+    fn list() {
+        let args = parse_str(&["list", "--json"]).unwrap();
+        assert_eq!(args.command, Command::List { json: true });
+        assert!(parse_str(&["--json", "list"]).is_err());
+    }
+
+    #[test]
+    fn env() {
+        let args = parse_str(&["env", "myrepo"]).unwrap();
+        assert_eq!(
+            args.command,
+            Command::Env {
+                repository: "myrepo".into(),
+                format: environment::Format::Shell
+            }
+        );
+        let args = parse_str(&["env", "--format", "dotenv", "myrepo"]).unwrap();
+        assert_eq!(
+            args.command,
+            Command::Env {
+                repository: "myrepo".into(),
+                format: environment::Format::Dotenv
+            }
+        );
+        assert!(parse_str(&["env"]).is_err());
+        assert!(parse_str(&["env", "a", "b"]).is_err());
+        assert!(parse_str(&["env", "--format", "xml", "a"]).is_err());
+    }
+
+    #[test]
+    fn exec() {
+        let expected = Command::Exec {
+            repository: "myrepo".into(),
+            args: vec!["snapshots".into(), "--latest".into(), "5".into()],
+        };
+        let args = parse_str(&["exec", "myrepo", "--", "snapshots", "--latest", "5"]).unwrap();
+        assert_eq!(args.command, expected);
+        let args = parse_str(&[
+            "-c",
+            "a.toml",
+            "exec",
+            "myrepo",
+            "snapshots",
+            "--latest",
+            "5",
+        ]);
+        assert_eq!(args.unwrap().command, expected);
+    }
+
+    #[test]
+    fn exec_keeps_restic_help() {
+        let args = parse_str(&["exec", "myrepo", "--help"]).unwrap();
+        assert_eq!(
+            args.command,
+            Command::Exec {
+                repository: "myrepo".into(),
+                args: vec!["--help".into()],
+            }
+        );
+    }
+
+    #[test]
+    fn preview_forget() {
+        let args = parse_str(&["preview-forget", "myrepo"]).unwrap();
+        assert_eq!(
+            args.command,
+            Command::PreviewForget {
+                repository: "myrepo".into()
+            }
+        );
+        assert!(parse_str(&["preview-forget"]).is_err());
+        assert!(parse_str(&["preview-forget", "a", "b"]).is_err());
+    }
+
+    #[test]
+    fn stats() {
+        let args = parse_str(&["stats", "myrepo", "--output", "/var/lib/stats.json"]).unwrap();
+        assert_eq!(
+            args.command,
+            Command::Stats {
+                repository: "myrepo".into(),
+                output: Some("/var/lib/stats.json".into()),
+                resolved: None,
+            }
+        );
+        assert!(parse_str(&["stats"]).is_err());
+        assert_eq!(
+            parse_str(&["snapshot-diff", "myrepo", "--job", "photos"])
+                .unwrap()
+                .command,
+            Command::SnapshotDiff {
+                repository: "myrepo".into(),
+                job: Some("photos".into()),
+                resolved: None,
+            }
+        );
+        assert_eq!(
+            parse_str(&["freshness", "myrepo"]).unwrap().command,
+            Command::Freshness {
+                repository: "myrepo".into(),
+                resolved: None,
+            }
+        );
+    }
+
+    #[test]
+    fn resolved_settings() {
+        let args = parse_str(&[
+            "freshness",
+            "myrepo",
+            "--resolved",
+            "--max-age",
+            "2d",
+            "--host",
+            "laptop",
+            "--host",
+            "server",
+            "--option",
+            "s3.region=eu-west-1",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.command,
+            Command::Freshness {
+                repository: "myrepo".into(),
+                resolved: Some(Resolved {
+                    options: vec!["s3.region=eu-west-1".into()],
+                    hosts: vec!["laptop".into(), "server".into()],
+                    max_age: Some(TimeSpan::new("2d")),
+                    ..Default::default()
+                }),
+            }
+        );
+        assert_eq!(
+            parse_str(&["stats", "myrepo", "--resolved"])
+                .unwrap()
+                .command,
+            Command::Stats {
+                repository: "myrepo".into(),
+                output: None,
+                resolved: Some(Resolved::default()),
+            }
+        );
+        assert!(parse_str(&["stats", "myrepo", "--option", "s3.region=eu-west-1"]).is_err());
+        assert_eq!(
+            parse_str(&[
+                "snapshot-diff",
+                "myrepo",
+                "--resolved",
+                "--host",
+                "laptop",
+                "--path",
+                "/photos",
+            ])
+            .unwrap()
+            .command,
+            Command::SnapshotDiff {
+                repository: "myrepo".into(),
+                job: None,
+                resolved: Some(Resolved {
+                    hosts: vec!["laptop".into()],
+                    paths: vec!["/photos".into()],
+                    ..Default::default()
+                }),
+            }
+        );
+        assert!(parse_str(&["snapshot-diff", "myrepo", "--resolved", "--path", "/"]).is_err());
+        assert!(parse_str(&["snapshot-diff", "myrepo", "--path", "/"]).is_err());
+        assert!(parse_str(&[
+            "snapshot-diff",
+            "myrepo",
+            "--resolved",
+            "--host",
+            "laptop",
+            "--path",
+            "/",
+            "--job",
+            "photos",
+        ])
+        .is_err());
+        assert!(parse_str(&["freshness", "myrepo", "--resolved", "--path", "/"]).is_err());
+        assert!(parse_str(&["freshness", "myrepo", "--host", "laptop"]).is_err());
+        assert!(parse_str(&["freshness", "myrepo", "--resolved", "--host", "laptop"]).is_err());
+    }
+
+    #[test]
+    fn exec_requires_arguments() {
+        assert!(parse_str(&["exec"]).is_err());
+        assert!(parse_str(&["exec", "myrepo"]).is_err());
+        assert!(parse_str(&["exec", "myrepo", "--"]).is_err());
+    }
+
+    #[test]
+    fn init() {
+        let args = parse_str(&["init", "--location", "/media/backup", "--force"]).unwrap();
+        assert_eq!(
+            args.command,
+            Command::Init(init::Options {
+                location: Some("/media/backup".into()),
+                force: true,
+                ..Default::default()
+            })
+        );
+        assert!(parse_str(&["init", "--password-file=a", "--password-command=b"]).is_err());
+    }
+
+    #[test]
+    fn status() {
+        let args = parse_str(&["status"]).unwrap();
+        assert_eq!(args.command, Command::Status { json: false });
+        let args = parse_str(&["status", "--json"]).unwrap();
+        assert_eq!(args.command, Command::Status { json: true });
+    }
+
+    #[test]
+    fn verify() {
+        assert_eq!(parse_str(&["verify"]).unwrap().command, Command::Verify);
+        assert!(parse_str(&["verify", "extra"]).is_err());
+    }
+
+    #[test]
+    fn diff() {
+        assert_eq!(
+            parse_str(&["diff"]).unwrap().command,
+            Command::Diff { against: None }
+        );
+        assert_eq!(
+            parse_str(&["diff", "--against", "/etc/systemd/system"])
+                .unwrap()
+                .command,
+            Command::Diff {
+                against: Some("/etc/systemd/system".into())
+            }
+        );
+    }
+
+    #[test]
+    fn next_runs() {
+        assert_eq!(
+            parse_str(&["next-runs"]).unwrap().command,
+            Command::NextRuns
+        );
+    }
+
+    #[test]
+    fn completions() {
+        let args = parse_str(&["completions", "zsh"]).unwrap();
+        assert_eq!(
+            args.command,
+            Command::Completions {
+                shell: "zsh".into()
+            }
+        );
+        assert!(parse_str(&["completions", "tcsh"]).is_err());
+    }
+
+    #[test]
+    fn schema() {
+        assert_eq!(parse_str(&["schema"]).unwrap().command, Command::Schema);
+        assert!(parse_str(&["schema", "extra"]).is_err());
+    }
+
+    #[test]
+    fn import() {
+        assert_eq!(
+            parse_str(&["import", "--from", "autorestic", ".autorestic.yml"])
+                .unwrap()
+                .command,
+            Command::Import {
+                from: import::Tool::Autorestic,
+                path: ".autorestic.yml".into()
+            }
+        );
+        assert!(parse_str(&["import", "profiles.yaml"]).is_err());
+        assert!(parse_str(&["import", "--from", "borgmatic", "config.yaml"]).is_err());
+    }
+
+    /// Keep the specs used for completions in sync with the parser
+    #[test]
+    fn specs_match_parser() {
+        let example = |value: Value| match value {
+            Value::Choice(choices) => choices[0],
+            _ => "example",
+        };
+        for command in COMMANDS {
+            for option in command.options.iter().chain(GLOBAL_OPTIONS) {
+                let mut args = vec![command.name, option.long];
+                if option.value != Value::None {
+                    args.push(example(option.value));
+                }
+                if let Some(err) = parse_str(&args).err() {
+                    assert!(
+                        !err.to_string().contains("unknown option"),
+                        "{}: {}",
+                        command.name,
+                        err
+                    );
+                }
+            }
+        }
+        for option in GENERATOR_OPTIONS {
+            let mut args = vec![option.long];
+            if option.value != Value::None {
+                args.push(example(option.value));
+            }
+            if let Some(err) = parse_str(&args).err() {
+                assert!(!err.to_string().contains("unknown option"), "{}", err);
+            }
+        }
+    }
+
+    #[test]
+    fn doctor() {
+        let args = parse_str(&["doctor"]).unwrap();
+        assert_eq!(
+            args.command,
+            Command::Doctor {
+                check_credentials: false
+            }
+        );
+        let args = parse_str(&["doctor", "--check-credentials"]).unwrap();
+        assert_eq!(
+            args.command,
+            Command::Doctor {
+                check_credentials: true
+            }
+        );
+    }
+
+    #[test]
+    fn user_scope() {
+        let args = parse_str(&["--user", "out"]).unwrap();
+        assert_eq!(args.scope, Some(Scope::User));
+    }
+
+    #[test]
+    fn system_scope() {
+        let args = parse_str(&["--system", "out"]).unwrap();
+        assert_eq!(args.scope, Some(Scope::System));
+    }
+
+    #[test]
+    fn conflicting_scopes() {
+        assert!(parse_str(&["--user", "--system", "out"]).is_err());
+    }
+
+    #[test]
+    fn help() {
+        assert_eq!(
+            parse_str(&["out", "--help"]).unwrap().command,
+            Command::Help
+        );
+        assert_eq!(parse_str(&["-h"]).unwrap().command, Command::Help);
+    }
+
+    #[test]
+    fn version() {
+        assert_eq!(parse_str(&["-V"]).unwrap().command, Command::Version);
+    }
+
+    #[test]
+    fn double_dash() {
+        let args = parse_str(&["--", "--user"]).unwrap();
+        assert_eq!(args.command, generate("--user"));
+    }
+
+    #[test]
+    fn missing_dir() {
+        assert!(parse_str(&[]).is_err());
+    }
+
+    #[test]
+    fn too_many_dirs() {
+        assert!(parse_str(&["a", "b", "c", "d"]).is_err());
+    }
+
+    #[test]
+    fn unknown_option() {
+        assert!(parse_str(&["--frobnicate", "out"]).is_err());
+    }
+}
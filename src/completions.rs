@@ -0,0 +1,323 @@
+use std::io::Write;
+
+use crate::cli::{CommandSpec, OptionSpec, Value, COMMANDS, GENERATOR_OPTIONS, GLOBAL_OPTIONS};
+
+const BIN: &str = env!("CARGO_BIN_NAME");
+
+/// Print the completion script for `shell`, one of `cli::SHELLS`
+pub fn print(out: &mut impl Write, shell: &str) -> anyhow::Result<()> {
+    let script = match shell {
+        "bash" => bash(),
+        "zsh" => zsh(),
+        "fish" => fish(),
+        _ => anyhow::bail!("unsupported shell {}", shell),
+    };
+    out.write_all(script.as_bytes())?;
+    Ok(())
+}
+
+/// The spellings of the given options, e.g. `-c --config`
+fn names<'a>(options: impl IntoIterator<Item = &'a OptionSpec>) -> Vec<String> {
+    let mut names = Vec::new();
+    for option in options {
+        if let Some(short) = option.short {
+            names.push(format!("-{}", short));
+        }
+        names.push(option.long.to_string());
+    }
+    names
+}
+
+fn all_options() -> impl Iterator<Item = &'static OptionSpec> {
+    GLOBAL_OPTIONS
+        .iter()
+        .chain(GENERATOR_OPTIONS)
+        .chain(COMMANDS.iter().flat_map(|command| command.options))
+}
+
+/// The spellings of all the options taking a path
+fn path_options() -> String {
+    let mut names = names(all_options().filter(|option| option.value == Value::Path));
+    names.sort();
+    names.dedup();
+    names.join("|")
+}
+
+/// The spellings of all the options taking a value
+fn value_options() -> String {
+    let mut names = names(all_options().filter(|option| option.value != Value::None));
+    names.sort();
+    names.dedup();
+    names.join("|")
+}
+
+fn choice_options() -> impl Iterator<Item = (&'static str, &'static [&'static str])> {
+    all_options().filter_map(|option| match option.value {
+        Value::Choice(choices) => Some((option.long, choices)),
+        _ => None,
+    })
+}
+
+fn function_name() -> String {
+    format!("_{}", BIN.replace('-', "_"))
+}
+
+fn list_repositories() -> String {
+    format!("{} _repositories 2>/dev/null", BIN)
+}
+
+/// The words completing the first argument of a command, as a shell expression
+fn argument_words(command: &CommandSpec) -> Option<String> {
+    match command.argument {
+        Value::Choice(choices) => Some(choices.join(" ")),
+        Value::Repository => Some(format!("$({})", list_repositories())),
+        Value::None | Value::Path | Value::Text => None,
+    }
+}
+
+fn bash() -> String {
+    let function = function_name();
+    let mut choices = String::new();
+    for (option, values) in choice_options() {
+        choices.push_str(&format!(
+            "        {}) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return ;;\n",
+            option,
+            values.join(" ")
+        ));
+    }
+    let mut commands = String::new();
+    for command in COMMANDS {
+        commands.push_str(&format!("        {})\n", command.name));
+        if let Some(words) = argument_words(command) {
+            commands.push_str(&format!(
+                r#"            if [ "$argc" -eq 0 ] && [[ "$cur" != -* ]]; then
+                COMPREPLY=($(compgen -W "{}" -- "$cur"))
+                return
+            fi
+"#,
+                words
+            ));
+        }
+        commands.push_str(&format!(
+            "            COMPREPLY=($(compgen -W \"{} $global\" -- \"$cur\")) ;;\n",
+            names(command.options).join(" ")
+        ));
+    }
+    let command_names: Vec<_> = COMMANDS.iter().map(|command| command.name).collect();
+    format!(
+        r#"# bash completion for {bin}
+{function}() {{
+    local cur prev cmd="" argc=0 i
+    cur="${{COMP_WORDS[COMP_CWORD]}}"
+    prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+    local global="{global}"
+    case "$prev" in
+        {path_options}) COMPREPLY=($(compgen -f -- "$cur")); return ;;
+{choices}    esac
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        case "${{COMP_WORDS[i]}}" in
+            {value_options}) i=$((i + 1)) ;;
+            -*) ;;
+            *)
+                if [ -z "$cmd" ]; then
+                    cmd="${{COMP_WORDS[i]}}"
+                else
+                    argc=$((argc + 1))
+                fi ;;
+        esac
+    done
+    case "$cmd" in
+        "") COMPREPLY=($(compgen -W "{commands} {generator} $global" -- "$cur")) ;;
+{per_command}    esac
+}}
+complete -o default -F {function} {bin}
+"#,
+        bin = BIN,
+        function = function,
+        global = names(GLOBAL_OPTIONS).join(" "),
+        path_options = path_options(),
+        value_options = value_options(),
+        choices = choices,
+        commands = command_names.join(" "),
+        generator = names(GENERATOR_OPTIONS).join(" "),
+        per_command = commands,
+    )
+}
+
+fn zsh() -> String {
+    let function = function_name();
+    let mut choices = String::new();
+    for (option, values) in choice_options() {
+        choices.push_str(&format!(
+            "    ({}) compadd -- {}; return ;;\n",
+            option,
+            values.join(" ")
+        ));
+    }
+    let mut commands = String::new();
+    for command in COMMANDS {
+        commands.push_str(&format!("    ({})\n", command.name));
+        let words = match command.argument {
+            Value::Repository => Some(format!("${{(f)\"$({})\"}}", list_repositories())),
+            _ => argument_words(command),
+        };
+        if let Some(words) = words {
+            commands.push_str(&format!(
+                r#"      if (( argc == 0 )) && [[ $PREFIX != -* ]]; then
+        compadd -- {}
+        return
+      fi
+"#,
+                words
+            ));
+        }
+        commands.push_str(&format!(
+            "      compadd -- {} $global ;;\n",
+            names(command.options).join(" ")
+        ));
+    }
+    let described: Vec<_> = COMMANDS
+        .iter()
+        .map(|command| format!("'{}:{}'", command.name, command.about))
+        .collect();
+    format!(
+        r#"#compdef {bin}
+{function}() {{
+  local cmd="" argc=0 i
+  local -a global commands
+  global=({global})
+  commands=({described})
+  case ${{words[CURRENT-1]}} in
+    ({path_options}) _files; return ;;
+{choices}  esac
+  for (( i = 2; i < CURRENT; i++ )); do
+    case ${{words[i]}} in
+      ({value_options}) (( i++ )) ;;
+      (-*) ;;
+      (*)
+        if [[ -z $cmd ]]; then
+          cmd=${{words[i]}}
+        else
+          (( argc++ ))
+        fi ;;
+    esac
+  done
+  case $cmd in
+    ('')
+      _describe command commands
+      compadd -- {generator} $global ;;
+{per_command}  esac
+}}
+if [ "$funcstack[1]" = "{function}" ]; then
+  {function} "$@"
+else
+  compdef {function} {bin}
+fi
+"#,
+        bin = BIN,
+        function = function,
+        global = names(GLOBAL_OPTIONS).join(" "),
+        described = described.join(" "),
+        generator = names(GENERATOR_OPTIONS).join(" "),
+        path_options = path_options(),
+        value_options = value_options(),
+        choices = choices,
+        per_command = commands,
+    )
+}
+
+fn fish_option(condition: &str, option: &OptionSpec) -> String {
+    let mut line = format!("complete -c {}", BIN);
+    if !condition.is_empty() {
+        line.push_str(&format!(" -n '{}'", condition));
+    }
+    if let Some(short) = option.short {
+        line.push_str(&format!(" -s {}", short));
+    }
+    line.push_str(&format!(" -l {}", option.long.trim_start_matches("--")));
+    match option.value {
+        Value::None => (),
+        Value::Path => line.push_str(" -r -F"),
+        Value::Text => line.push_str(" -r"),
+        Value::Choice(values) => line.push_str(&format!(" -r -a '{}'", values.join(" "))),
+        Value::Repository => line.push_str(&format!(" -r -a '({})'", list_repositories())),
+    }
+    line
+}
+
+fn fish() -> String {
+    let command_names: Vec<_> = COMMANDS.iter().map(|command| command.name).collect();
+    let no_command = format!(
+        "not __fish_seen_subcommand_from {}",
+        command_names.join(" ")
+    );
+    let mut lines = vec![
+        format!("# fish completion for {}", BIN),
+        format!("complete -c {} -f", BIN),
+    ];
+    for option in GLOBAL_OPTIONS {
+        lines.push(fish_option("", option));
+    }
+    for option in GENERATOR_OPTIONS {
+        lines.push(fish_option(&no_command, option));
+    }
+    for command in COMMANDS {
+        lines.push(format!(
+            "complete -c {} -n '{}' -a {} -d '{}'",
+            BIN, no_command, command.name, command.about
+        ));
+        let condition = format!("__fish_seen_subcommand_from {}", command.name);
+        for option in command.options {
+            lines.push(fish_option(&condition, option));
+        }
+        let words = match command.argument {
+            Value::Repository => Some(format!("({})", list_repositories())),
+            _ => argument_words(command),
+        };
+        if let Some(words) = words {
+            lines.push(format!(
+                "complete -c {} -n '{}' -a '{}'",
+                BIN, condition, words
+            ));
+        }
+    }
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script(shell: &str) -> String {
+        let mut out = Vec::new();
+        print(&mut out, shell).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn scripts_mention_every_command() {
+        for shell in crate::cli::SHELLS {
+            let script = script(shell);
+            for command in COMMANDS {
+                assert!(script.contains(command.name), "{}: {}", shell, command.name);
+            }
+        }
+    }
+
+    #[test]
+    fn repositories_are_completed() {
+        assert!(script("bash").contains("$(restic-generator _repositories 2>/dev/null)"));
+        assert!(script("zsh").contains("${(f)\"$(restic-generator _repositories 2>/dev/null)\"}"));
+        assert!(script("fish")
+            .contains("-n '__fish_seen_subcommand_from env' -a '(restic-generator _repositories"));
+    }
+
+    #[test]
+    fn path_options_complete_files() {
+        assert_eq!(
+            path_options(),
+            "--against|--config|--location|--out-dir|--output|--password-file|--source|-c|-o"
+        );
+    }
+}
@@ -1,25 +1,1991 @@
-use serde::Deserialize;
+use anyhow::Context as _;
+use serde::{Deserialize, Deserializer};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
 
-#[derive(Deserialize, Default)]
+use crate::glob::glob;
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
-    pub source: String,
+    /// The paths of the implicit job backing up to every repository
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub source: Vec<String>,
+    /// Files listing more paths to back up, one per line, as maintained by
+    /// other tools
+    #[serde(default, rename = "files-from")]
+    pub files_from: Vec<String>,
+    /// Like files-from, for lists of paths taken as is, without patterns or
+    /// comments
+    #[serde(default, rename = "files-from-verbatim")]
+    pub files_from_verbatim: Vec<String>,
     #[serde(default)]
     pub exclude: Vec<String>,
+    /// Like exclude, for patterns ignoring the case, e.g. `*.iso`
+    #[serde(default)]
+    pub iexclude: Vec<String>,
+    /// Files listing more patterns excluded from every job, one per line
+    #[serde(default, rename = "exclude-file")]
+    pub exclude_file: Vec<String>,
+    /// Like exclude-file, for patterns ignoring the case
+    #[serde(default, rename = "iexclude-file")]
+    pub iexclude_file: Vec<String>,
+    /// Whether every job skips the directories holding a CACHEDIR.TAG file
+    #[serde(default, rename = "exclude-caches")]
+    pub exclude_caches: bool,
+    /// The names of the files marking the directories every job skips, e.g.
+    /// `.nobackup`
+    #[serde(default, rename = "exclude-if-present")]
+    pub exclude_if_present: Vec<String>,
+    /// The size above which every job skips files, e.g. `1G`
+    #[serde(default, rename = "exclude-larger-than")]
+    pub exclude_larger_than: Option<Size>,
+    /// Whether every job stays on the filesystems of its sources, e.g. skips
+    /// /proc and the mounted drives when backing up `/`. Each source keeps
+    /// its own filesystem, so `source = ["/", "/home"]` still backs up /home
+    /// when it is a separate filesystem.
+    #[serde(default, rename = "one-file-system")]
+    pub one_file_system: bool,
+    /// How many files every job reads at once, more than restic's default of
+    /// 2 speeding up the backups of SSDs
+    #[serde(default, rename = "read-concurrency")]
+    pub read_concurrency: Option<u32>,
+    /// Whether every job skips the scan estimating the size of the backup,
+    /// which takes long on trees of millions of files (the progress then
+    /// shows no ETA)
+    #[serde(default, rename = "no-scan")]
+    pub no_scan: bool,
+    /// Whether every job ignores the inode numbers when finding the changed
+    /// files, for filesystems changing them, e.g. some network and overlay
+    /// filesystems, on which every file would be read again
+    #[serde(default, rename = "ignore-inode")]
+    pub ignore_inode: bool,
+    /// Like ignore-inode, for the change times
+    #[serde(default, rename = "ignore-ctime")]
+    pub ignore_ctime: bool,
+    /// Whether every job stores the access times of the files in the snapshots
+    #[serde(default, rename = "with-atime")]
+    pub with_atime: bool,
+    /// Whether the backups of every job make no snapshot when nothing changed
+    /// since the previous one, e.g. for hourly backups of idle machines (restic
+    /// 0.17 or later)
+    #[serde(default, rename = "skip-if-unchanged")]
+    pub skip_if_unchanged: bool,
+    /// The tags of the snapshots of every job, e.g. `["system", "nightly"]`
     #[serde(default)]
+    pub tags: Vec<String>,
+    /// Whether forget only considers the snapshots with all the tags, instead
+    /// of every snapshot of the source
+    #[serde(default, rename = "forget-by-tags")]
+    pub forget_by_tags: bool,
+    /// The tags the snapshots forget considers must all have, for every job,
+    /// e.g. to keep the snapshots made by hand; this replaces forget-by-tags
+    #[serde(default, rename = "forget-tags")]
+    pub forget_tags: Vec<String>,
+    /// The paths of the snapshots the forget of the top-level source
+    /// considers, by default the source
+    #[serde(default, rename = "forget-paths")]
+    pub forget_paths: Vec<String>,
+    /// Like tags, where `{job}` stands for the name of the job (`default` for
+    /// the top-level source)
+    #[serde(default, rename = "post-backup-tags")]
+    pub post_backup_tags: Vec<String>,
+    #[serde(default, alias = "repository")]
     pub repositories: Vec<RepositoryConfig>,
+    #[serde(default, alias = "job")]
+    pub jobs: Vec<JobConfig>,
+    #[serde(default, alias = "copy")]
+    pub copies: Vec<CopyConfig>,
+    #[serde(default)]
+    pub host: Option<String>,
+    /// Settings inherited by the repositories that don't set them
+    #[serde(default)]
+    pub defaults: Defaults,
+    /// Variables set in the environment of every repository
+    #[serde(default)]
+    pub environment: BTreeMap<String, String>,
+    /// A set of defaults for a kind of machine, below the ones of
+    /// `[defaults]`
+    #[serde(default)]
+    pub profile: Option<Profile>,
+    /// The delay over which the timers of the repositories sharing a
+    /// calendar are spread, at a fixed offset for each unit
+    #[serde(default)]
+    pub stagger: Option<TimeSpan>,
+    /// Generate a unit removing the old cache directories when set
+    #[serde(default, rename = "cache-cleanup")]
+    pub cache_cleanup: Option<CacheCleanup>,
+    /// What generating units from a configuration with problems does
+    #[serde(default, rename = "on-problems")]
+    pub on_problems: OnProblems,
+    /// The other files to read, merged by `load`: the includes of each file
+    /// are read from it alone, before merging it
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// The files the configuration was read from
+    #[serde(skip)]
+    pub files: Vec<PathBuf>,
+}
+
+/// The kinds of machines with their own defaults
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Profile {
+    /// Backups wait for the machine to be on AC power
+    Laptop,
+}
+
+/// Whether the problems found by `validate` stop the generation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnProblems {
+    #[default]
+    Error,
+    Warn,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Defaults {
+    pub keep_last: Option<usize>,
+    pub keep_hourly: Option<usize>,
+    pub keep_daily: Option<usize>,
+    pub keep_weekly: Option<usize>,
+    pub keep_monthly: Option<usize>,
+    pub keep_yearly: Option<usize>,
+    #[serde(default, deserialize_with = "some_one_or_many")]
+    pub keep_tag: Option<Vec<String>>,
+    pub keep_within: Option<Duration>,
+    pub keep_within_hourly: Option<Duration>,
+    pub keep_within_daily: Option<Duration>,
+    pub keep_within_weekly: Option<Duration>,
+    pub keep_within_monthly: Option<Duration>,
+    pub keep_within_yearly: Option<Duration>,
+    pub group_by: Option<GroupBy>,
+    /// The keep-* settings as a table
+    #[serde(default)]
+    pub retention: Retention,
+    pub backup_calendar: Option<Calendar>,
+    pub forget_calendar: Option<Calendar>,
+    pub prune_calendar: Option<Calendar>,
+    pub check_calendar: Option<Calendar>,
+    pub stats_calendar: Option<Calendar>,
+    pub check: Option<bool>,
+    pub init: Option<bool>,
+    pub stats: Option<bool>,
+    pub max_age: Option<TimeSpan>,
+    pub repair: Option<bool>,
+    pub rewrite: Option<bool>,
+    pub migrate: Option<bool>,
+    pub diff: Option<bool>,
+    pub unlock: Option<Unlock>,
+    pub retry_lock: Option<TimeSpan>,
+    pub pack_size: Option<u32>,
+    pub limit_upload: Option<u32>,
+    pub limit_download: Option<u32>,
+    pub randomized_delay: Option<TimeSpan>,
+    pub fixed_random_delay: Option<bool>,
+    pub accuracy: Option<TimeSpan>,
+    pub require_ac_power: Option<bool>,
+}
+
+/// Whether the units of a repository are started by timers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Schedule {
+    #[default]
+    Timers,
+    Manual,
+}
+
+/// Which locks the units remove before running restic
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Unlock {
+    /// The locks left by processes that are gone
+    #[default]
+    Stale,
+    /// All the locks, even the ones held by another machine
+    All,
+    Never,
+}
+
+/// What starts the forget and prune units of a repository
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Maintenance {
+    #[default]
+    Timers,
+    Chained,
+}
+
+/// When the prune unit of a repository is generated and started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Pruning {
+    /// When a job has a forget policy, started like forget
+    #[default]
+    WithForget,
+    /// Even without a forget policy, by its own timer
+    Timer,
+    /// Even without a forget policy, only by hand
+    Manual,
+    Never,
+}
+
+/// A restic duration, e.g. `2y5m7d3h` for 2 years, 5 months, 7 days and 3
+/// hours
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Duration {
+    pub years: u32,
+    pub months: u32,
+    pub days: u32,
+    pub hours: u32,
+}
+
+impl std::str::FromStr for Duration {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Duration> {
+        let invalid = || anyhow::anyhow!("invalid duration {:?} (expected e.g. 2y5m7d3h)", value);
+        let mut duration = Duration::default();
+        let mut number = String::new();
+        for c in value.chars() {
+            let unit = match c {
+                '0'..='9' => {
+                    number.push(c);
+                    continue;
+                }
+                'y' => &mut duration.years,
+                'm' => &mut duration.months,
+                'd' => &mut duration.days,
+                'h' => &mut duration.hours,
+                _ => return Err(invalid()),
+            };
+            let amount: u32 = number.parse().map_err(|_| invalid())?;
+            *unit = unit.checked_add(amount).ok_or_else(invalid)?;
+            number.clear();
+        }
+        if value.is_empty() || !number.is_empty() {
+            return Err(invalid());
+        }
+        Ok(duration)
+    }
+}
+
+impl std::convert::TryFrom<String> for Duration {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> anyhow::Result<Duration> {
+        value.parse()
+    }
+}
+
+impl std::fmt::Display for Duration {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let parts = [
+            (self.years, 'y'),
+            (self.months, 'm'),
+            (self.days, 'd'),
+            (self.hours, 'h'),
+        ];
+        if parts.iter().all(|(amount, _)| *amount == 0) {
+            return write!(f, "0h");
+        }
+        for (amount, unit) in parts {
+            if amount > 0 {
+                write!(f, "{}{}", amount, unit)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How forget groups the snapshots before applying the policy, e.g.
+/// `host,paths`, or the empty string to apply it to all of them at once
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct GroupBy(String);
+
+impl std::str::FromStr for GroupBy {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<GroupBy> {
+        let known = |field: &str| ["host", "paths", "tags"].contains(&field);
+        if !value.is_empty() && !value.split(',').all(known) {
+            anyhow::bail!(
+                "invalid group-by {:?} (expected a comma-separated list of host, paths and tags)",
+                value
+            );
+        }
+        Ok(GroupBy(value.to_string()))
+    }
+}
+
+impl std::convert::TryFrom<String> for GroupBy {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> anyhow::Result<GroupBy> {
+        value.parse()
+    }
+}
+
+impl std::fmt::Display for GroupBy {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A systemd calendar event, as given to `OnCalendar=`, e.g. `daily` or
+/// `Sat *-*-* 02:00`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Calendar(String);
+
+impl Calendar {
+    pub fn new(value: &str) -> Calendar {
+        Calendar(value.to_string())
+    }
+}
+
+/// The calendar shorthands known to systemd
+const CALENDAR_SHORTHANDS: &[&str] = &[
+    "minutely",
+    "hourly",
+    "daily",
+    "weekly",
+    "monthly",
+    "yearly",
+    "annually",
+    "quarterly",
+    "semiannually",
+];
+
+const WEEKDAYS: &[(&str, &str)] = &[
+    ("mon", "monday"),
+    ("tue", "tuesday"),
+    ("wed", "wednesday"),
+    ("thu", "thursday"),
+    ("fri", "friday"),
+    ("sat", "saturday"),
+    ("sun", "sunday"),
+];
+
+impl std::str::FromStr for Calendar {
+    type Err = anyhow::Error;
+
+    /// Check the syntax of `[weekdays] [[year-]month-day] [hour:minute[:second]]
+    /// [timezone]`, without checking the ranges of the values
+    fn from_str(value: &str) -> anyhow::Result<Calendar> {
+        let invalid = || {
+            anyhow::anyhow!(
+                "invalid calendar expression {:?} (expected e.g. daily or Sat *-*-* 02:00)",
+                value
+            )
+        };
+        let words: Vec<&str> = value.split_whitespace().collect();
+        if let [word] = words[..] {
+            if CALENDAR_SHORTHANDS.contains(&word.to_lowercase().as_str()) {
+                return Ok(Calendar(value.trim().to_string()));
+            }
+        }
+        let mut words = words.into_iter().peekable();
+        let weekdays = words.next_if(|word| is_weekdays(word)).is_some();
+        let date = words.next_if(|word| is_date(word)).is_some();
+        let time = words.next_if(|word| is_time(word)).is_some();
+        // A timezone can only follow a date or a time
+        if date || time {
+            words.next_if(|word| is_timezone(word));
+        }
+        if !(weekdays || date || time) || words.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(Calendar(value.trim().to_string()))
+    }
+}
+
+/// Whether `word` is a list of weekdays or ranges of weekdays, e.g. `Mon..Fri,Sun`
+fn is_weekdays(word: &str) -> bool {
+    let is_weekday = |day: &str| {
+        let day = day.to_lowercase();
+        WEEKDAYS
+            .iter()
+            .any(|(short, long)| day == *short || day == *long)
+    };
+    word.split(',').all(|part| match part.split_once("..") {
+        Some((first, last)) => is_weekday(first) && is_weekday(last),
+        None => is_weekday(part),
+    })
+}
+
+/// Whether `word` is `[year-]month-day`, with `~` instead of the last `-` to
+/// count the days from the end of the month
+fn is_date(word: &str) -> bool {
+    let (rest, day) = match word.rsplit_once('~') {
+        Some(split) => split,
+        None => match word.rsplit_once('-') {
+            Some(split) => split,
+            None => return false,
+        },
+    };
+    let mut parts: Vec<&str> = rest.split('-').collect();
+    parts.push(day);
+    (2..=3).contains(&parts.len()) && parts.into_iter().all(is_calendar_component)
+}
+
+/// Whether `word` is `hour:minute[:second]`
+fn is_time(word: &str) -> bool {
+    let parts: Vec<&str> = word.split(':').collect();
+    (2..=3).contains(&parts.len())
+        && parts.iter().enumerate().all(|(index, part)| {
+            // Seconds can have a fractional part
+            is_calendar_component(if index == 2 {
+                part.split_once('.').map_or(part, |(whole, _)| whole)
+            } else {
+                part
+            })
+        })
+}
+
+fn is_timezone(word: &str) -> bool {
+    !is_weekdays(word)
+        && word.starts_with(|c: char| c.is_ascii_alphabetic())
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/_+-".contains(c))
+}
+
+/// Whether `part` is `*` or a list of values, ranges (`1..5`) and repetitions
+/// (`*/2`, `1/3`)
+fn is_calendar_component(part: &str) -> bool {
+    let is_number = |value: &str| !value.is_empty() && value.chars().all(|c| c.is_ascii_digit());
+    part.split(',').all(|item| {
+        let (range, repetition) = match item.split_once('/') {
+            Some((range, step)) => (range, Some(step)),
+            None => (item, None),
+        };
+        let range_ok = range == "*"
+            || match range.split_once("..") {
+                Some((first, last)) => is_number(first) && is_number(last),
+                None => is_number(range),
+            };
+        range_ok && repetition.is_none_or(is_number)
+    })
+}
+
+impl std::convert::TryFrom<String> for Calendar {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> anyhow::Result<Calendar> {
+        value.parse()
+    }
+}
+
+impl std::fmt::Display for Calendar {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A systemd time span, e.g. `30min` or `1h 30s`, in seconds without a unit
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TimeSpan(String);
+
+impl TimeSpan {
+    pub fn new(value: &str) -> TimeSpan {
+        TimeSpan(value.to_string())
+    }
+
+    /// The length of the span, with systemd's months and years of 30.44 and
+    /// 365.25 days
+    pub fn seconds(&self) -> f64 {
+        parse_time_span(&self.0).expect("time spans are validated when parsed")
+    }
+}
+
+/// The time span units known to systemd, with their length in seconds
+const TIME_SPAN_UNITS: &[(&str, f64)] = &[
+    ("usec", 1e-6),
+    ("us", 1e-6),
+    ("µs", 1e-6),
+    ("msec", 1e-3),
+    ("ms", 1e-3),
+    ("seconds", 1.0),
+    ("second", 1.0),
+    ("sec", 1.0),
+    ("s", 1.0),
+    ("", 1.0),
+    ("minutes", 60.0),
+    ("minute", 60.0),
+    ("min", 60.0),
+    ("m", 60.0),
+    ("hours", 3600.0),
+    ("hour", 3600.0),
+    ("hr", 3600.0),
+    ("h", 3600.0),
+    ("days", 86400.0),
+    ("day", 86400.0),
+    ("d", 86400.0),
+    ("weeks", 604800.0),
+    ("week", 604800.0),
+    ("w", 604800.0),
+    ("months", 2629800.0),
+    ("month", 2629800.0),
+    ("M", 2629800.0),
+    ("years", 31557600.0),
+    ("year", 31557600.0),
+    ("y", 31557600.0),
+];
+
+/// The length of a time span in seconds, or `None` if it is invalid
+fn parse_time_span(value: &str) -> Option<f64> {
+    let mut rest = value.trim();
+    if rest.is_empty() {
+        return None;
+    }
+    let mut seconds = 0.0;
+    while !rest.is_empty() {
+        let number_end = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let number: f64 = rest[..number_end].parse().ok()?;
+        rest = rest[number_end..].trim_start();
+        let unit_end = rest
+            .find(|c: char| c.is_ascii_digit() || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let (_, unit) = TIME_SPAN_UNITS
+            .iter()
+            .find(|(unit, _)| *unit == &rest[..unit_end])?;
+        seconds += number * unit;
+        rest = rest[unit_end..].trim_start();
+    }
+    Some(seconds)
+}
+
+impl std::str::FromStr for TimeSpan {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<TimeSpan> {
+        match parse_time_span(value) {
+            Some(_) => Ok(TimeSpan(value.trim().to_string())),
+            None => anyhow::bail!("invalid time span {:?} (expected e.g. 30min)", value),
+        }
+    }
+}
+
+impl std::convert::TryFrom<String> for TimeSpan {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> anyhow::Result<TimeSpan> {
+        value.parse()
+    }
+}
+
+impl std::fmt::Display for TimeSpan {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A size as restic takes it: a number of bytes, optionally followed by one
+/// of the `K`, `M`, `G` or `T` binary suffixes, e.g. `500M`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Size(String);
+
+impl Size {
+    pub fn bytes(&self) -> u64 {
+        parse_size(&self.0).expect("sizes are validated when parsed")
+    }
+}
+
+/// The number of bytes of a size, or `None` if it is invalid
+fn parse_size(value: &str) -> Option<u64> {
+    let number = value.trim_end_matches(|c: char| "kKmMgGtT".contains(c));
+    let shift = match &value[number.len()..] {
+        "" => 0,
+        "k" | "K" => 10,
+        "m" | "M" => 20,
+        "g" | "G" => 30,
+        "t" | "T" => 40,
+        _ => return None,
+    };
+    number.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+impl std::str::FromStr for Size {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Size> {
+        match parse_size(value) {
+            Some(_) => Ok(Size(value.to_string())),
+            None => anyhow::bail!("invalid size {:?} (expected e.g. 1G)", value),
+        }
+    }
+}
+
+impl std::convert::TryFrom<String> for Size {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> anyhow::Result<Size> {
+        value.parse()
+    }
+}
+
+impl std::fmt::Display for Size {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// The unused space prune leaves in the repository, as given to
+/// `--max-unused`: a percentage of the size of the repository, e.g. `5%`, a
+/// size, e.g. `1G`, or `unlimited`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct MaxUnused(String);
+
+impl std::str::FromStr for MaxUnused {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<MaxUnused> {
+        let valid = match value.strip_suffix('%') {
+            Some(percentage) => matches!(
+                percentage.parse::<f64>(),
+                Ok(percentage) if (0.0..=100.0).contains(&percentage)
+            ),
+            None => value == "unlimited" || parse_size(value).is_some(),
+        };
+        if !valid {
+            anyhow::bail!(
+                "invalid max-unused {:?} (expected e.g. 5%, 1G or unlimited)",
+                value
+            );
+        }
+        Ok(MaxUnused(value.to_string()))
+    }
+}
+
+impl std::convert::TryFrom<String> for MaxUnused {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> anyhow::Result<MaxUnused> {
+        value.parse()
+    }
+}
+
+impl std::fmt::Display for MaxUnused {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// The data read by check, as given to `--read-data-subset`: with `n/t`
+/// each run reads the next of the `t` parts, `10%` or `1G` are random subsets
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum DataSubset {
+    /// The number of parts read in turn
+    Rotation(u32),
+    Random(String),
+}
+
+impl std::str::FromStr for DataSubset {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<DataSubset> {
+        let invalid = || {
+            anyhow::anyhow!(
+                "invalid data subset {:?} (expected e.g. 1/30, 10% or 1G)",
+                value
+            )
+        };
+        if let Some((part, parts)) = value.split_once('/') {
+            let (part, parts): (u32, u32) = (
+                part.parse().map_err(|_| invalid())?,
+                parts.parse().map_err(|_| invalid())?,
+            );
+            if part == 0 || part > parts {
+                return Err(invalid());
+            }
+            return Ok(DataSubset::Rotation(parts));
+        }
+        if let Some(percentage) = value.strip_suffix('%') {
+            match percentage.parse::<f64>() {
+                Ok(percentage) if percentage > 0.0 && percentage <= 100.0 => {}
+                _ => return Err(invalid()),
+            }
+        } else if parse_size(value).is_none() {
+            return Err(invalid());
+        }
+        Ok(DataSubset::Random(value.to_string()))
+    }
+}
+
+impl std::convert::TryFrom<String> for DataSubset {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> anyhow::Result<DataSubset> {
+        value.parse()
+    }
+}
+
+/// A calendar event ending with a range of times, e.g. `Sat 02:00..06:00`,
+/// when a unit starts at the beginning of the range and must be done by its
+/// end
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Window {
+    /// The calendar event of the beginning of the window
+    pub start: Calendar,
+    /// The length of the window in seconds
+    pub seconds: u32,
+}
+
+impl std::str::FromStr for Window {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Window> {
+        let invalid = || {
+            anyhow::anyhow!(
+                "invalid window {:?} (expected e.g. Sat *-*-* 02:00..06:00)",
+                value
+            )
+        };
+        let seconds = |time: &str| -> Option<u32> {
+            let parts: Vec<u32> = time
+                .split(':')
+                .map(|part| part.parse().ok())
+                .collect::<Option<_>>()?;
+            match parts[..] {
+                [hours, minutes] if hours < 24 && minutes < 60 => Some((hours * 60 + minutes) * 60),
+                [hours, minutes, seconds] if hours < 24 && minutes < 60 && seconds < 60 => {
+                    Some((hours * 60 + minutes) * 60 + seconds)
+                }
+                _ => None,
+            }
+        };
+        let (days, range) = match value.trim().rsplit_once(char::is_whitespace) {
+            Some((days, range)) => (days.trim_end(), range),
+            None => ("", value.trim()),
+        };
+        let (start, end) = range.split_once("..").ok_or_else(invalid)?;
+        let (from, to) = (
+            seconds(start).ok_or_else(invalid)?,
+            seconds(end).ok_or_else(invalid)?,
+        );
+        // A window ending before it starts ends the next day
+        let length = if to > from {
+            to - from
+        } else {
+            to + 24 * 3600 - from
+        };
+        let start = format!("{} {}", days, start)
+            .trim()
+            .parse()
+            .map_err(|_| invalid())?;
+        Ok(Window {
+            start,
+            seconds: length,
+        })
+    }
+}
+
+impl std::convert::TryFrom<String> for Window {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> anyhow::Result<Window> {
+        value.parse()
+    }
+}
+
+/// Copy the fields that are not set on a repository from the defaults
+macro_rules! inherit {
+    ($repository:expr, $defaults:expr, $($field:ident),+) => {
+        $(
+            if $repository.$field.is_none() {
+                $repository.$field = $defaults.$field.clone();
+            }
+        )+
+    };
+}
+
+/// A backup of one source to some of the repositories
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct JobConfig {
+    pub name: String,
+    /// Optional when the job has files-from lists
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub source: Vec<String>,
+    #[serde(default)]
+    pub files_from: Vec<String>,
+    #[serde(default)]
+    pub files_from_verbatim: Vec<String>,
+    /// The command whose output the job backs up instead of a source, e.g.
+    /// `pg_dumpall` or `["mysqldump", "--all-databases"]`
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub command: Vec<String>,
+    /// The name of the file holding the output of the command in the
+    /// snapshots, by default `stdin`
+    pub stdin_filename: Option<String>,
+    /// Excluded in addition to the top-level patterns
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub iexclude: Vec<String>,
+    #[serde(default)]
+    pub exclude_file: Vec<String>,
+    #[serde(default)]
+    pub iexclude_file: Vec<String>,
+    #[serde(default)]
+    pub exclude_caches: bool,
+    #[serde(default)]
+    pub exclude_if_present: Vec<String>,
+    /// Overrides the top-level size
+    pub exclude_larger_than: Option<Size>,
+    #[serde(default)]
+    pub one_file_system: bool,
+    /// Overrides the top-level concurrency
+    pub read_concurrency: Option<u32>,
+    #[serde(default)]
+    pub no_scan: bool,
+    #[serde(default)]
+    pub ignore_inode: bool,
     #[serde(default)]
+    pub ignore_ctime: bool,
+    #[serde(default)]
+    pub with_atime: bool,
+    #[serde(default)]
+    pub skip_if_unchanged: bool,
+    /// Tags in addition to the top-level ones
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub forget_by_tags: bool,
+    /// Overrides the top-level tags
+    #[serde(default)]
+    pub forget_tags: Vec<String>,
+    #[serde(default)]
+    pub forget_paths: Vec<String>,
+    #[serde(default)]
+    pub post_backup_tags: Vec<String>,
     pub host: Option<String>,
+    /// The names of the repositories to back up to
+    pub repositories: Vec<String>,
+    /// The machines running the job, all of them if empty
+    #[serde(default)]
+    pub hosts: Vec<String>,
+
+    // Forget policies, overriding the ones of the repositories
+    pub keep_last: Option<usize>,
+    pub keep_hourly: Option<usize>,
+    pub keep_daily: Option<usize>,
+    pub keep_weekly: Option<usize>,
+    pub keep_monthly: Option<usize>,
+    pub keep_yearly: Option<usize>,
+    #[serde(default, deserialize_with = "some_one_or_many")]
+    pub keep_tag: Option<Vec<String>>,
+    pub keep_within: Option<Duration>,
+    pub keep_within_hourly: Option<Duration>,
+    pub keep_within_daily: Option<Duration>,
+    pub keep_within_weekly: Option<Duration>,
+    pub keep_within_monthly: Option<Duration>,
+    pub keep_within_yearly: Option<Duration>,
+    pub group_by: Option<GroupBy>,
+    /// The forget policy as a table, e.g. `retention = { keep-daily = 30 }`
+    #[serde(default)]
+    pub retention: Retention,
+}
+
+/// A forget policy: the snapshots to keep, also the `retention` table of the
+/// defaults, repositories and jobs
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Retention {
+    pub keep_last: Option<usize>,
+    pub keep_hourly: Option<usize>,
+    pub keep_daily: Option<usize>,
+    pub keep_weekly: Option<usize>,
+    pub keep_monthly: Option<usize>,
+    pub keep_yearly: Option<usize>,
+    #[serde(default, deserialize_with = "some_one_or_many")]
+    pub keep_tag: Option<Vec<String>>,
+    pub keep_within: Option<Duration>,
+    pub keep_within_hourly: Option<Duration>,
+    pub keep_within_daily: Option<Duration>,
+    pub keep_within_weekly: Option<Duration>,
+    pub keep_within_monthly: Option<Duration>,
+    pub keep_within_yearly: Option<Duration>,
+    pub group_by: Option<GroupBy>,
+}
+
+/// The forget policy set by a repository or a job: the keep-* settings,
+/// completed with its `retention` table
+macro_rules! retention {
+    ($value:expr) => {
+        Retention {
+            keep_last: $value.keep_last,
+            keep_hourly: $value.keep_hourly,
+            keep_daily: $value.keep_daily,
+            keep_weekly: $value.keep_weekly,
+            keep_monthly: $value.keep_monthly,
+            keep_yearly: $value.keep_yearly,
+            keep_tag: $value.keep_tag.clone(),
+            keep_within: $value.keep_within,
+            keep_within_hourly: $value.keep_within_hourly,
+            keep_within_daily: $value.keep_within_daily,
+            keep_within_weekly: $value.keep_within_weekly,
+            keep_within_monthly: $value.keep_within_monthly,
+            keep_within_yearly: $value.keep_within_yearly,
+            group_by: $value.group_by.clone(),
+        }
+        .or($value.retention.clone())
+    };
+}
+
+/// Move the `retention` table of the defaults or a repository to its keep-*
+/// settings, which take precedence, for the defaults to be inherited field
+/// by field
+macro_rules! flatten_retention {
+    ($value:expr) => {
+        let retention = std::mem::take(&mut $value.retention);
+        inherit!(
+            $value,
+            retention,
+            keep_last,
+            keep_hourly,
+            keep_daily,
+            keep_weekly,
+            keep_monthly,
+            keep_yearly,
+            keep_tag,
+            keep_within,
+            keep_within_hourly,
+            keep_within_daily,
+            keep_within_weekly,
+            keep_within_monthly,
+            keep_within_yearly,
+            group_by
+        );
+    };
+}
+
+impl Retention {
+    /// This policy, completed with the settings of `other` it doesn't have
+    pub fn or(mut self, other: Retention) -> Retention {
+        inherit!(
+            self,
+            other,
+            keep_last,
+            keep_hourly,
+            keep_daily,
+            keep_weekly,
+            keep_monthly,
+            keep_yearly,
+            keep_tag,
+            keep_within,
+            keep_within_hourly,
+            keep_within_daily,
+            keep_within_weekly,
+            keep_within_monthly,
+            keep_within_yearly,
+            group_by
+        );
+        self
+    }
+
+    /// Whether the policy keeps no snapshots: the grouping alone is no policy
+    pub fn is_empty(&self) -> bool {
+        let policy = Retention {
+            group_by: None,
+            ..self.clone()
+        };
+        policy == Retention::default()
+    }
+}
+
+/// The settings of the unit running `restic cache --cleanup`
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct CacheCleanup {
+    /// The age in days of the cache directories removed, 30 by default
+    pub max_age: Option<u32>,
+    /// When the timer starts the cleanup, by default weekly
+    pub calendar: Option<Calendar>,
+}
+
+/// A replication of the snapshots of a repository to another one
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct CopyConfig {
+    pub from: String,
+    pub to: String,
+    /// When the timer starts the copy, by default daily
+    pub calendar: Option<Calendar>,
+}
+
+/// A job with the top-level settings applied
+#[derive(Debug, Clone)]
+pub struct Job<'a> {
+    /// `None` for the job made from the top-level source
+    pub name: Option<&'a str>,
+    pub source: &'a [String],
+    pub files_from: &'a [String],
+    pub files_from_verbatim: &'a [String],
+    /// The command whose output is backed up, if any
+    pub command: &'a [String],
+    pub stdin_filename: Option<&'a str>,
+    pub exclude: Vec<&'a str>,
+    pub iexclude: Vec<&'a str>,
+    pub exclude_file: Vec<&'a str>,
+    pub iexclude_file: Vec<&'a str>,
+    pub exclude_caches: bool,
+    pub exclude_if_present: Vec<&'a str>,
+    pub exclude_larger_than: Option<&'a Size>,
+    pub one_file_system: bool,
+    pub read_concurrency: Option<u32>,
+    pub no_scan: bool,
+    pub ignore_inode: bool,
+    pub ignore_ctime: bool,
+    pub with_atime: bool,
+    pub skip_if_unchanged: bool,
+    /// The tags of the snapshots, with `{job}` replaced
+    pub tags: Vec<String>,
+    /// The tags of the snapshots forget considers, all of them if empty
+    pub forget_tags: Vec<&'a str>,
+    forget_paths: &'a [String],
+    pub host: Option<&'a str>,
+    /// `None` if the job backs up to every repository
+    repositories: Option<&'a [String]>,
+    hosts: &'a [String],
+    /// The forget policy settings overriding the repositories' ones
+    retention: Retention,
+}
+
+impl Job<'_> {
+    /// What the job backs up, for the unit descriptions
+    pub fn description(&self) -> String {
+        if !self.command.is_empty() {
+            return format!("the output of {}", self.command.join(" "));
+        }
+        let lists = self.files_from.iter().chain(self.files_from_verbatim);
+        let lists = lists.map(|path| format!("files from {}", path));
+        let words: Vec<_> = self.source.iter().cloned().chain(lists).collect();
+        words.join(" ")
+    }
+
+    /// The paths of the snapshots of the job, which tell them apart from the
+    /// ones of the other jobs: the source, or the file holding the output of
+    /// the command
+    pub fn snapshot_paths(&self) -> Vec<String> {
+        if self.command.is_empty() {
+            return self.source.to_vec();
+        }
+        vec![format!("/{}", self.stdin_filename.unwrap_or("stdin"))]
+    }
+
+    /// The paths of the snapshots forget considers
+    pub fn forget_paths(&self) -> Vec<String> {
+        if self.forget_paths.is_empty() {
+            return self.snapshot_paths();
+        }
+        self.forget_paths.to_vec()
+    }
+
+    pub fn targets(&self, repository: &RepositoryConfig) -> bool {
+        match self.repositories {
+            None => true,
+            Some(names) => names.contains(&repository.name),
+        }
+    }
+
+    /// Whether the job runs on the machine named `hostname`
+    pub fn runs_on(&self, hostname: &str) -> bool {
+        matches_host(self.hosts, hostname)
+    }
+
+    /// The forget policy for the snapshots of the job in `repository`
+    pub fn retention(&self, repository: &RepositoryConfig) -> Retention {
+        self.retention.clone().or(repository.retention())
+    }
+
+    pub fn has_forget_policy(&self, repository: &RepositoryConfig) -> bool {
+        !self.retention.is_empty() || repository.has_forget_policy()
+    }
+}
+
+/// Whether `hostname` is one of `hosts`, or `hosts` is empty
+fn matches_host(hosts: &[String], hostname: &str) -> bool {
+    hosts.is_empty() || hosts.iter().any(|host| host.eq_ignore_ascii_case(hostname))
+}
+
+/// Read the configuration from `path`, merged with the files it includes and
+/// the `conf.d` fragments next to it, and with the repositories of the
+/// `repositories.d` directory next to it, one per file. The main file may be
+/// missing if there are other files. If `path` is a directory, its files are
+/// merged instead.
+pub fn load(path: &Path) -> anyhow::Result<Config> {
+    let mut roots = Vec::new();
+    let mut repositories = Vec::new();
+    if path.is_dir() {
+        roots = config_files(path)?;
+        if roots.is_empty() {
+            anyhow::bail!("{}: no configuration files in directory", path.display());
+        }
+    } else {
+        let fragments = config_files(&path.with_file_name("conf.d"))?;
+        repositories = config_files(&path.with_file_name("repositories.d"))?;
+        if (fragments.is_empty() && repositories.is_empty()) || path.exists() {
+            roots.push(path.to_path_buf());
+        }
+        roots.extend(fragments);
+    }
+    let mut value = toml::Value::Table(Default::default());
+    let mut files = Vec::new();
+    for root in roots.iter() {
+        read_into(&mut value, root, &mut files)?;
+    }
+    for file in repositories.iter() {
+        read_repository(&mut value, file, &mut files)?;
+    }
+    let names = repository_names(&value);
+    let mut config: Config = match crate::de::from_value(value) {
+        Ok(config) => config,
+        Err(err) => {
+            let message = suggest(err.message().to_string());
+            let what = match err.index_in("repositories").and_then(|i| names.get(i)) {
+                Some(Some(name)) => format!("invalid config for repository {}", name),
+                _ => "invalid config".to_string(),
+            };
+            let Some((file, line)) = error_line(&files, &err) else {
+                anyhow::bail!("{}: {}: {}", what, err.path(), message);
+            };
+            anyhow::bail!(
+                "{}: {}: {} at line {}: {}",
+                file.display(),
+                what,
+                err.path(),
+                line,
+                message
+            );
+        }
+    };
+    config.files = files;
+    config.interpolate(&|name| std::env::var(name).ok())?;
+    config.apply_defaults();
+    config.check_jobs()?;
+    Ok(config)
+}
+
+/// The names of the repositories of the merged configuration, to name the
+/// repository of an error found when deserializing it
+fn repository_names(value: &toml::Value) -> Vec<Option<String>> {
+    let repositories = value.get("repositories").and_then(toml::Value::as_array);
+    repositories
+        .into_iter()
+        .flatten()
+        .map(|repository| {
+            let name = repository.get("name").and_then(toml::Value::as_str);
+            name.map(str::to_string)
+        })
+        .collect()
+}
+
+/// Merge the file at `path` into `value`, followed by the files it includes
+fn read_into(value: &mut toml::Value, path: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    if files.iter().any(|file| file == path) {
+        anyhow::bail!("{}: included more than once", path.display());
+    }
+    let mut fragment: toml::Value = parse_file(path)?;
+    normalize(&mut fragment);
+    let include = match fragment
+        .as_table_mut()
+        .and_then(|table| table.remove("include"))
+    {
+        None => Vec::new(),
+        Some(include) => {
+            let mut table = toml::value::Table::new();
+            table.insert("include".to_string(), include);
+            crate::de::from_value::<Config>(toml::Value::Table(table))
+                .with_context(|| format!("{}: include must be a list of paths", path.display()))?
+                .include
+        }
+    };
+    files.push(path.to_path_buf());
+    merge(value, fragment);
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    for pattern in include {
+        for file in glob(&dir.join(pattern)) {
+            read_into(value, &file, files)?;
+        }
+    }
+    Ok(())
+}
+
+/// Parse the file at `path` in the syntax given by its extension
+pub fn parse_file<T: serde::de::DeserializeOwned>(path: &Path) -> anyhow::Result<T> {
+    let content = read_file(path)?;
+    Syntax::of(path)
+        .parse(&content)
+        .with_context(|| format!("{}: invalid config", path.display()))
+}
+
+/// The content of the file at `path`, decrypted with sops if it encrypted it
+fn read_file(path: &Path) -> anyhow::Result<String> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("{}: error reading file", path.display()))?;
+    if !is_sops(Syntax::of(path), &content) {
+        return Ok(content);
+    }
+    let output = std::process::Command::new("sops")
+        .arg("--decrypt")
+        .arg(path)
+        .output()
+        .with_context(|| format!("{}: error running sops", path.display()))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "{}: error decrypting with sops: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    String::from_utf8(output.stdout)
+        .with_context(|| format!("{}: decrypted file is not UTF-8", path.display()))
+}
+
+/// Whether `content` was encrypted by sops, which keeps its metadata in a
+/// top-level `sops` table. sops encrypts TOML files as binary data in JSON.
+fn is_sops(syntax: Syntax, content: &str) -> bool {
+    let value: Option<serde_json::Value> = syntax
+        .parse(content)
+        .ok()
+        .or_else(|| Syntax::Json.parse(content).ok());
+    value.is_some_and(|value| value.get("sops").is_some_and(|sops| sops.is_object()))
+}
+
+/// The singular spellings of the lists, as in `[[repository]]`
+const ALIASES: &[(&str, &str)] = &[
+    ("repository", "repositories"),
+    ("job", "jobs"),
+    ("copy", "copies"),
+];
+
+/// Move the lists spelled with an alias to their key, so that they are merged
+/// with the other files' lists
+fn normalize(fragment: &mut toml::Value) {
+    let Some(table) = fragment.as_table_mut() else {
+        return;
+    };
+    for (alias, key) in ALIASES {
+        if let Some(value) = table.remove(*alias) {
+            let list = table
+                .entry(key.to_string())
+                .or_insert_with(|| toml::Value::Array(Vec::new()));
+            merge(list, value);
+        }
+    }
+}
+
+/// The languages a configuration file can be written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Syntax {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl Syntax {
+    /// The syntax of the file at `path`, from its extension. Files with
+    /// another extension are read as TOML.
+    fn of(path: &Path) -> Syntax {
+        Self::from_extension(path).unwrap_or(Syntax::Toml)
+    }
+
+    fn from_extension(path: &Path) -> Option<Syntax> {
+        match path.extension()?.to_str()? {
+            "toml" => Some(Syntax::Toml),
+            "yaml" | "yml" => Some(Syntax::Yaml),
+            "json" => Some(Syntax::Json),
+            _ => None,
+        }
+    }
+
+    fn parse<T: serde::de::DeserializeOwned>(self, content: &str) -> anyhow::Result<T> {
+        Ok(match self {
+            Syntax::Toml => toml::from_str(content)?,
+            Syntax::Yaml => serde_yaml::from_str(content)?,
+            Syntax::Json => serde_json::from_str(content)?,
+        })
+    }
+
+    /// The line, counted from 1, at which deserializing a `Config` from
+    /// `content` fails, if it does and the parser knows where
+    fn error_line(self, content: &str) -> Option<usize> {
+        match self {
+            Syntax::Toml => {
+                let (line, _) = toml::from_str::<Config>(content).err()?.line_col()?;
+                Some(line + 1)
+            }
+            Syntax::Yaml => Some(
+                serde_yaml::from_str::<Config>(content)
+                    .err()?
+                    .location()?
+                    .line(),
+            ),
+            Syntax::Json => Some(serde_json::from_str::<Config>(content).err()?.line()),
+        }
+    }
+}
+
+/// Where the error happened, when the configuration comes from a single file.
+/// The TOML parser reports the line of the table, so look for the key from
+/// there.
+fn error_line<'a>(files: &'a [PathBuf], err: &crate::de::Error) -> Option<(&'a Path, usize)> {
+    let [file] = files else {
+        return None;
+    };
+    let content = fs::read_to_string(file).ok()?;
+    let syntax = Syntax::of(file);
+    if is_sops(syntax, &content) {
+        // The lines of the decrypted file are not known
+        return None;
+    }
+    let line = syntax.error_line(&content)?;
+    if syntax != Syntax::Toml {
+        return Some((file, line));
+    }
+    let Some(key) = err.key() else {
+        return Some((file, line));
+    };
+    let key_line = content
+        .lines()
+        .enumerate()
+        .skip(line - 1)
+        .take_while(|(index, text)| *index == line - 1 || !text.trim_start().starts_with('['))
+        .find(|(_, text)| {
+            text.trim_start()
+                .strip_prefix(key)
+                .is_some_and(|rest| rest.trim_start().starts_with('='))
+        })
+        .map(|(index, _)| index + 1);
+    Some((file, key_line.unwrap_or(line)))
+}
+
+/// Add the closest valid key to the errors about unknown keys, which serde
+/// words as "unknown field `x`, expected one of `a`, `b`"
+fn suggest(message: String) -> String {
+    let Some((_, rest)) = message.split_once("unknown field `") else {
+        return message;
+    };
+    let Some((unknown, expected)) = rest.split_once('`') else {
+        return message;
+    };
+    let normalized = unknown.replace('_', "-").to_lowercase();
+    let closest = expected
+        .split('`')
+        .skip(1)
+        .step_by(2)
+        .map(|key| (distance(&normalized, key), key))
+        .min();
+    match closest {
+        Some((distance, key)) if distance <= 1.max(normalized.len() / 3) => {
+            format!("{} (did you mean `{}`?)", message, key)
+        }
+        _ => message,
+    }
+}
+
+/// The Levenshtein distance between `a` and `b`
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Merge the repository described by the file at `path` into `value`. The
+/// repository is named after the file if the file doesn't name it.
+fn read_repository(
+    value: &mut toml::Value,
+    path: &Path,
+    files: &mut Vec<PathBuf>,
+) -> anyhow::Result<()> {
+    let mut repository: toml::Value = parse_file(path)?;
+    let Some(table) = repository.as_table_mut() else {
+        anyhow::bail!("{}: expected a repository table", path.display());
+    };
+    if !table.contains_key("name") {
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        table.insert("name".into(), name.into_owned().into());
+    }
+    let mut fragment = toml::value::Table::new();
+    fragment.insert("repositories".into(), vec![repository].into());
+    files.push(path.to_path_buf());
+    merge(value, toml::Value::Table(fragment));
+    Ok(())
+}
+
+/// The configuration files in `dir`, sorted, or none if it doesn't exist
+fn config_files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut fragments = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("{}: error reading", dir.display()))? {
+        let path = entry?.path();
+        if Syntax::from_extension(&path).is_some() {
+            fragments.push(path);
+        }
+    }
+    fragments.sort();
+    Ok(fragments)
+}
+
+/// Merge `other` into `base`: tables are merged recursively, and lists are
+/// appended to, except for tables with the same name, which are merged.
+fn merge(base: &mut toml::Value, other: toml::Value) {
+    use toml::Value;
+    match (base, other) {
+        (Value::Table(base), Value::Table(other)) => {
+            for (key, value) in other {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Value::Array(base), Value::Array(other)) => {
+            for value in other {
+                let name = value.get("name").cloned();
+                let same = base
+                    .iter_mut()
+                    .find(|item| name.is_some() && item.get("name") == name.as_ref());
+                match same {
+                    Some(existing) => merge(existing, value),
+                    None => base.push(value),
+                }
+            }
+        }
+        (base, other) => *base = other,
+    }
+}
+
+/// Expand the `${VAR}` references to environment variables in `value`.
+/// `$${` is a literal `${`.
+fn interpolate(value: &str, env: &dyn Fn(&str) -> Option<String>) -> anyhow::Result<String> {
+    let mut result = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("$${") {
+            result.push_str("${");
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("${") {
+            let Some(end) = after.find('}') else {
+                anyhow::bail!("unterminated ${{ in {:?}", value);
+            };
+            let name = &after[..end];
+            match env(name) {
+                Some(expansion) => result.push_str(&expansion),
+                None => anyhow::bail!("environment variable {} is not set", name),
+            }
+            rest = &after[end + 1..];
+        } else {
+            result.push('$');
+            rest = &rest[1..];
+        }
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Interpolate strings in place, naming the setting in errors
+macro_rules! interpolate {
+    ($env:expr, $context:expr, $($name:literal => $value:expr),+ $(,)?) => {
+        $(
+            for value in $value {
+                *value = interpolate(value, $env)
+                    .with_context(|| format!("{}{}", $context, $name))?;
+            }
+        )+
+    };
+}
+
+/// Accept a single path as well as a list of paths
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(path) => vec![path],
+        OneOrMany::Many(paths) => paths,
+    })
+}
+
+fn some_one_or_many<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<String>>, D::Error> {
+    one_or_many(deserializer).map(Some)
+}
+
+impl Config {
+    /// The backup jobs: the top-level source, if any, followed by the `[[jobs]]`
+    pub fn jobs<'a>(&'a self) -> Vec<Job<'a>> {
+        let global_exclude = self.exclude.iter().map(String::as_str);
+        // The top-level settings followed by the job's
+        let both = |top: &'a [String], job: &'a [String]| -> Vec<&'a str> {
+            top.iter().chain(job.iter()).map(String::as_str).collect()
+        };
+        // The plain tags followed by the post-backup ones
+        let tags = |name: &str, tags: &[String], extra: &[String]| -> Vec<String> {
+            let templates = self.post_backup_tags.iter().chain(extra.iter());
+            let templates = templates.map(|tag| tag.replace("{job}", name));
+            self.tags
+                .iter()
+                .chain(tags)
+                .cloned()
+                .chain(templates)
+                .collect()
+        };
+        let forget_tags = |by_tags: bool, tags: &'a [String], filter: &'a [String]| {
+            if !filter.is_empty() {
+                both(filter, &[])
+            } else if !self.forget_tags.is_empty() {
+                both(&self.forget_tags, &[])
+            } else if by_tags {
+                both(&self.tags, tags)
+            } else {
+                Vec::new()
+            }
+        };
+        let mut jobs = Vec::new();
+        if !self.source.is_empty()
+            || !self.files_from.is_empty()
+            || !self.files_from_verbatim.is_empty()
+        {
+            jobs.push(Job {
+                name: None,
+                source: &self.source,
+                files_from: &self.files_from,
+                files_from_verbatim: &self.files_from_verbatim,
+                command: &[],
+                stdin_filename: None,
+                exclude: global_exclude.clone().collect(),
+                iexclude: both(&self.iexclude, &[]),
+                exclude_file: both(&self.exclude_file, &[]),
+                iexclude_file: both(&self.iexclude_file, &[]),
+                exclude_caches: self.exclude_caches,
+                exclude_if_present: both(&self.exclude_if_present, &[]),
+                exclude_larger_than: self.exclude_larger_than.as_ref(),
+                one_file_system: self.one_file_system,
+                read_concurrency: self.read_concurrency,
+                no_scan: self.no_scan,
+                ignore_inode: self.ignore_inode,
+                ignore_ctime: self.ignore_ctime,
+                with_atime: self.with_atime,
+                skip_if_unchanged: self.skip_if_unchanged,
+                tags: tags("default", &[], &[]),
+                forget_tags: forget_tags(self.forget_by_tags, &[], &[]),
+                forget_paths: &self.forget_paths,
+                host: self.host.as_deref(),
+                repositories: None,
+                hosts: &[],
+                retention: Retention::default(),
+            });
+        }
+        for job in self.jobs.iter() {
+            jobs.push(Job {
+                name: Some(&job.name),
+                source: &job.source,
+                files_from: &job.files_from,
+                files_from_verbatim: &job.files_from_verbatim,
+                command: &job.command,
+                stdin_filename: job.stdin_filename.as_deref(),
+                exclude: global_exclude
+                    .clone()
+                    .chain(job.exclude.iter().map(String::as_str))
+                    .collect(),
+                iexclude: both(&self.iexclude, &job.iexclude),
+                exclude_file: both(&self.exclude_file, &job.exclude_file),
+                iexclude_file: both(&self.iexclude_file, &job.iexclude_file),
+                exclude_caches: self.exclude_caches || job.exclude_caches,
+                exclude_if_present: both(&self.exclude_if_present, &job.exclude_if_present),
+                exclude_larger_than: job
+                    .exclude_larger_than
+                    .as_ref()
+                    .or(self.exclude_larger_than.as_ref()),
+                one_file_system: self.one_file_system || job.one_file_system,
+                read_concurrency: job.read_concurrency.or(self.read_concurrency),
+                no_scan: self.no_scan || job.no_scan,
+                ignore_inode: self.ignore_inode || job.ignore_inode,
+                ignore_ctime: self.ignore_ctime || job.ignore_ctime,
+                with_atime: self.with_atime || job.with_atime,
+                skip_if_unchanged: self.skip_if_unchanged || job.skip_if_unchanged,
+                tags: tags(&job.name, &job.tags, &job.post_backup_tags),
+                forget_tags: forget_tags(
+                    self.forget_by_tags || job.forget_by_tags,
+                    &job.tags,
+                    &job.forget_tags,
+                ),
+                forget_paths: &job.forget_paths,
+                host: job.host.as_deref().or(self.host.as_deref()),
+                repositories: Some(&job.repositories),
+                hosts: &job.hosts,
+                retention: retention!(job),
+            });
+        }
+        jobs
+    }
+
+    /// Expand the environment variables in the string settings, except for
+    /// the names of the jobs and repositories and the references to them.
+    /// The other settings, like calendars, are parsed before.
+    fn interpolate(&mut self, env: &dyn Fn(&str) -> Option<String>) -> anyhow::Result<()> {
+        interpolate!(env, "",
+            "source" => self.source.iter_mut(),
+            "files-from" => self.files_from.iter_mut(),
+            "files-from-verbatim" => self.files_from_verbatim.iter_mut(),
+            "exclude" => self.exclude.iter_mut(),
+            "iexclude" => self.iexclude.iter_mut(),
+            "exclude-file" => self.exclude_file.iter_mut(),
+            "iexclude-file" => self.iexclude_file.iter_mut(),
+            "exclude-if-present" => self.exclude_if_present.iter_mut(),
+            "tags" => self.tags.iter_mut(),
+            "forget-tags" => self.forget_tags.iter_mut(),
+            "forget-paths" => self.forget_paths.iter_mut(),
+            "post-backup-tags" => self.post_backup_tags.iter_mut(),
+            "host" => self.host.iter_mut(),
+            "environment" => self.environment.values_mut(),
+        );
+        let defaults = &mut self.defaults;
+        interpolate!(env, "defaults: ",
+            "keep-tag" => defaults.keep_tag.iter_mut().flatten(),
+            "retention.keep-tag" => defaults.retention.keep_tag.iter_mut().flatten(),
+        );
+        for job in self.jobs.iter_mut() {
+            let context = format!("job {}: ", job.name);
+            interpolate!(env, context,
+                "source" => job.source.iter_mut(),
+                "files-from" => job.files_from.iter_mut(),
+                "files-from-verbatim" => job.files_from_verbatim.iter_mut(),
+                "command" => job.command.iter_mut(),
+                "stdin-filename" => job.stdin_filename.iter_mut(),
+                "exclude" => job.exclude.iter_mut(),
+                "iexclude" => job.iexclude.iter_mut(),
+                "exclude-file" => job.exclude_file.iter_mut(),
+                "iexclude-file" => job.iexclude_file.iter_mut(),
+                "exclude-if-present" => job.exclude_if_present.iter_mut(),
+                "tags" => job.tags.iter_mut(),
+                "forget-tags" => job.forget_tags.iter_mut(),
+                "forget-paths" => job.forget_paths.iter_mut(),
+                "post-backup-tags" => job.post_backup_tags.iter_mut(),
+                "host" => job.host.iter_mut(),
+                "hosts" => job.hosts.iter_mut(),
+                "keep-tag" => job.keep_tag.iter_mut().flatten(),
+                "retention.keep-tag" => job.retention.keep_tag.iter_mut().flatten(),
+            );
+        }
+        for repository in self.repositories.iter_mut() {
+            let context = format!("repository {}: ", repository.name);
+            interpolate!(env, context,
+                "location" => std::iter::once(&mut repository.location),
+                "password-command" => repository.password_command.iter_mut(),
+                "password-file" => repository.password_file.iter_mut(),
+                "aws-access-key" => repository.aws_access_key.iter_mut(),
+                "aws-secret-access-key" => repository.aws_secret_access_key.iter_mut(),
+                "azure-account-name" => repository.azure_account_name.iter_mut(),
+                "azure-account-key" => repository.azure_account_key.iter_mut(),
+                "azure-account-sas" => repository.azure_account_sas.iter_mut(),
+                "exclude" => repository.exclude.iter_mut(),
+                "iexclude" => repository.iexclude.iter_mut(),
+                "exclude-file" => repository.exclude_file.iter_mut(),
+                "iexclude-file" => repository.iexclude_file.iter_mut(),
+                "exclude-if-present" => repository.exclude_if_present.iter_mut(),
+                "hosts" => repository.hosts.iter_mut(),
+                "environment" => repository.environment.values_mut(),
+                "options" => repository.options.values_mut(),
+                "backup-extra-args" => repository.backup_extra_args.iter_mut(),
+                "forget-extra-args" => repository.forget_extra_args.iter_mut(),
+                "prune-extra-args" => repository.prune_extra_args.iter_mut(),
+                "check-extra-args" => repository.check_extra_args.iter_mut(),
+                "mountpoint" => repository.mountpoint.iter_mut(),
+                "restore-target" => repository.restore_target.iter_mut(),
+                "keep-tag" => repository.keep_tag.iter_mut().flatten(),
+                "retention.keep-tag" => repository.retention.keep_tag.iter_mut().flatten(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Fill in the repository settings from the `[defaults]` section
+    pub fn apply_defaults(&mut self) {
+        flatten_retention!(self.defaults);
+        let defaults = &self.defaults;
+        for repository in self.repositories.iter_mut() {
+            flatten_retention!(repository);
+            inherit!(
+                repository,
+                defaults,
+                keep_last,
+                keep_hourly,
+                keep_daily,
+                keep_weekly,
+                keep_monthly,
+                keep_yearly,
+                keep_tag,
+                keep_within,
+                keep_within_hourly,
+                keep_within_daily,
+                keep_within_weekly,
+                keep_within_monthly,
+                keep_within_yearly,
+                group_by,
+                backup_calendar,
+                forget_calendar,
+                prune_calendar,
+                check_calendar,
+                stats_calendar,
+                check,
+                init,
+                stats,
+                max_age,
+                repair,
+                rewrite,
+                migrate,
+                diff,
+                unlock,
+                retry_lock,
+                pack_size,
+                limit_upload,
+                limit_download,
+                randomized_delay,
+                fixed_random_delay,
+                accuracy,
+                require_ac_power
+            );
+            if self.profile == Some(Profile::Laptop) && repository.require_ac_power.is_none() {
+                repository.require_ac_power = Some(true);
+            }
+            for (name, value) in self.environment.iter() {
+                if !repository.environment.contains_key(name) {
+                    repository.environment.insert(name.clone(), value.clone());
+                }
+            }
+        }
+    }
+
+    /// Check that the jobs and copies only refer to existing repositories
+    pub fn check_jobs(&self) -> anyhow::Result<()> {
+        for job in self.jobs.iter() {
+            for name in job.repositories.iter() {
+                self.repository(name)
+                    .map_err(|err| anyhow::anyhow!("job {}: {}", job.name, err))?;
+            }
+        }
+        for copy in self.copies.iter() {
+            for name in [&copy.from, &copy.to] {
+                self.repository(name)
+                    .map_err(|err| anyhow::anyhow!("copy {} to {}: {}", copy.from, copy.to, err))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Find a repository by name
+    pub fn repository(&self, name: &str) -> anyhow::Result<&RepositoryConfig> {
+        match self.repositories.iter().find(|repo| repo.name == name) {
+            Some(repository) => Ok(repository),
+            None => {
+                let names: Vec<_> = self.repositories.iter().map(|r| r.name.as_str()).collect();
+                anyhow::bail!(
+                    "no repository named {} (available: {})",
+                    name,
+                    names.join(", ")
+                )
+            }
+        }
+    }
 }
 
 #[derive(Debug, Default, Deserialize)]
-#[serde(rename_all = "kebab-case")]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct RepositoryConfig {
     pub name: String,
     pub location: String,
+    /// Set to false to generate no units for the repository
+    pub enabled: Option<bool>,
+    /// The machines backing up to the repository, all of them if empty
+    #[serde(default)]
+    pub hosts: Vec<String>,
     pub password_command: Option<String>,
     pub password_file: Option<String>,
     pub aws_access_key: Option<String>,
     pub aws_secret_access_key: Option<String>,
+    /// The credentials of `azure:` repositories: the storage account, with
+    /// its key or a shared access signature
+    pub azure_account_name: Option<String>,
+    pub azure_account_key: Option<String>,
+    pub azure_account_sas: Option<String>,
+    /// Excluded from the backups to this repository, in addition to the
+    /// top-level and job patterns
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub iexclude: Vec<String>,
+    #[serde(default)]
+    pub exclude_file: Vec<String>,
+    #[serde(default)]
+    pub iexclude_file: Vec<String>,
+    #[serde(default)]
+    pub exclude_caches: bool,
+    #[serde(default)]
+    pub exclude_if_present: Vec<String>,
+    /// The size above which the backups to this repository skip files, when
+    /// smaller than the job's
+    pub exclude_larger_than: Option<Size>,
+    /// Variables set in the environment of restic, overriding the top-level
+    /// ones
+    #[serde(default)]
+    pub environment: BTreeMap<String, String>,
+    /// The backend options given to every restic command with `--option`,
+    /// e.g. `{ "s3.storage-class" = "STANDARD_IA" }`
+    #[serde(default)]
+    pub options: BTreeMap<String, String>,
+    /// Arguments appended to the restic commands, for the options that have
+    /// no setting
+    #[serde(default)]
+    pub backup_extra_args: Vec<String>,
+    #[serde(default)]
+    pub forget_extra_args: Vec<String>,
+    #[serde(default)]
+    pub prune_extra_args: Vec<String>,
+    #[serde(default)]
+    pub check_extra_args: Vec<String>,
+    /// When the timers start the units, by default daily backups, weekly
+    /// forgets and stats and monthly prunes and checks
+    pub backup_calendar: Option<Calendar>,
+    pub forget_calendar: Option<Calendar>,
+    pub prune_calendar: Option<Calendar>,
+    pub check_calendar: Option<Calendar>,
+    pub stats_calendar: Option<Calendar>,
+    /// Whether a unit checks the integrity of the repository
+    pub check: Option<bool>,
+    /// Whether a unit started before the backups initializes the repository
+    /// if it doesn't exist
+    pub init: Option<bool>,
+    /// Whether a unit records the size of the repository, in
+    /// restic-generator's state directory and in the journal
+    pub stats: Option<bool>,
+    /// How old the latest snapshot of this machine may get before a unit
+    /// checking it daily fails, e.g. when the backups silently stopped
+    pub max_age: Option<TimeSpan>,
+    /// Whether units started by hand repair the index and the snapshots of
+    /// the repository, for when check finds it damaged
+    pub repair: Option<bool>,
+    /// Whether a unit started by hand removes the files now excluded from
+    /// the existing snapshots, replacing them (only the exclude patterns and
+    /// files apply, not the caches, exclude-if-present or exclude-larger-than)
+    pub rewrite: Option<bool>,
+    /// Whether a unit started by hand upgrades the repository to the
+    /// format 2, which compresses the data (set repack-uncompressed
+    /// afterwards for the scheduled prunes to compress the existing data)
+    pub migrate: Option<bool>,
+    /// Whether a unit started after each successful backup logs what
+    /// changed since the previous snapshot of the job
+    pub diff: Option<bool>,
+    /// Which locks the units remove first, by default the stale ones, and
+    /// the unlock unit started by hand removes
+    pub unlock: Option<Unlock>,
+    /// How long restic waits for a lock held by another process, instead of
+    /// failing at once (restic 0.16 or later)
+    pub retry_lock: Option<TimeSpan>,
+    /// The target size of the pack files in MiB, between 4 and 128: larger
+    /// packs mean fewer requests to high-latency object storage
+    pub pack_size: Option<u32>,
+    /// The bandwidth the units transferring data may use to upload to the
+    /// repository, in KiB/s
+    pub limit_upload: Option<u32>,
+    /// Like limit-upload, for the downloads from the repository
+    pub limit_download: Option<u32>,
+    /// Where a unit started by hand mounts the repository to browse the
+    /// snapshots
+    pub mountpoint: Option<String>,
+    /// Where a template unit started by hand, with a snapshot ID or latest
+    /// as instance, restores the snapshot
+    pub restore_target: Option<String>,
+    /// Whether check uses the local cache instead of a temporary one
+    #[serde(default)]
+    pub check_with_cache: bool,
+    /// Whether the backups only show what they would do, writing nothing to
+    /// the repository, e.g. to try a new repository or exclude set
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Whether check reads all the data, not just the metadata
+    #[serde(default)]
+    pub check_read_data: bool,
+    /// The part of the data check reads at each run, instead of all of it
+    pub check_read_data_subset: Option<DataSubset>,
+    /// The unused space prune may leave instead of repacking the data, by
+    /// default 5%
+    pub max_unused: Option<MaxUnused>,
+    /// Whether prune compresses the data stored uncompressed, e.g. after the
+    /// migration to the format 2
+    #[serde(default)]
+    pub repack_uncompressed: bool,
+    /// Whether prune merges the small pack files
+    #[serde(default)]
+    pub repack_small: bool,
+    /// The most data each prune repacks, e.g. `10G`, to bound its duration
+    pub max_repack_size: Option<Size>,
+    /// Set to manual to generate the services without their timers
+    #[serde(default)]
+    pub schedule: Schedule,
+    /// Set to chained to start forget after each successful backup and
+    /// prune after forget, instead of with their own timers
+    #[serde(default)]
+    pub maintenance: Maintenance,
+    /// Set to timer to prune on prune-calendar whatever the forget policies
+    /// and maintenance, to manual to only prune by hand, or to never
+    #[serde(default)]
+    pub prune: Pruning,
+    /// When prune runs, instead of prune-calendar: it is stopped at the end
+    /// of the window
+    pub prune_window: Option<Window>,
+    /// The RandomizedDelaySec= and AccuracySec= of the timers, by default a
+    /// random delay of up to 30 minutes for remote repositories
+    pub randomized_delay: Option<TimeSpan>,
+    pub accuracy: Option<TimeSpan>,
+    /// Whether the random delay is the same at every run, for a given
+    /// machine and unit
+    pub fixed_random_delay: Option<bool>,
+    /// Whether the backups are skipped when the machine is on battery, by
+    /// default only with the laptop profile
+    pub require_ac_power: Option<bool>,
+    /// Whether the timers run the units missed while the machine was off,
+    /// by default only for backups
+    pub persistent: Option<bool>,
+    /// Whether the timers wake the machine from suspend
+    #[serde(default)]
+    pub wake_system: bool,
 
     // Forget policies
     pub keep_last: Option<usize>,
@@ -28,20 +1994,39 @@ pub struct RepositoryConfig {
     pub keep_weekly: Option<usize>,
     pub keep_monthly: Option<usize>,
     pub keep_yearly: Option<usize>,
-    pub keep_tag: Option<String>,
-    pub keep_within: Option<String>,
+    /// The tags of the snapshots kept, one or a list
+    #[serde(default, deserialize_with = "some_one_or_many")]
+    pub keep_tag: Option<Vec<String>>,
+    pub keep_within: Option<Duration>,
+    pub keep_within_hourly: Option<Duration>,
+    pub keep_within_daily: Option<Duration>,
+    pub keep_within_weekly: Option<Duration>,
+    pub keep_within_monthly: Option<Duration>,
+    pub keep_within_yearly: Option<Duration>,
+    /// How forget groups the snapshots, by default by host and paths
+    pub group_by: Option<GroupBy>,
+    /// The forget policy as a table, e.g. `[repositories.retention]`: the
+    /// keep-* settings outside of it take precedence
+    #[serde(default)]
+    pub retention: Retention,
 }
 
 impl RepositoryConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled != Some(false)
+    }
+
+    /// Whether the machine named `hostname` backs up to the repository
+    pub fn runs_on(&self, hostname: &str) -> bool {
+        matches_host(&self.hosts, hostname)
+    }
+
     pub fn has_forget_policy(&self) -> bool {
-        self.keep_last.is_some()
-            || self.keep_hourly.is_some()
-            || self.keep_daily.is_some()
-            || self.keep_weekly.is_some()
-            || self.keep_monthly.is_some()
-            || self.keep_yearly.is_some()
-            || self.keep_tag.is_some()
-            || self.keep_within.is_some()
+        !self.retention().is_empty()
+    }
+
+    pub fn retention(&self) -> Retention {
+        retention!(self)
     }
 }
 
@@ -49,6 +2034,1066 @@ impl RepositoryConfig {
 mod tests {
     use super::*;
 
+    #[test]
+    fn find_repository() {
+        let config = Config {
+            repositories: vec![
+                RepositoryConfig {
+                    name: "a".into(),
+                    ..Default::default()
+                },
+                RepositoryConfig {
+                    name: "b".into(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(config.repository("b").unwrap().name, "b");
+        let err = config.repository("c").unwrap_err();
+        assert_eq!(err.to_string(), "no repository named c (available: a, b)");
+    }
+
+    #[test]
+    fn jobs() {
+        let config: Config = toml::from_str(
+            r#"
+source = "/"
+exclude = ["*~"]
+host = "laptop"
+
+[[repositories]]
+name = "a"
+location = "/a"
+
+[[repositories]]
+name = "b"
+location = "/b"
+
+[[job]]
+name = "var"
+source = "/var/lib"
+exclude = ["*.tmp"]
+host = "server"
+repositories = ["b"]
+"#,
+        )
+        .unwrap();
+        let jobs = config.jobs();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].name, None);
+        assert!(jobs[0].targets(&config.repositories[0]));
+        assert_eq!(jobs[1].name, Some("var"));
+        assert_eq!(jobs[1].exclude, vec!["*~", "*.tmp"]);
+        assert_eq!(jobs[1].host, Some("server"));
+        assert!(!jobs[1].targets(&config.repositories[0]));
+        assert!(jobs[1].targets(&config.repositories[1]));
+        config.check_jobs().unwrap();
+    }
+
+    #[test]
+    fn defaults() {
+        let mut config: Config = toml::from_str(
+            r#"
+source = "/"
+
+[defaults]
+keep-daily = 7
+keep-weekly = 4
+
+[[repositories]]
+name = "a"
+location = "/a"
+
+[[repositories]]
+name = "b"
+location = "/b"
+keep-daily = 30
+"#,
+        )
+        .unwrap();
+        config.apply_defaults();
+        let (a, b) = (&config.repositories[0], &config.repositories[1]);
+        assert_eq!((a.keep_daily, a.keep_weekly), (Some(7), Some(4)));
+        assert_eq!((b.keep_daily, b.keep_weekly), (Some(30), Some(4)));
+        assert_eq!(a.keep_monthly, None);
+    }
+
+    #[test]
+    fn retention_tables() {
+        let mut config: Config = toml::from_str(
+            r#"
+[defaults.retention]
+keep-daily = 7
+keep-weekly = 4
+
+[[repositories]]
+name = "a"
+location = "/a"
+keep-weekly = 8
+retention = { keep-weekly = 2, keep-monthly = 12, keep-tag = "important" }
+
+[[repositories]]
+name = "b"
+location = "/b"
+
+[[job]]
+name = "home"
+source = "/home"
+repositories = ["b"]
+retention = { keep-daily = 30 }
+"#,
+        )
+        .unwrap();
+        config.apply_defaults();
+        let (a, b) = (&config.repositories[0], &config.repositories[1]);
+        assert_eq!(
+            (a.keep_daily, a.keep_weekly, a.keep_monthly),
+            (Some(7), Some(8), Some(12))
+        );
+        assert_eq!(a.keep_tag, Some(vec!["important".to_string()]));
+        assert_eq!((b.keep_daily, b.keep_weekly), (Some(7), Some(4)));
+        let jobs = config.jobs();
+        let retention = jobs[0].retention(b);
+        assert_eq!(
+            (retention.keep_daily, retention.keep_weekly),
+            (Some(30), Some(4))
+        );
+
+        let repository: RepositoryConfig =
+            toml::from_str("name = \"c\"\nlocation = \"/c\"\n[retention]\nkeep-last = 3\n")
+                .unwrap();
+        assert!(repository.has_forget_policy());
+        assert!(toml::from_str::<RepositoryConfig>(
+            "name = \"c\"\nlocation = \"/c\"\n[retention]\nkeep-dayly = 3\n"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn laptop_profile() {
+        let mut config: Config = toml::from_str(
+            r#"
+source = "/"
+profile = "laptop"
+
+[[repositories]]
+name = "a"
+location = "/a"
+
+[[repositories]]
+name = "b"
+location = "/b"
+require-ac-power = false
+"#,
+        )
+        .unwrap();
+        config.apply_defaults();
+        let (a, b) = (&config.repositories[0], &config.repositories[1]);
+        assert_eq!(a.require_ac_power, Some(true));
+        assert_eq!(b.require_ac_power, Some(false));
+    }
+
+    #[test]
+    fn cache_cleanup() {
+        let config: Config =
+            toml::from_str("source = \"/\"\ncache-cleanup = { max-age = 7 }\n").unwrap();
+        let cleanup = config.cache_cleanup.unwrap();
+        assert_eq!((cleanup.max_age, cleanup.calendar), (Some(7), None));
+    }
+
+    #[test]
+    fn default_calendars() {
+        let mut config: Config = toml::from_str(
+            r#"
+source = "/"
+
+[defaults]
+backup-calendar = "hourly"
+prune-calendar = "Sun 03:00"
+
+[[repositories]]
+name = "a"
+location = "/a"
+
+[[repositories]]
+name = "b"
+location = "/b"
+backup-calendar = "daily"
+"#,
+        )
+        .unwrap();
+        config.apply_defaults();
+        let (a, b) = (&config.repositories[0], &config.repositories[1]);
+        assert_eq!(a.backup_calendar, Some(Calendar::new("hourly")));
+        assert_eq!(b.backup_calendar, Some(Calendar::new("daily")));
+        assert_eq!(b.prune_calendar, Some(Calendar::new("Sun 03:00")));
+        assert_eq!(a.forget_calendar, None);
+    }
+
+    #[test]
+    fn environment_tables() {
+        let mut config: Config = toml::from_str(
+            r#"
+source = "/"
+
+[environment]
+GOGC = "20"
+HTTPS_PROXY = "http://proxy:3128"
+
+[[repositories]]
+name = "a"
+location = "/a"
+
+[[repositories]]
+name = "b"
+location = "/b"
+environment = { GOGC = "50", RESTIC_PACK_SIZE = "64" }
+"#,
+        )
+        .unwrap();
+        config.apply_defaults();
+        let environment = |index: usize| -> Vec<_> {
+            config.repositories[index]
+                .environment
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect()
+        };
+        assert_eq!(
+            environment(0),
+            vec!["GOGC=20", "HTTPS_PROXY=http://proxy:3128"]
+        );
+        assert_eq!(
+            environment(1),
+            vec![
+                "GOGC=50",
+                "HTTPS_PROXY=http://proxy:3128",
+                "RESTIC_PACK_SIZE=64"
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_fragments() {
+        let mut base: toml::Value = toml::from_str(
+            r#"
+source = "/"
+exclude = ["*~"]
+
+[[repositories]]
+name = "a"
+location = "/a"
+"#,
+        )
+        .unwrap();
+        let fragment = toml::from_str(
+            r#"
+source = "/home"
+exclude = ["*.tmp"]
+
+[[repositories]]
+name = "a"
+password-file = "/secret"
+
+[[repositories]]
+name = "b"
+location = "/b"
+"#,
+        )
+        .unwrap();
+        merge(&mut base, fragment);
+        let config: Config = base.try_into().unwrap();
+        assert_eq!(config.source, vec!["/home"]);
+        assert_eq!(config.exclude, vec!["*~", "*.tmp"]);
+        assert_eq!(config.repositories.len(), 2);
+        assert_eq!(config.repositories[0].location, "/a");
+        assert_eq!(
+            config.repositories[0].password_file.as_deref(),
+            Some("/secret")
+        );
+    }
+
+    #[test]
+    fn load_conf_d() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "source = \"/\"\n").unwrap();
+        fs::create_dir(dir.path().join("conf.d")).unwrap();
+        let fragment = |name: &str, repository: &str| {
+            let content = format!(
+                "[[repositories]]\nname = \"{0}\"\nlocation = \"/{0}\"\n",
+                repository
+            );
+            fs::write(dir.path().join("conf.d").join(name), content).unwrap();
+        };
+        fragment("20-b.toml", "b");
+        fragment("10-a.toml", "a");
+        fragment("ignored.txt", "c");
+        let config = load(&path).unwrap();
+        let names: Vec<_> = config
+            .repositories
+            .iter()
+            .map(|r| r.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["a", "b"]);
+        assert_eq!(config.files.len(), 3);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(load(&path).unwrap().repositories.len(), 2);
+    }
+
+    #[test]
+    fn unknown_keys() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(
+            &path,
+            "source = \"/\"\n\n[[repositories]]\nname = \"a\"\nlocation = \"/a\"\nkeep_dayly = 7\n",
+        )
+        .unwrap();
+        let message = format!("{:#}", load(&path).unwrap_err());
+        assert!(
+            message.contains("repositories[0].keep_dayly at line 6: unknown field `keep_dayly`"),
+            "{}",
+            message
+        );
+        assert!(
+            message.ends_with("(did you mean `keep-daily`?)"),
+            "{}",
+            message
+        );
+        fs::write(&path, "sources = \"/\"\n").unwrap();
+        let message = format!("{:#}", load(&path).unwrap_err());
+        assert!(message.ends_with("(did you mean `source`?)"), "{}", message);
+        fs::write(&path, "colour = \"blue\"\n").unwrap();
+        let message = format!("{:#}", load(&path).unwrap_err());
+        assert!(!message.contains("did you mean"), "{}", message);
+    }
+
+    #[test]
+    fn error_locations() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(
+            &path,
+            "source = \"/\"\n\n[[repositories]]\nname = \"a\"\nlocation = \"/a\"\n\n[[repositories]]\nname = \"b\"\nlocation = \"/b\"\nkeep-daily = \"seven\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            format!("{:#}", load(&path).unwrap_err()),
+            format!(
+                "{}: invalid config for repository b: repositories[1].keep-daily at line 10: invalid type: string \"seven\", expected usize",
+                path.display()
+            )
+        );
+        let path = dir.path().join("config.yaml");
+        fs::write(
+            &path,
+            "source: /\nrepositories:\n  - name: a\n    location: /a\n    keep-daily: seven\n",
+        )
+        .unwrap();
+        let message = format!("{:#}", load(&path).unwrap_err());
+        assert!(
+            message.contains("repositories[0].keep-daily at line 5: invalid type"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn durations() {
+        let duration: Duration = "2y5m7d3h".parse().unwrap();
+        assert_eq!(
+            duration,
+            Duration {
+                years: 2,
+                months: 5,
+                days: 7,
+                hours: 3
+            }
+        );
+        assert_eq!(duration.to_string(), "2y5m7d3h");
+        assert_eq!("3h14d".parse::<Duration>().unwrap().to_string(), "14d3h");
+        assert_eq!("0d".parse::<Duration>().unwrap().to_string(), "0h");
+        for invalid in ["", "d", "14", "1dd", "1w", "2x5q", "99999999999d"] {
+            assert!(invalid.parse::<Duration>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn invalid_keep_within() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(
+            &path,
+            "source = \"/\"\n\n[[repositories]]\nname = \"a\"\nlocation = \"/a\"\nkeep-within = \"2x5q\"\n",
+        )
+        .unwrap();
+        let message = format!("{:#}", load(&path).unwrap_err());
+        assert!(
+            message.ends_with(
+                "repositories[0].keep-within at line 6: invalid duration \"2x5q\" (expected e.g. 2y5m7d3h)"
+            ),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn calendars() {
+        for valid in [
+            "daily",
+            "Hourly",
+            "Sat *-*-* 02:00",
+            "Mon..Fri 22:30",
+            "Mon,Wed",
+            "*-*-01 03:00:00",
+            "2026-10-14 12:00 UTC",
+            "*-*~1",
+            "*:0/15",
+            "Sun *-*-* 04:00:00.5 Europe/Paris",
+        ] {
+            assert!(valid.parse::<Calendar>().is_ok(), "{}", valid);
+        }
+        for invalid in ["", "sometimes", "02:00 Mon", "Mon Tue", "*-*-* 25", "1d"] {
+            assert!(invalid.parse::<Calendar>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn time_spans() {
+        for valid in ["30min", "0", "90", "1h 30s", "2.5h", "1h30m", "5 minutes"] {
+            assert!(valid.parse::<TimeSpan>().is_ok(), "{}", valid);
+        }
+        for invalid in ["", "soon", "1x", "h", "1.2.3s"] {
+            assert!(invalid.parse::<TimeSpan>().is_err(), "{}", invalid);
+        }
+        assert_eq!(TimeSpan::new("1h 30s").seconds(), 3630.0);
+        assert_eq!(TimeSpan::new("90").seconds(), 90.0);
+        assert_eq!(TimeSpan::new("2d").seconds(), 2.0 * 86400.0);
+    }
+
+    #[test]
+    fn windows() {
+        let window: Window = "Sat *-*-* 02:00..06:00".parse().unwrap();
+        assert_eq!(window.start, Calendar::new("Sat *-*-* 02:00"));
+        assert_eq!(window.seconds, 4 * 3600);
+        let window: Window = "23:30..01:00".parse().unwrap();
+        assert_eq!(window.start, Calendar::new("23:30"));
+        assert_eq!(window.seconds, 90 * 60);
+        for invalid in [
+            "",
+            "Sat",
+            "Sat 02:00",
+            "Sat 02:00..25:00",
+            "sometimes 02:00..03:00",
+        ] {
+            assert!(invalid.parse::<Window>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn data_subsets() {
+        assert_eq!(
+            "1/30".parse::<DataSubset>().unwrap(),
+            DataSubset::Rotation(30)
+        );
+        for random in ["10%", "2.5%", "500M", "1G", "1024"] {
+            assert_eq!(
+                random.parse::<DataSubset>().unwrap(),
+                DataSubset::Random(random.into())
+            );
+        }
+        for invalid in ["", "0/30", "31/30", "1/", "120%", "1GB", "half"] {
+            assert!(invalid.parse::<DataSubset>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn invalid_calendar() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(
+            &path,
+            "source = \"/\"\n\n[[repositories]]\nname = \"a\"\nlocation = \"/a\"\nbackup-calendar = \"every day\"\n",
+        )
+        .unwrap();
+        let message = format!("{:#}", load(&path).unwrap_err());
+        assert!(
+            message.ends_with(
+                "invalid config for repository a: repositories[0].backup-calendar at line 6: invalid calendar expression \"every day\" (expected e.g. daily or Sat *-*-* 02:00)"
+            ),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn edit_distance() {
+        assert_eq!(distance("keep-dayly", "keep-daily"), 1);
+        assert_eq!(distance("", "abc"), 3);
+        assert_eq!(distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn sops_files() {
+        assert!(is_sops(
+            Syntax::Yaml,
+            "source: ENC[AES256_GCM,data:...]\nsops:\n  version: 3.8.1\n"
+        ));
+        assert!(is_sops(
+            Syntax::Toml,
+            r#"{"data": "ENC[AES256_GCM,data:...]", "sops": {"version": "3.8.1"}}"#
+        ));
+        assert!(!is_sops(Syntax::Toml, "source = \"/\"\n"));
+        assert!(!is_sops(Syntax::Yaml, "sops: 3\n"));
+    }
+
+    #[test]
+    fn load_yaml_and_json() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.yaml");
+        fs::write(
+            &path,
+            "source: /home\nrepositories:\n  - name: a\n    location: /a\n    keep-daily: 7\n",
+        )
+        .unwrap();
+        let config = load(&path).unwrap();
+        assert_eq!(config.source, vec!["/home"]);
+        assert_eq!(config.repositories[0].keep_daily, Some(7));
+        fs::create_dir(dir.path().join("conf.d")).unwrap();
+        fs::write(
+            dir.path().join("conf.d/b.json"),
+            r#"{"repositories": [{"name": "b", "location": "/b"}]}"#,
+        )
+        .unwrap();
+        let config = load(&path).unwrap();
+        assert_eq!(config.repositories.len(), 2);
+        assert_eq!(config.repositories[1].location, "/b");
+    }
+
+    #[test]
+    fn load_directory() {
+        let dir = tempfile::TempDir::new().unwrap();
+        assert!(load(dir.path()).is_err());
+        fs::write(dir.path().join("10-source.toml"), "source = \"/\"\n").unwrap();
+        fs::write(
+            dir.path().join("20-repo.yaml"),
+            "repositories:\n  - name: a\n    location: /a\n",
+        )
+        .unwrap();
+        let config = load(dir.path()).unwrap();
+        assert_eq!(config.source, vec!["/"]);
+        assert_eq!(config.repositories[0].name, "a");
+        assert_eq!(config.files.len(), 2);
+    }
+
+    #[test]
+    fn load_singular_lists() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(
+            &path,
+            r#"
+[[repository]]
+name = "nas"
+location = "sftp:nas:/srv/restic"
+password-file = "/etc/restic/nas"
+keep-daily = 7
+
+[[job]]
+name = "home"
+source = "/home"
+repositories = ["nas"]
+keep-daily = 30
+"#,
+        )
+        .unwrap();
+        let conf_d = dir.path().join("conf.d");
+        fs::create_dir(&conf_d).unwrap();
+        fs::write(
+            conf_d.join("nas.toml"),
+            "[[repositories]]\nname = \"nas\"\nkeep-weekly = 4\n",
+        )
+        .unwrap();
+        let config = load(&path).unwrap();
+        assert_eq!(config.repositories.len(), 1);
+        let jobs = config.jobs();
+        let retention = jobs[0].retention(&config.repositories[0]);
+        assert_eq!(
+            (retention.keep_daily, retention.keep_weekly),
+            (Some(30), Some(4))
+        );
+    }
+
+    #[test]
+    fn load_repositories_d() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(
+            &path,
+            "source = \"/\"\n\n[[repositories]]\nname = \"local\"\nlocation = \"/local\"\npassword-file = \"/old\"\n",
+        )
+        .unwrap();
+        let repositories = dir.path().join("repositories.d");
+        fs::create_dir(&repositories).unwrap();
+        fs::write(
+            repositories.join("nas.toml"),
+            "location = \"sftp:nas:/srv/restic\"\n",
+        )
+        .unwrap();
+        fs::write(
+            repositories.join("secrets.toml"),
+            "name = \"local\"\npassword-file = \"/new\"\n",
+        )
+        .unwrap();
+        let config = load(&path).unwrap();
+        let names: Vec<_> = config
+            .repositories
+            .iter()
+            .map(|r| r.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["local", "nas"]);
+        assert_eq!(
+            config.repositories[0].password_file.as_deref(),
+            Some("/new")
+        );
+        assert_eq!(config.repositories[1].location, "sftp:nas:/srv/restic");
+        assert_eq!(config.files.len(), 3);
+        fs::write(repositories.join("bad.toml"), "keep-daily = \"seven\"\n").unwrap();
+        assert!(load(&path).is_err());
+    }
+
+    #[test]
+    fn load_includes() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(
+            &path,
+            "include = [\"repos/*.toml\", \"secrets.toml\"]\nsource = \"/\"\n",
+        )
+        .unwrap();
+        fs::create_dir(dir.path().join("repos")).unwrap();
+        fs::write(
+            dir.path().join("repos/a.toml"),
+            "[[repositories]]\nname = \"a\"\nlocation = \"/a\"\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("secrets.toml"),
+            "[[repositories]]\nname = \"a\"\npassword-command = \"pass a\"\n",
+        )
+        .unwrap();
+        let config = load(&path).unwrap();
+        assert_eq!(config.files.len(), 3);
+        assert_eq!(config.repositories.len(), 1);
+        assert_eq!(
+            config.repositories[0].password_command.as_deref(),
+            Some("pass a")
+        );
+        fs::write(&path, "include = [\"config.toml\"]\n").unwrap();
+        assert!(load(&path).is_err());
+        fs::write(&path, "include = \"secrets.toml\"\n").unwrap();
+        assert!(
+            format!("{:#}", load(&path).unwrap_err()).contains("include must be a list of paths")
+        );
+    }
+
+    #[test]
+    fn interpolation() {
+        let env = |name: &str| match name {
+            "HOST" => Some("nas".to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            interpolate("sftp:backup@${HOST}:/srv", &env).unwrap(),
+            "sftp:backup@nas:/srv"
+        );
+        assert_eq!(
+            interpolate("echo $${HOST} $HOME $", &env).unwrap(),
+            "echo ${HOST} $HOME $"
+        );
+        assert_eq!(
+            interpolate("${MISSING}", &env).unwrap_err().to_string(),
+            "environment variable MISSING is not set"
+        );
+        assert!(interpolate("${HOST", &env).is_err());
+    }
+
+    #[test]
+    fn interpolate_config() {
+        let mut config = Config {
+            repositories: vec![RepositoryConfig {
+                name: "s3".into(),
+                location: "s3:${BUCKET}".into(),
+                aws_secret_access_key: Some("${SECRET}".into()),
+                password_command: Some("pass ${PASS}".into()),
+                environment: [("B2_ACCOUNT_ID".to_string(), "${ID}".to_string())].into(),
+                backup_extra_args: vec!["--pack-size=${SIZE}".into()],
+                mountpoint: Some("/run/user/${UID}/restic".into()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let err = config.interpolate(&|_| None).unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "repository s3: location: environment variable BUCKET is not set"
+        );
+        config
+            .interpolate(&|name| Some(name.to_lowercase()))
+            .unwrap();
+        assert_eq!(config.repositories[0].location, "s3:bucket");
+        let repository = &config.repositories[0];
+        assert_eq!(repository.aws_secret_access_key.as_deref(), Some("secret"));
+        assert_eq!(repository.password_command.as_deref(), Some("pass pass"));
+        assert_eq!(repository.environment["B2_ACCOUNT_ID"], "id");
+        assert_eq!(repository.backup_extra_args, ["--pack-size=size"]);
+        assert_eq!(
+            repository.mountpoint.as_deref(),
+            Some("/run/user/uid/restic")
+        );
+    }
+
+    #[test]
+    fn post_backup_tags() {
+        let config: Config = toml::from_str(
+            r#"
+source = "/"
+post-backup-tags = ["job:{job}"]
+
+[[jobs]]
+name = "photos"
+source = "/photos"
+repositories = []
+post-backup-tags = ["media"]
+"#,
+        )
+        .unwrap();
+        let jobs = config.jobs();
+        assert_eq!(jobs[0].tags, vec!["job:default"]);
+        assert_eq!(jobs[1].tags, vec!["job:photos", "media"]);
+    }
+
+    #[test]
+    fn tags() {
+        let config: Config = toml::from_str(
+            r#"
+source = "/"
+tags = ["system"]
+post-backup-tags = ["job:{job}"]
+
+[[jobs]]
+name = "photos"
+source = "/photos"
+repositories = []
+tags = ["nightly"]
+forget-by-tags = true
+"#,
+        )
+        .unwrap();
+        let jobs = config.jobs();
+        assert_eq!(jobs[0].tags, vec!["system", "job:default"]);
+        assert!(jobs[0].forget_tags.is_empty());
+        assert_eq!(jobs[1].tags, vec!["system", "nightly", "job:photos"]);
+        assert_eq!(jobs[1].forget_tags, vec!["system", "nightly"]);
+    }
+
+    #[test]
+    fn forget_filters() {
+        let config: Config = toml::from_str(
+            r#"
+source = "/"
+forget-tags = ["auto"]
+forget-paths = ["/", "/boot"]
+
+[[jobs]]
+name = "photos"
+source = "/photos"
+repositories = []
+forget-tags = ["auto", "photos"]
+
+[[jobs]]
+name = "home"
+source = "/home"
+repositories = []
+"#,
+        )
+        .unwrap();
+        let jobs = config.jobs();
+        assert_eq!(jobs[0].forget_tags, vec!["auto"]);
+        assert_eq!(jobs[0].forget_paths(), vec!["/", "/boot"]);
+        assert_eq!(jobs[1].forget_tags, vec!["auto", "photos"]);
+        assert_eq!(jobs[1].forget_paths(), vec!["/photos"]);
+        assert_eq!(jobs[2].forget_tags, vec!["auto"]);
+    }
+
+    #[test]
+    fn exclude_if_present() {
+        let config: Config = toml::from_str(
+            r#"
+source = "/"
+exclude-if-present = [".nobackup"]
+
+[[jobs]]
+name = "home"
+source = "/home"
+repositories = []
+exclude-if-present = [".cache-only"]
+"#,
+        )
+        .unwrap();
+        let jobs = config.jobs();
+        assert_eq!(jobs[0].exclude_if_present, vec![".nobackup"]);
+        assert_eq!(jobs[1].exclude_if_present, vec![".nobackup", ".cache-only"]);
+    }
+
+    #[test]
+    fn iexclude() {
+        let config: Config = toml::from_str(
+            r#"
+source = "/"
+exclude = ["*~"]
+iexclude = ["*.iso"]
+"#,
+        )
+        .unwrap();
+        let jobs = config.jobs();
+        assert_eq!(jobs[0].exclude, vec!["*~"]);
+        assert_eq!(jobs[0].iexclude, vec!["*.iso"]);
+    }
+
+    #[test]
+    fn exclude_larger_than() {
+        let config: Config = toml::from_str(
+            r#"
+source = "/"
+exclude-larger-than = "1G"
+
+[[job]]
+name = "vms"
+source = "/var/lib/libvirt"
+repositories = ["offsite"]
+exclude-larger-than = "20G"
+"#,
+        )
+        .unwrap();
+        let jobs = config.jobs();
+        assert_eq!(jobs[0].exclude_larger_than.unwrap().bytes(), 1 << 30);
+        assert_eq!(jobs[1].exclude_larger_than.unwrap().bytes(), 20 << 30);
+        let error = toml::from_str::<Config>(r#"exclude-larger-than = "1GB""#).unwrap_err();
+        assert!(
+            error.to_string().contains("invalid size \"1GB\""),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn one_file_system() {
+        let config: Config = toml::from_str(
+            r#"
+source = "/"
+one-file-system = true
+
+[[job]]
+name = "home"
+source = "/home"
+repositories = ["nas"]
+"#,
+        )
+        .unwrap();
+        assert!(config.jobs().iter().all(|job| job.one_file_system));
+        let config: Config = toml::from_str(
+            r#"
+source = "/"
+
+[[job]]
+name = "home"
+source = "/home"
+repositories = ["nas"]
+one-file-system = true
+"#,
+        )
+        .unwrap();
+        let jobs = config.jobs();
+        assert!(!jobs[0].one_file_system);
+        assert!(jobs[1].one_file_system);
+    }
+
+    #[test]
+    fn files_from() {
+        let config: Config = toml::from_str(
+            r#"
+files-from = ["/etc/restic/include.txt"]
+
+[[job]]
+name = "home"
+files-from-verbatim = ["/var/lib/restic/home.list"]
+repositories = ["nas"]
+"#,
+        )
+        .unwrap();
+        let jobs = config.jobs();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].files_from, ["/etc/restic/include.txt"]);
+        assert!(jobs[1].source.is_empty());
+        assert_eq!(
+            jobs[1].description(),
+            "files from /var/lib/restic/home.list"
+        );
+    }
+
+    #[test]
+    fn read_concurrency() {
+        let config: Config = toml::from_str(
+            r#"
+source = "/"
+read-concurrency = 8
+
+[[job]]
+name = "archive"
+source = "/mnt/hdd"
+repositories = ["nas"]
+read-concurrency = 2
+"#,
+        )
+        .unwrap();
+        let jobs = config.jobs();
+        assert_eq!(jobs[0].read_concurrency, Some(8));
+        assert_eq!(jobs[1].read_concurrency, Some(2));
+    }
+
+    #[test]
+    fn no_scan() {
+        let config: Config = toml::from_str(
+            r#"
+source = "/"
+
+[[job]]
+name = "mail"
+source = "/var/mail"
+repositories = ["nas"]
+no-scan = true
+"#,
+        )
+        .unwrap();
+        let jobs = config.jobs();
+        assert!(!jobs[0].no_scan);
+        assert!(jobs[1].no_scan);
+    }
+
+    #[test]
+    fn change_detection() {
+        let config: Config = toml::from_str(
+            r#"
+source = "/"
+ignore-ctime = true
+
+[[job]]
+name = "nfs"
+source = "/mnt/nfs"
+repositories = ["nas"]
+ignore-inode = true
+"#,
+        )
+        .unwrap();
+        let jobs = config.jobs();
+        assert!(!jobs[0].ignore_inode && jobs[0].ignore_ctime);
+        assert!(jobs[1].ignore_inode && jobs[1].ignore_ctime);
+    }
+
+    #[test]
+    fn skip_if_unchanged() {
+        let config: Config = toml::from_str(
+            r#"
+source = "/"
+skip-if-unchanged = true
+
+[[job]]
+name = "home"
+source = "/home"
+repositories = ["nas"]
+"#,
+        )
+        .unwrap();
+        assert!(config.jobs().iter().all(|job| job.skip_if_unchanged));
+    }
+
+    #[test]
+    fn command_jobs() {
+        let config: Config = toml::from_str(
+            r#"
+source = "/"
+
+[[job]]
+name = "postgres"
+command = "pg_dumpall"
+stdin-filename = "db.sql"
+repositories = ["nas"]
+
+[[job]]
+name = "mysql"
+command = ["mysqldump", "--all-databases"]
+repositories = ["nas"]
+"#,
+        )
+        .unwrap();
+        let jobs = config.jobs();
+        assert_eq!(jobs[0].snapshot_paths(), vec!["/"]);
+        assert_eq!(jobs[1].command, ["pg_dumpall"]);
+        assert_eq!(jobs[1].snapshot_paths(), vec!["/db.sql"]);
+        assert_eq!(jobs[1].description(), "the output of pg_dumpall");
+        assert_eq!(jobs[2].snapshot_paths(), vec!["/stdin"]);
+    }
+
+    #[test]
+    fn max_unused() {
+        for valid in ["5%", "0%", "12.5%", "unlimited", "1G", "500000"] {
+            assert_eq!(valid.parse::<MaxUnused>().unwrap().to_string(), valid);
+        }
+        for invalid in ["", "101%", "-1%", "5 %", "none", "1GB"] {
+            assert!(invalid.parse::<MaxUnused>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!("1024".parse::<Size>().unwrap().bytes(), 1024);
+        assert_eq!("500k".parse::<Size>().unwrap().bytes(), 500 << 10);
+        assert_eq!("2T".parse::<Size>().unwrap().bytes(), 2 << 40);
+        for invalid in ["", "G", "1GB", "1.5G", "-1M", "99999999999T"] {
+            assert!(invalid.parse::<Size>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn source_list() {
+        let config: Config = toml::from_str(r#"source = ["/home", "/etc"]"#).unwrap();
+        assert_eq!(config.source, vec!["/home", "/etc"]);
+        let config: Config = toml::from_str(r#"source = "/home""#).unwrap();
+        assert_eq!(config.source, vec!["/home"]);
+        assert!(toml::from_str::<Config>("source = 42").is_err());
+    }
+
+    #[test]
+    fn jobs_without_top_level_source() {
+        let config = Config {
+            jobs: vec![JobConfig {
+                name: "home".into(),
+                source: vec!["/home".into()],
+                repositories: vec!["c".into()],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert_eq!(config.jobs().len(), 1);
+        assert_eq!(
+            config.check_jobs().unwrap_err().to_string(),
+            "job home: no repository named c (available: )"
+        );
+    }
+
     #[test]
     fn default_has_no_forget_policy() {
         let repo = RepositoryConfig::default();
@@ -74,6 +3119,43 @@ mod tests {
     test_has_forget_policy!(keep_weekly_has_forget_policy, keep_weekly: 42);
     test_has_forget_policy!(keep_monthly_has_forget_policy, keep_monthly: 42);
     test_has_forget_policy!(keep_yearly_has_forget_policy, keep_yearly: 42);
-    test_has_forget_policy!(keep_tag_has_forget_policy, keep_tag: "important".into());
-    test_has_forget_policy!(keep_within_has_forget_policy, keep_within: "2y5m7d3h".into());
+    test_has_forget_policy!(keep_tag_has_forget_policy, keep_tag: vec!["important".into()]);
+
+    #[test]
+    fn keep_tags() {
+        let repository = |keep_tag: &str| {
+            let toml = format!("name = \"a\"\nlocation = \"/a\"\nkeep-tag = {}\n", keep_tag);
+            toml::from_str::<RepositoryConfig>(&toml).unwrap().keep_tag
+        };
+        assert_eq!(repository(r#""important""#), Some(vec!["important".into()]));
+        assert_eq!(
+            repository(r#"["pre-upgrade", "legal-hold"]"#),
+            Some(vec!["pre-upgrade".into(), "legal-hold".into()])
+        );
+    }
+    test_has_forget_policy!(keep_within_has_forget_policy, keep_within: "2y5m7d3h".parse().unwrap());
+    test_has_forget_policy!(keep_within_hourly_has_forget_policy, keep_within_hourly: "7d".parse().unwrap());
+    test_has_forget_policy!(keep_within_daily_has_forget_policy, keep_within_daily: "7d".parse().unwrap());
+    test_has_forget_policy!(keep_within_weekly_has_forget_policy, keep_within_weekly: "7d".parse().unwrap());
+    test_has_forget_policy!(keep_within_monthly_has_forget_policy, keep_within_monthly: "7d".parse().unwrap());
+    test_has_forget_policy!(keep_within_yearly_has_forget_policy, keep_within_yearly: "7d".parse().unwrap());
+
+    #[test]
+    fn group_by_is_no_forget_policy() {
+        let repo = RepositoryConfig {
+            group_by: Some("host".parse().unwrap()),
+            ..RepositoryConfig::default()
+        };
+        assert!(!repo.has_forget_policy());
+    }
+
+    #[test]
+    fn group_by() {
+        for valid in ["", "host", "host,paths,tags", "tags"] {
+            assert_eq!(valid.parse::<GroupBy>().unwrap().to_string(), valid);
+        }
+        for invalid in ["hosts", "host,", "host paths", "path"] {
+            assert!(invalid.parse::<GroupBy>().is_err(), "{}", invalid);
+        }
+    }
 }
@@ -0,0 +1,235 @@
+use std::io::Write;
+
+use crate::config::Config;
+use crate::environment::shell_quote;
+use crate::units::{self, Context, PlannedUnit, UnitKind};
+
+/// The name of the file rendered with `--format crontab`
+pub const FILE_NAME: &str = "restic-generator.crontab";
+
+/// The systemd calendar shorthands cron also knows
+const SHORTHANDS: &[(&str, &str)] = &[
+    ("hourly", "@hourly"),
+    ("daily", "@daily"),
+    ("weekly", "@weekly"),
+    ("monthly", "@monthly"),
+    ("yearly", "@yearly"),
+    ("annually", "@yearly"),
+];
+
+/// When the unit runs, as the crontab shorthand of its calendar
+pub fn schedule(planned: &PlannedUnit) -> anyhow::Result<&'static str> {
+    if planned.kind == UnitKind::Init {
+        return Ok("@reboot");
+    }
+    let calendar = planned.calendar();
+    let value = calendar.to_string().to_lowercase();
+    let shorthand = SHORTHANDS.iter().find(|(systemd, _)| *systemd == value);
+    let (_, schedule) = shorthand.ok_or_else(|| {
+        anyhow::anyhow!(
+            "{}: calendar {:?} has no cron or launchd equivalent \
+             (use hourly, daily, weekly, monthly or yearly)",
+            planned.name.trim_end_matches(".service"),
+            calendar.to_string()
+        )
+    })?;
+    Ok(schedule)
+}
+
+/// Write a crontab (as installed with `crontab <file>`) with one entry per
+/// generated unit, running the same commands.
+pub fn write(out: &mut impl Write, context: &Context, config: &Config) -> anyhow::Result<()> {
+    writeln!(
+        out,
+        "# generated by {} from {}",
+        context.program_name,
+        context.config_path.display()
+    )?;
+    let plan = units::plan(context, config);
+    for planned in plan.iter() {
+        // The units without a timer only run by hand, except init at boot
+        let scheduled = planned.has_timer() || planned.kind == UnitKind::Init;
+        if planned.skipped.is_some() || !scheduled || planned.kind.is_systemd_only() {
+            continue;
+        }
+        let exports: Vec<_> = planned
+            .environment()
+            .into_iter()
+            .map(|(name, value)| format!("{}={}", name, shell_quote(&value)))
+            .collect();
+        // The chained forget and prune only run after a successful backup
+        let commands: Vec<_> = units::chained_commands(context, &plan, planned)
+            .iter()
+            .map(|command| format!("nice -n 10 {}", command.to_shell()))
+            .collect();
+        let line = format!(
+            "{} export {}; {}",
+            schedule(planned)?,
+            exports.join(" "),
+            commands.join(" && ")
+        );
+        writeln!(out)?;
+        writeln!(out, "# {}", planned.name.trim_end_matches(".service"))?;
+        // cron turns unescaped % into newlines
+        writeln!(out, "{}", line.replace('%', "\\%"))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CopyConfig, Maintenance, Pruning, RepositoryConfig, Schedule};
+    use crate::units::tests::context;
+
+    #[test]
+    fn one_entry_per_unit() {
+        let context = context();
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "/repo".into(),
+                password_command: Some("pass show restic".into()),
+                keep_tag: Some(vec!["1%".into()]),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        write(&mut out, &context, &config).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            r#"# generated by restic-generator from config.toml
+
+# restic-myrepo-backup
+@daily export RESTIC_REPOSITORY=/repo RESTIC_PASSWORD_COMMAND='pass show restic'; nice -n 10 restic unlock && nice -n 10 restic backup --host=laptop /
+
+# restic-myrepo-forget
+@weekly export RESTIC_REPOSITORY=/repo RESTIC_PASSWORD_COMMAND='pass show restic'; nice -n 10 restic unlock && nice -n 10 restic forget --host=laptop --path=/ --keep-tag=1\%
+
+# restic-myrepo-prune
+@monthly export RESTIC_REPOSITORY=/repo RESTIC_PASSWORD_COMMAND='pass show restic'; nice -n 10 restic unlock && nice -n 10 restic prune
+"#
+        );
+    }
+
+    #[test]
+    fn prune_cadence() {
+        let repository = |name: &str, prune| RepositoryConfig {
+            name: name.into(),
+            location: format!("/{}", name),
+            keep_daily: Some(7),
+            prune,
+            prune_calendar: Some("yearly".parse().unwrap()),
+            ..Default::default()
+        };
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![
+                repository("timer", Pruning::Timer),
+                repository("manual", Pruning::Manual),
+            ],
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        write(&mut out, &context(), &config).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("# restic-timer-prune\n@yearly "));
+        assert!(!out.contains("restic-manual-prune"));
+    }
+
+    #[test]
+    fn manual_schedule() {
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![RepositoryConfig {
+                name: "archive".into(),
+                location: "/archive".into(),
+                keep_daily: Some(7),
+                schedule: Schedule::Manual,
+                init: Some(true),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        write(&mut out, &context(), &config).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let entries: Vec<_> = out
+            .lines()
+            .filter(|line| line.starts_with("# restic-"))
+            .collect();
+        assert_eq!(entries, vec!["# restic-archive-init"]);
+        assert!(out.contains("\n@reboot "));
+    }
+
+    #[test]
+    fn chained_maintenance() {
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "/repo".into(),
+                keep_daily: Some(7),
+                maintenance: Maintenance::Chained,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        write(&mut out, &context(), &config).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            r#"# generated by restic-generator from config.toml
+
+# restic-myrepo-backup
+@daily export RESTIC_REPOSITORY=/repo; nice -n 10 restic unlock && nice -n 10 restic backup --host=laptop / && nice -n 10 restic unlock && nice -n 10 restic forget --host=laptop --path=/ --keep-daily=7 && nice -n 10 restic unlock && nice -n 10 restic prune
+"#
+        );
+    }
+
+    #[test]
+    fn calendars() {
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![
+                RepositoryConfig {
+                    name: "disk".into(),
+                    location: "/disk".into(),
+                    backup_calendar: Some("hourly".parse().unwrap()),
+                    ..Default::default()
+                },
+                RepositoryConfig {
+                    name: "b2".into(),
+                    location: "b2:bucket".into(),
+                    ..Default::default()
+                },
+            ],
+            copies: vec![CopyConfig {
+                from: "disk".into(),
+                to: "b2".into(),
+                calendar: Some("Weekly".parse().unwrap()),
+            }],
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        write(&mut out, &context(), &config).unwrap();
+        let schedules: Vec<_> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .filter(|line| line.starts_with('@'))
+            .map(|line| line.split(' ').next().unwrap().to_string())
+            .collect();
+        assert_eq!(schedules, vec!["@hourly", "@daily", "@weekly"]);
+
+        let mut config = config;
+        config.repositories[0].backup_calendar = Some("Mon..Fri 18:00".parse().unwrap());
+        let error = write(&mut Vec::new(), &context(), &config).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "restic-disk-backup: calendar \"Mon..Fri 18:00\" has no cron or launchd equivalent \
+             (use hourly, daily, weekly, monthly or yearly)"
+        );
+    }
+}
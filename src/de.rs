@@ -0,0 +1,243 @@
+//! Deserialization from a `toml::Value` that remembers where errors happen,
+//! so that they can be reported as e.g. `repositories[1].keep-daily`.
+
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor};
+use std::{fmt, vec};
+use toml::Value;
+
+#[derive(Debug)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+#[derive(Debug)]
+pub struct Error {
+    path: Vec<Segment>,
+    message: String,
+}
+
+impl Error {
+    /// The path of the key the error is about, empty for the whole document
+    pub fn path(&self) -> String {
+        let mut path = String::new();
+        for segment in self.path.iter() {
+            match segment {
+                Segment::Key(key) if path.is_empty() => path.push_str(key),
+                Segment::Key(key) => {
+                    path.push('.');
+                    path.push_str(key);
+                }
+                Segment::Index(index) => path.push_str(&format!("[{}]", index)),
+            }
+        }
+        path
+    }
+
+    /// The last key of the path, if any
+    pub fn key(&self) -> Option<&str> {
+        self.path.iter().rev().find_map(|segment| match segment {
+            Segment::Key(key) => Some(key.as_str()),
+            Segment::Index(_) => None,
+        })
+    }
+
+    /// The index of the element of the top-level `key` array the error is
+    /// in, e.g. 1 for `repositories[1].keep-daily`
+    pub fn index_in(&self, key: &str) -> Option<usize> {
+        match &self.path[..] {
+            [Segment::Key(first), Segment::Index(index), ..] if first == key => Some(*index),
+            _ => None,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    fn within(mut self, segment: Segment) -> Error {
+        self.path.insert(0, segment);
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path(), self.message)
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(message: T) -> Error {
+        Error {
+            path: Vec::new(),
+            message: message.to_string(),
+        }
+    }
+}
+
+pub fn from_value<T: DeserializeOwned>(value: Value) -> Result<T, Error> {
+    T::deserialize(Deserializer(value))
+}
+
+struct Deserializer(Value);
+
+impl<'de> de::Deserializer<'de> for Deserializer {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            Value::String(value) => visitor.visit_string(value),
+            Value::Integer(value) => visitor.visit_i64(value),
+            Value::Float(value) => visitor.visit_f64(value),
+            Value::Boolean(value) => visitor.visit_bool(value),
+            Value::Datetime(value) => visitor.visit_string(value.to_string()),
+            Value::Array(values) => visitor.visit_seq(SeqAccess(values.into_iter().enumerate())),
+            Value::Table(table) => visitor.visit_map(MapAccess {
+                entries: table.into_iter().collect::<Vec<_>>().into_iter(),
+                next: None,
+            }),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.0 {
+            Value::String(variant) => visitor.visit_enum(variant.into_deserializer()),
+            value => Deserializer(value).deserialize_any(visitor),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+struct SeqAccess(std::iter::Enumerate<vec::IntoIter<Value>>);
+
+impl<'de> de::SeqAccess<'de> for SeqAccess {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        match self.0.next() {
+            None => Ok(None),
+            Some((index, value)) => seed
+                .deserialize(Deserializer(value))
+                .map(Some)
+                .map_err(|err| err.within(Segment::Index(index))),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+struct MapAccess {
+    entries: vec::IntoIter<(String, Value)>,
+    /// The entry whose key was just deserialized
+    next: Option<(String, Value)>,
+}
+
+impl<'de> de::MapAccess<'de> for MapAccess {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        let Some((key, value)) = self.entries.next() else {
+            return Ok(None);
+        };
+        let result = seed
+            .deserialize(key.clone().into_deserializer())
+            .map_err(|err: Error| err.within(Segment::Key(key.clone())));
+        self.next = Some((key, value));
+        result.map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let (key, value) = self
+            .next
+            .take()
+            .ok_or_else(|| de::Error::custom("value requested before its key"))?;
+        seed.deserialize(Deserializer(value))
+            .map_err(|err| err.within(Segment::Key(key)))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    #[serde(rename_all = "kebab-case", deny_unknown_fields)]
+    struct Repository {
+        keep_daily: Option<usize>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct Config {
+        #[serde(default)]
+        repositories: Vec<Repository>,
+    }
+
+    fn error(source: &str) -> Error {
+        from_value::<Config>(toml::from_str(source).unwrap()).unwrap_err()
+    }
+
+    #[test]
+    fn paths() {
+        let err = error("[[repositories]]\n[[repositories]]\nkeep-daily = \"seven\"\n");
+        assert_eq!(err.path(), "repositories[1].keep-daily");
+        assert_eq!(err.key(), Some("keep-daily"));
+        assert_eq!(
+            err.to_string(),
+            "repositories[1].keep-daily: invalid type: string \"seven\", expected usize"
+        );
+        let err = error("[[repositories]]\nkeep_daily = 7\n");
+        assert_eq!(err.path(), "repositories[0].keep_daily");
+        assert!(err.message().starts_with("unknown field `keep_daily`"));
+        assert_eq!(error("repositories = 3\n").path(), "repositories");
+    }
+
+    #[test]
+    fn values() {
+        let config: Config =
+            from_value(toml::from_str("[[repositories]]\nkeep-daily = 7\n").unwrap()).unwrap();
+        assert_eq!(config.repositories[0].keep_daily, Some(7));
+    }
+}
@@ -0,0 +1,195 @@
+use anyhow::Context as _;
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::units::Unit;
+
+/// How a file differs between a directory and the rendered units
+#[derive(Debug, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Removed,
+    /// The lines of the old and new versions, marked with ' ', '-' or '+'
+    Changed(Vec<(char, String)>),
+    Unchanged,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct FileDiff {
+    pub name: String,
+    pub change: Change,
+}
+
+/// The directory where systemd runs the generators
+pub fn generator_dir(user: bool) -> PathBuf {
+    if !user {
+        return PathBuf::from("/run/systemd/generator");
+    }
+    let runtime_dir = env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(format!("/run/user/{}", unsafe { libc::getuid() })));
+    runtime_dir.join("systemd/generator")
+}
+
+/// Compare the units with the files in `dir`. Files in `dir` that are not
+/// rendered anymore are reported as removed if they were generated by
+/// `program_name`.
+pub fn compare(dir: &Path, units: &[Unit], program_name: &str) -> anyhow::Result<Vec<FileDiff>> {
+    let mut diffs = Vec::new();
+    for unit in units {
+        let path = dir.join(&unit.name);
+        let change = match fs::read(&path) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Change::Added,
+            Err(err) => {
+                return Err(err).with_context(|| format!("{}: error reading file", path.display()))
+            }
+            Ok(old) if old == unit.content => Change::Unchanged,
+            Ok(old) => Change::Changed(diff_lines(
+                &String::from_utf8_lossy(&old),
+                &String::from_utf8_lossy(&unit.content),
+            )),
+        };
+        diffs.push(FileDiff {
+            name: unit.name.clone(),
+            change,
+        });
+    }
+    let entries = match fs::read_dir(dir) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(diffs),
+        result => result.with_context(|| format!("{}: error reading directory", dir.display()))?,
+    };
+    let header = format!("# generated by {}", program_name);
+    let mut removed = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if units.iter().any(|unit| unit.name == name) {
+            continue;
+        }
+        let content = fs::read_to_string(entry.path()).unwrap_or_default();
+        if content.lines().next() == Some(header.as_str()) {
+            removed.push(name);
+        }
+    }
+    removed.sort();
+    diffs.extend(removed.into_iter().map(|name| FileDiff {
+        name,
+        change: Change::Removed,
+    }));
+    Ok(diffs)
+}
+
+/// A line diff of `old` and `new`, from their longest common subsequence
+fn diff_lines(old: &str, new: &str) -> Vec<(char, String)> {
+    let old: Vec<_> = old.lines().collect();
+    let new: Vec<_> = new.lines().collect();
+    // common[i][j] is the length of the LCS of old[i..] and new[j..]
+    let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push((' ', old[i].to_string()));
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && common[i + 1][j] >= common[i][j + 1]) {
+            lines.push(('-', old[i].to_string()));
+            i += 1;
+        } else {
+            lines.push(('+', new[j].to_string()));
+            j += 1;
+        }
+    }
+    lines
+}
+
+pub fn print(out: &mut impl Write, diffs: &[FileDiff]) -> anyhow::Result<()> {
+    let mut unchanged = 0;
+    for diff in diffs {
+        match &diff.change {
+            Change::Added => writeln!(out, "added {}", diff.name)?,
+            Change::Removed => writeln!(out, "removed {}", diff.name)?,
+            Change::Changed(lines) => {
+                writeln!(out, "changed {}", diff.name)?;
+                for (mark, line) in lines {
+                    writeln!(out, "{}{}", mark, line)?;
+                }
+            }
+            Change::Unchanged => unchanged += 1,
+        }
+    }
+    writeln!(out, "{} file(s) unchanged", unchanged)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit(name: &str, content: &str) -> Unit {
+        Unit {
+            name: name.into(),
+            content: content.as_bytes().to_vec(),
+            executable: false,
+        }
+    }
+
+    #[test]
+    fn lines() {
+        assert_eq!(
+            diff_lines("a\nb\nc\n", "a\nB\nc\nd\n"),
+            vec![
+                (' ', "a".to_string()),
+                ('-', "b".to_string()),
+                ('+', "B".to_string()),
+                (' ', "c".to_string()),
+                ('+', "d".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn compare_dir() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let header = "# generated by restic-generator\n";
+        fs::write(dir.path().join("same.service"), header).unwrap();
+        fs::write(
+            dir.path().join("changed.service"),
+            format!("{}old\n", header),
+        )
+        .unwrap();
+        fs::write(dir.path().join("gone.service"), header).unwrap();
+        fs::write(dir.path().join("other.service"), "[Unit]\n").unwrap();
+        let units = [
+            unit("same.service", header),
+            unit("changed.service", &format!("{}new\n", header)),
+            unit("new.service", header),
+        ];
+        let diffs = compare(dir.path(), &units, "restic-generator").unwrap();
+        let mut out = Vec::new();
+        print(&mut out, &diffs).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "changed changed.service
+ # generated by restic-generator
+-old
++new
+added new.service
+removed gone.service
+1 file(s) unchanged
+"
+        );
+    }
+}
@@ -0,0 +1,237 @@
+use std::{
+    fmt,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use crate::config::{Config, RepositoryConfig};
+use crate::restic;
+use crate::units::is_local_repository;
+
+/// The oldest restic release supporting all the flags used in the generated units
+pub const MIN_RESTIC_VERSION: (u32, u32, u32) = (0, 9, 0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Status {
+    Ok,
+    Warning,
+    Error,
+}
+
+/// The outcome of one check
+#[derive(Debug, PartialEq, Eq)]
+pub struct Check {
+    pub status: Status,
+    pub message: String,
+}
+
+impl Check {
+    fn new(status: Status, message: impl Into<String>) -> Check {
+        Check {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self.status {
+            Status::Ok => "ok",
+            Status::Warning => "warning",
+            Status::Error => "error",
+        };
+        write!(f, "[{}] {}", label, self.message)
+    }
+}
+
+/// Check that the environment can run the units generated from `config` on
+/// the machine named `hostname`. If `check_credentials` is set, also try to
+/// open every repository.
+pub fn run(config: &Config, hostname: &str, check_credentials: bool) -> Vec<Check> {
+    let mut checks = vec![check_restic()];
+    let repositories = config.repositories.iter();
+    for repository in repositories.filter(|r| r.is_enabled() && r.runs_on(hostname)) {
+        checks.extend(check_repository(repository, check_credentials));
+    }
+    checks
+}
+
+/// The exit code summarizing the checks: 0 if everything is fine, 2 if there
+/// are only warnings, 1 if at least one check failed.
+pub fn exit_code(checks: &[Check]) -> i32 {
+    match checks.iter().map(|check| check.status).max() {
+        None | Some(Status::Ok) => 0,
+        Some(Status::Warning) => 2,
+        Some(Status::Error) => 1,
+    }
+}
+
+fn check_restic() -> Check {
+    let output = match Command::new("restic").arg("version").output() {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            return Check::new(
+                Status::Error,
+                format!("restic version failed: {}", output.status),
+            )
+        }
+        Err(err) => return Check::new(Status::Error, format!("restic not found: {}", err)),
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match parse_restic_version(&stdout) {
+        Some(version) if version >= MIN_RESTIC_VERSION => {
+            Check::new(Status::Ok, format!("restic {}", format_version(version)))
+        }
+        Some(version) => Check::new(
+            Status::Error,
+            format!(
+                "restic {} is too old (need at least {})",
+                format_version(version),
+                format_version(MIN_RESTIC_VERSION)
+            ),
+        ),
+        None => Check::new(
+            Status::Warning,
+            format!("cannot parse restic version from {:?}", stdout.trim()),
+        ),
+    }
+}
+
+/// Parse the output of `restic version`, e.g. `restic 0.16.4 compiled with go1.21.6 on linux/amd64`
+fn parse_restic_version(output: &str) -> Option<(u32, u32, u32)> {
+    let version = output.split_whitespace().nth(1)?;
+    let mut parts = version.split(['.', '-']).map(|part| part.parse::<u32>());
+    let major = parts.next()?.ok()?;
+    let minor = parts.next()?.ok()?;
+    let patch = parts.next().and_then(|part| part.ok()).unwrap_or(0);
+    Some((major, minor, patch))
+}
+
+fn format_version((major, minor, patch): (u32, u32, u32)) -> String {
+    format!("{}.{}.{}", major, minor, patch)
+}
+
+fn check_repository(repository: &RepositoryConfig, check_credentials: bool) -> Vec<Check> {
+    let name = &repository.name;
+    let mut checks = Vec::new();
+    if let Some(password_file) = &repository.password_file {
+        let check = check_password_file(&expand_home(password_file));
+        checks.push(Check::new(
+            check.status,
+            format!("{}: password file {}", name, check.message),
+        ));
+    }
+    if is_local_repository(&repository.location) {
+        let path = expand_home(&repository.location);
+        checks.push(if path.is_dir() {
+            Check::new(Status::Ok, format!("{}: {} exists", name, path.display()))
+        } else {
+            Check::new(
+                Status::Error,
+                format!("{}: {} does not exist", name, path.display()),
+            )
+        });
+    }
+    if check_credentials {
+        checks.push(check_repository_access(repository));
+    }
+    checks
+}
+
+fn check_password_file(path: &Path) -> Check {
+    match path.metadata() {
+        Err(err) => Check::new(Status::Error, format!("{}: {}", path.display(), err)),
+        Ok(metadata) if metadata.permissions().mode() & 0o004 != 0 => Check::new(
+            Status::Warning,
+            format!("{} is world-readable", path.display()),
+        ),
+        Ok(_) => Check::new(Status::Ok, format!("{} is private", path.display())),
+    }
+}
+
+fn check_repository_access(repository: &RepositoryConfig) -> Check {
+    let result = Command::new("restic")
+        .args(["cat", "config"])
+        .args(restic::option_args(repository))
+        .envs(restic::environment(repository))
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output();
+    match result {
+        Ok(output) if output.status.success() => Check::new(
+            Status::Ok,
+            format!("{}: repository can be opened", repository.name),
+        ),
+        Ok(output) => Check::new(
+            Status::Error,
+            format!(
+                "{}: cannot open repository: {}",
+                repository.name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ),
+        Err(err) => Check::new(
+            Status::Error,
+            format!("{}: cannot run restic: {}", repository.name, err),
+        ),
+    }
+}
+
+/// Expand the `%h` systemd specifier so that paths can be checked from a shell
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("%h"), home::home_dir()) {
+        (Some(rest), Some(home)) => PathBuf::from(format!("{}{}", home.display(), rest)),
+        _ => PathBuf::from(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn restic_version() {
+        assert_eq!(
+            parse_restic_version("restic 0.16.4 compiled with go1.21.6 on linux/amd64"),
+            Some((0, 16, 4))
+        );
+        assert_eq!(
+            parse_restic_version("restic 0.17.0-dev (compiled manually)"),
+            Some((0, 17, 0))
+        );
+        assert_eq!(parse_restic_version("restic 0.9"), Some((0, 9, 0)));
+        assert_eq!(parse_restic_version("restic"), None);
+        assert_eq!(parse_restic_version("restic unknown"), None);
+    }
+
+    #[test]
+    fn exit_codes() {
+        let ok = || Check::new(Status::Ok, "");
+        let warning = || Check::new(Status::Warning, "");
+        let error = || Check::new(Status::Error, "");
+        assert_eq!(exit_code(&[]), 0);
+        assert_eq!(exit_code(&[ok(), ok()]), 0);
+        assert_eq!(exit_code(&[ok(), warning()]), 2);
+        assert_eq!(exit_code(&[error(), warning()]), 1);
+    }
+
+    #[test]
+    fn password_file_permissions() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("password");
+        fs::write(&path, "s3cr3t").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(check_password_file(&path).status, Status::Ok);
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(check_password_file(&path).status, Status::Warning);
+    }
+
+    #[test]
+    fn missing_password_file() {
+        let check = check_password_file(Path::new("/does/not/exist"));
+        assert_eq!(check.status, Status::Error);
+    }
+}
@@ -0,0 +1,133 @@
+use std::io::Write;
+
+use crate::config::RepositoryConfig;
+use crate::restic;
+use crate::units;
+
+/// How to print the repository environment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `export` lines to be evaluated by a POSIX shell
+    Shell,
+    /// `Environment=` lines, as in the generated units
+    Systemd,
+    /// A file for `EnvironmentFile=`, docker or dotenv loaders
+    Dotenv,
+}
+
+impl std::str::FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Format> {
+        match s {
+            "shell" => Ok(Format::Shell),
+            "systemd" => Ok(Format::Systemd),
+            "dotenv" => Ok(Format::Dotenv),
+            _ => anyhow::bail!("unknown format {} (expected shell, systemd or dotenv)", s),
+        }
+    }
+}
+
+pub fn print(
+    out: &mut impl Write,
+    repository: &RepositoryConfig,
+    format: Format,
+) -> anyhow::Result<()> {
+    if format == Format::Systemd {
+        return units::write_environment(out, repository);
+    }
+    for (name, value) in restic::environment(repository) {
+        match format {
+            Format::Shell => writeln!(out, "export {}={}", name, shell_quote(&value))?,
+            Format::Dotenv => writeln!(out, "{}={}", name, dotenv_quote(&value))?,
+            Format::Systemd => unreachable!(),
+        }
+    }
+    Ok(())
+}
+
+/// Quote a string for a POSIX shell
+pub fn shell_quote(value: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "%+,-./:=@_".contains(c);
+    if !value.is_empty() && value.chars().all(safe) {
+        return value.to_string();
+    }
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+fn dotenv_quote(value: &str) -> String {
+    let mut result = String::from("\"");
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            result.push('\\');
+        }
+        result.push(c);
+    }
+    result.push('"');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repository() -> RepositoryConfig {
+        RepositoryConfig {
+            name: "myrepo".into(),
+            location: "/my/repo".into(),
+            password_command: Some("pass show 'restic'".into()),
+            ..Default::default()
+        }
+    }
+
+    fn print_str(format: Format) -> String {
+        let mut out = Vec::new();
+        print(&mut out, &repository(), format).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn shell() {
+        assert_eq!(
+            print_str(Format::Shell),
+            r#"export RESTIC_REPOSITORY=/my/repo
+export RESTIC_PASSWORD_COMMAND='pass show '\''restic'\'''
+"#
+        );
+    }
+
+    #[test]
+    fn systemd() {
+        assert_eq!(
+            print_str(Format::Systemd),
+            r#"Environment=RESTIC_REPOSITORY="/my/repo"
+Environment=RESTIC_PASSWORD_COMMAND="pass show 'restic'"
+"#
+        );
+    }
+
+    #[test]
+    fn dotenv() {
+        assert_eq!(
+            print_str(Format::Dotenv),
+            r#"RESTIC_REPOSITORY="/my/repo"
+RESTIC_PASSWORD_COMMAND="pass show 'restic'"
+"#
+        );
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(shell_quote("s3:host/bucket"), "s3:host/bucket");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(dotenv_quote(r#"a"$b"#), r#""a\"\$b""#);
+    }
+
+    #[test]
+    fn parse_format() {
+        assert_eq!("shell".parse::<Format>().unwrap(), Format::Shell);
+        assert_eq!("dotenv".parse::<Format>().unwrap(), Format::Dotenv);
+        assert!("fish".parse::<Format>().is_err());
+    }
+}
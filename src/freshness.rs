@@ -0,0 +1,184 @@
+use anyhow::Context as _;
+use serde::Deserialize;
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+    time::SystemTime,
+};
+
+use crate::config::TimeSpan;
+use crate::restic::{self, Access};
+
+/// A snapshot, as listed by `restic snapshots --json`
+#[derive(Debug, Deserialize)]
+pub struct Snapshot {
+    pub time: String,
+    pub short_id: String,
+}
+
+/// Fail if the latest snapshot made by one of the `hosts` in the repository
+/// named `name` is older than `max_age`
+pub fn run(
+    out: &mut impl Write,
+    name: &str,
+    access: &Access,
+    max_age: &TimeSpan,
+    hosts: &[String],
+) -> anyhow::Result<()> {
+    let mut stale = Vec::new();
+    for host in hosts.iter() {
+        // Report every stale host, not only the first one
+        if let Err(error) = check(out, name, access, host, max_age) {
+            stale.push(error.to_string());
+        }
+    }
+    if !stale.is_empty() {
+        anyhow::bail!("{}", stale.join("\n"));
+    }
+    Ok(())
+}
+
+/// Fail if the latest snapshot made by `host` in the repository named `name`
+/// is older than `max_age`
+fn check(
+    out: &mut impl Write,
+    name: &str,
+    access: &Access,
+    host: &str,
+    max_age: &TimeSpan,
+) -> anyhow::Result<()> {
+    let output = Command::new("restic")
+        .args(restic::latest_snapshots_cmd::<&str>(host, &[], 1).args())
+        .args(access.option_args())
+        .envs(access.environment.clone())
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| "error running restic")?;
+    if !output.status.success() {
+        anyhow::bail!("restic snapshots failed ({})", output.status);
+    }
+    let snapshots: Vec<Snapshot> = serde_json::from_slice(&output.stdout)
+        .with_context(|| "unexpected output of restic snapshots")?;
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_secs() as i64;
+    let mut latest = None;
+    for snapshot in snapshots.iter() {
+        let time = parse_time(&snapshot.time)
+            .ok_or_else(|| anyhow::anyhow!("invalid snapshot time {:?}", snapshot.time))?;
+        if latest.is_none_or(|(latest, _)| time > latest) {
+            latest = Some((time, snapshot));
+        }
+    }
+    let Some((time, snapshot)) = latest else {
+        anyhow::bail!("repository {} has no snapshots from {}", name, host);
+    };
+    let age = now - time;
+    if age as f64 > max_age.seconds() {
+        anyhow::bail!(
+            "the latest snapshot of {} in repository {} ({}) is {} old, more than {}",
+            host,
+            name,
+            snapshot.short_id,
+            format_age(age),
+            max_age
+        );
+    }
+    writeln!(
+        out,
+        "the latest snapshot of {} in repository {} ({}) is {} old",
+        host,
+        name,
+        snapshot.short_id,
+        format_age(age)
+    )?;
+    Ok(())
+}
+
+/// The seconds since the epoch of an RFC 3339 time, as printed by restic,
+/// e.g. `2023-05-01T12:34:56.123456789+02:00`
+pub fn parse_time(value: &str) -> Option<i64> {
+    let number = |range: std::ops::Range<usize>| value.get(range)?.parse::<i64>().ok();
+    let separators = [(4, b'-'), (7, b'-'), (10, b'T'), (13, b':'), (16, b':')];
+    if separators
+        .iter()
+        .any(|(index, separator)| value.as_bytes().get(*index) != Some(separator))
+    {
+        return None;
+    }
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let rest = value.get(19..)?;
+    // The fraction of a second doesn't matter here
+    let zone = rest.trim_start_matches(|c: char| c == '.' || c.is_ascii_digit());
+    let offset = match zone {
+        "Z" => 0,
+        _ => {
+            let sign = match zone.get(..1)? {
+                "+" => 1,
+                "-" => -1,
+                _ => return None,
+            };
+            let (hours, minutes) = zone.get(1..)?.split_once(':')?;
+            sign * (hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60)
+        }
+    };
+    let days = days_from_civil(year, month, day);
+    Some(days * 86400 + hour * 3600 + minute * 60 + second - offset)
+}
+
+/// The number of days between 1970-01-01 and a date of the proleptic
+/// Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_from_march = (month + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn format_age(seconds: i64) -> String {
+    let (days, hours, minutes) = (seconds / 86400, seconds % 86400 / 3600, seconds % 3600 / 60);
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}min", hours, minutes)
+    } else {
+        format!("{}min", minutes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_restic_times() {
+        assert_eq!(parse_time("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(
+            parse_time("2023-05-01T12:34:56.123456789+02:00"),
+            Some(1682937296)
+        );
+        assert_eq!(parse_time("2000-03-01T00:00:00-01:30"), Some(951874200));
+        for invalid in [
+            "",
+            "2023-05-01",
+            "2023-13-01T00:00:00Z",
+            "2023-05-01T00:00:00",
+        ] {
+            assert_eq!(parse_time(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn formats_ages() {
+        assert_eq!(format_age(3 * 86400 + 7200), "3d 2h");
+        assert_eq!(format_age(5400), "1h 30min");
+        assert_eq!(format_age(59), "0min");
+    }
+}
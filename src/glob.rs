@@ -0,0 +1,101 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The paths matching `pattern`, sorted. `*` and `?` match within a path
+/// component, and not a leading dot. A pattern without wildcards is returned
+/// as is, whether the file exists or not.
+pub fn glob(pattern: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::new()];
+    let mut wildcards = false;
+    for component in pattern.components() {
+        let part = component.as_os_str().to_string_lossy();
+        if !part.contains(['*', '?']) {
+            for path in paths.iter_mut() {
+                path.push(component);
+            }
+            continue;
+        }
+        wildcards = true;
+        let mut matches = Vec::new();
+        for dir in paths {
+            let entries = match fs::read_dir(if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                &dir
+            }) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                if !name.starts_with('.') && matches_pattern(&part, &name) {
+                    matches.push(dir.join(&*name));
+                }
+            }
+        }
+        paths = matches;
+    }
+    if wildcards {
+        paths.retain(|path| path.exists());
+    }
+    paths.sort();
+    paths
+}
+
+/// Whether `name` matches `pattern`, in which `*` matches any characters and
+/// `?` matches one character
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // matches[i][j]: whether pattern[i..] matches name[j..]
+    let mut matches = vec![vec![false; name.len() + 1]; pattern.len() + 1];
+    matches[pattern.len()][name.len()] = true;
+    for i in (0..pattern.len()).rev() {
+        for j in (0..=name.len()).rev() {
+            matches[i][j] = match pattern[i] {
+                '*' => matches[i + 1][j] || (j < name.len() && matches[i][j + 1]),
+                '?' => j < name.len() && matches[i + 1][j + 1],
+                c => j < name.len() && name[j] == c && matches[i + 1][j + 1],
+            };
+        }
+    }
+    matches[0][0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns() {
+        assert!(matches_pattern("*.toml", "repo.toml"));
+        assert!(matches_pattern("*.toml", ".toml"));
+        assert!(matches_pattern("repo-?.toml", "repo-1.toml"));
+        assert!(matches_pattern("a*b*c", "abbbc"));
+        assert!(!matches_pattern("*.toml", "repo.toml~"));
+        assert!(!matches_pattern("repo-?.toml", "repo-10.toml"));
+    }
+
+    #[test]
+    fn glob_files() {
+        let dir = tempfile::TempDir::new().unwrap();
+        for name in ["b.toml", "a.toml", ".hidden.toml", "c.txt"] {
+            fs::write(dir.path().join(name), "").unwrap();
+        }
+        assert_eq!(
+            glob(&dir.path().join("*.toml")),
+            vec![dir.path().join("a.toml"), dir.path().join("b.toml")]
+        );
+        assert_eq!(
+            glob(&dir.path().join("missing.toml")),
+            vec![dir.path().join("missing.toml")]
+        );
+        assert_eq!(
+            glob(&dir.path().join("missing/*.toml")),
+            Vec::<PathBuf>::new()
+        );
+    }
+}
@@ -0,0 +1,497 @@
+use anyhow::Context as _;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::{collections::BTreeMap, io::Write, path::Path};
+
+use crate::config;
+
+/// The tools whose configuration can be imported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tool {
+    Resticprofile,
+    Autorestic,
+}
+
+impl std::str::FromStr for Tool {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Tool> {
+        match s {
+            "resticprofile" => Ok(Tool::Resticprofile),
+            "autorestic" => Ok(Tool::Autorestic),
+            _ => anyhow::bail!("unknown tool {} (expected resticprofile or autorestic)", s),
+        }
+    }
+}
+
+/// The configuration written by `import`, in the order of `config::Config`
+#[derive(Debug, Default, Serialize)]
+struct Imported {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    repositories: Vec<Repository>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    jobs: Vec<Job>,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Repository {
+    name: String,
+    location: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    password_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    password_command: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aws_access_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aws_secret_access_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    azure_account_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    azure_account_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    azure_account_sas: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    backup_calendar: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    forget_calendar: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prune_calendar: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    check: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    check_calendar: Option<String>,
+    /// The keep-* options, in the order they are found
+    #[serde(flatten)]
+    retention: Map<String, Value>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    environment: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Job {
+    name: String,
+    source: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    exclude: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    host: Option<String>,
+    repositories: Vec<String>,
+}
+
+const RETENTION: &[&str] = &[
+    "keep-last",
+    "keep-hourly",
+    "keep-daily",
+    "keep-weekly",
+    "keep-monthly",
+    "keep-yearly",
+    "keep-tag",
+    "keep-within",
+    "keep-within-hourly",
+    "keep-within-daily",
+    "keep-within-weekly",
+    "keep-within-monthly",
+    "keep-within-yearly",
+];
+
+/// Print the configuration converted from the file of `tool` at `path`, and
+/// warn about the settings that are not converted on stderr
+pub fn run(out: &mut impl Write, tool: Tool, path: &Path) -> anyhow::Result<()> {
+    let document: Value = config::parse_file(path)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut warnings = Vec::new();
+    let imported = match tool {
+        Tool::Resticprofile => resticprofile(&document, dir, &mut warnings),
+        Tool::Autorestic => autorestic(&document, &mut warnings),
+    }?;
+    for warning in warnings.iter() {
+        eprintln!("warning: {}", warning);
+    }
+    writeln!(
+        out,
+        "# imported from {} by {}\n",
+        path.display(),
+        env!("CARGO_BIN_NAME")
+    )?;
+    write!(out, "{}", toml::to_string(&imported)?)?;
+    Ok(())
+}
+
+/// Convert the profiles of a resticprofile configuration: each profile
+/// becomes a repository and a job backing up to it
+fn resticprofile(
+    document: &Value,
+    dir: &Path,
+    warnings: &mut Vec<String>,
+) -> anyhow::Result<Imported> {
+    let profiles = document
+        .as_object()
+        .with_context(|| "expected a table of profiles")?;
+    let mut imported = Imported::default();
+    for name in profiles.keys() {
+        if ["global", "groups", "includes", "version"].contains(&name.as_str()) {
+            continue;
+        }
+        let profile = inherited(profiles, name, 0)?;
+        let Some(location) = string(profile.get("repository")) else {
+            warnings.push(format!("profile {}: no repository, skipped", name));
+            continue;
+        };
+        let mut repository = Repository {
+            name: name.clone(),
+            location,
+            password_file: string(profile.get("password-file")).map(|file| {
+                // resticprofile resolves the paths from the configuration directory
+                dir.join(file).to_string_lossy().into_owned()
+            }),
+            ..Default::default()
+        };
+        if let Some(env) = profile.get("env").and_then(Value::as_object) {
+            environment(&mut repository, env, &format!("profile {}", name), warnings);
+        }
+        for section in ["forget", "retention"] {
+            if let Some(options) = profile.get(section).and_then(Value::as_object) {
+                retention(&mut repository, options);
+            }
+        }
+        // resticprofile schedules are systemd calendar expressions
+        let calendars = [
+            ("backup", &mut repository.backup_calendar),
+            ("forget", &mut repository.forget_calendar),
+            ("prune", &mut repository.prune_calendar),
+            ("check", &mut repository.check_calendar),
+        ];
+        for (section, calendar) in calendars {
+            let Some(schedule) = profile.get(section).and_then(|s| s.get("schedule")) else {
+                continue;
+            };
+            match string(Some(schedule)) {
+                Some(schedule) if schedule.parse::<config::Calendar>().is_ok() => {
+                    *calendar = Some(schedule)
+                }
+                _ => warnings.push(format!(
+                    "profile {}: the {} schedule {} is not imported, set {}-calendar",
+                    name, section, schedule, section
+                )),
+            }
+        }
+        if repository.check_calendar.is_some() {
+            repository.check = Some(true);
+        }
+        let backup = profile.get("backup").and_then(Value::as_object);
+        let source = strings(backup.and_then(|backup| backup.get("source")));
+        if !source.is_empty() {
+            let backup = backup.expect("the source is in the backup section");
+            for option in ["iexclude", "exclude-file", "iexclude-file"] {
+                if backup.contains_key(option) {
+                    warnings.push(format!("profile {}: {} is not imported", name, option));
+                }
+            }
+            imported.jobs.push(Job {
+                name: name.clone(),
+                source,
+                exclude: strings(backup.get("exclude")),
+                host: string(backup.get("host")),
+                repositories: vec![name.clone()],
+            });
+        }
+        imported.repositories.push(repository);
+    }
+    Ok(imported)
+}
+
+/// The settings of a resticprofile profile merged over the ones it inherits
+fn inherited(profiles: &Map<String, Value>, name: &str, depth: usize) -> anyhow::Result<Value> {
+    if depth > profiles.len() {
+        anyhow::bail!("profile {}: inheritance loop", name);
+    }
+    let profile = profiles
+        .get(name)
+        .with_context(|| format!("no profile named {}", name))?;
+    let Some(parent) = string(profile.get("inherit")) else {
+        return Ok(profile.clone());
+    };
+    let mut settings = inherited(profiles, &parent, depth + 1)?;
+    merge(&mut settings, profile);
+    Ok(settings)
+}
+
+fn merge(base: &mut Value, other: &Value) {
+    match (base, other) {
+        (Value::Object(base), Value::Object(other)) => {
+            for (key, value) in other {
+                match base.get_mut(key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (base, other) => *base = other.clone(),
+    }
+}
+
+/// Convert an autorestic configuration: the backends are the repositories and
+/// the locations are the jobs
+fn autorestic(document: &Value, warnings: &mut Vec<String>) -> anyhow::Result<Imported> {
+    let mut imported = Imported::default();
+    let backends = document.get("backends").and_then(Value::as_object);
+    for (name, backend) in backends.into_iter().flatten() {
+        let path =
+            string(backend.get("path")).with_context(|| format!("backend {}: no path", name))?;
+        let location = match string(backend.get("type")).as_deref() {
+            None | Some("local") => path,
+            Some(kind) => format!("{}:{}", kind, path),
+        };
+        let mut repository = Repository {
+            name: name.clone(),
+            location,
+            ..Default::default()
+        };
+        if backend.get("key").is_some() {
+            warnings.push(format!(
+                "backend {}: the key is not imported, store it in a file and set password-file",
+                name
+            ));
+        }
+        if let Some(env) = backend.get("env").and_then(Value::as_object) {
+            environment(&mut repository, env, &format!("backend {}", name), warnings);
+        }
+        imported.repositories.push(repository);
+    }
+    let locations = document.get("locations").and_then(Value::as_object);
+    for (name, location) in locations.into_iter().flatten() {
+        let targets = strings(location.get("to"));
+        if location.get("cron").is_some() {
+            warnings.push(format!(
+                "location {}: cron schedules are not imported, set backup-calendar",
+                name
+            ));
+        }
+        let options = location.get("options");
+        let option = |command: &str| {
+            options
+                .and_then(|options| options.get(command))
+                .and_then(Value::as_object)
+        };
+        if let Some(forget) = option("forget") {
+            for target in targets.iter() {
+                let Some(repository) = imported.repositories.iter_mut().find(|r| &r.name == target)
+                else {
+                    continue;
+                };
+                if !repository.retention.is_empty() {
+                    warnings.push(format!(
+                        "location {}: backend {} already has a retention policy, kept",
+                        name, target
+                    ));
+                    continue;
+                }
+                retention(repository, forget);
+            }
+        }
+        imported.jobs.push(Job {
+            name: name.clone(),
+            source: strings(location.get("from")),
+            exclude: strings(option("backup").and_then(|backup| backup.get("exclude"))),
+            host: string(option("backup").and_then(|backup| backup.get("host"))),
+            repositories: targets,
+        });
+    }
+    Ok(imported)
+}
+
+/// Copy the keep-* options
+fn retention(repository: &mut Repository, options: &Map<String, Value>) {
+    for key in RETENTION {
+        if let Some(value) = options.get(*key) {
+            let value = match value {
+                // autorestic and resticprofile accept lists for repeated options
+                Value::Array(values) => values.first().cloned().unwrap_or(Value::Null),
+                value => value.clone(),
+            };
+            if !value.is_null() {
+                repository.retention.insert(key.to_string(), value);
+            }
+        }
+    }
+}
+
+/// Copy the credentials to their settings, and the other variables to the
+/// repository environment
+fn environment(
+    repository: &mut Repository,
+    env: &Map<String, Value>,
+    what: &str,
+    warnings: &mut Vec<String>,
+) {
+    for (key, value) in env {
+        match key.to_uppercase().as_str() {
+            "AWS_ACCESS_KEY_ID" => repository.aws_access_key = string(Some(value)),
+            "AWS_SECRET_ACCESS_KEY" => repository.aws_secret_access_key = string(Some(value)),
+            "AZURE_ACCOUNT_NAME" => repository.azure_account_name = string(Some(value)),
+            "AZURE_ACCOUNT_KEY" => repository.azure_account_key = string(Some(value)),
+            "AZURE_ACCOUNT_SAS" => repository.azure_account_sas = string(Some(value)),
+            "RESTIC_PASSWORD_FILE" => repository.password_file = string(Some(value)),
+            "RESTIC_PASSWORD_COMMAND" => repository.password_command = string(Some(value)),
+            name => match string(Some(value)) {
+                Some(value) => {
+                    repository.environment.insert(name.to_string(), value);
+                }
+                None => warnings.push(format!(
+                    "{}: environment variable {} is not a string, not imported",
+                    what, key
+                )),
+            },
+        }
+    }
+}
+
+/// A string setting, or a number or boolean as a string
+fn string(value: Option<&Value>) -> Option<String> {
+    match value? {
+        Value::String(value) => Some(value.clone()),
+        Value::Number(value) => Some(value.to_string()),
+        Value::Bool(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// A setting that is a single string or a list of them
+fn strings(value: Option<&Value>) -> Vec<String> {
+    match value {
+        Some(Value::Array(values)) => values.iter().filter_map(|v| string(Some(v))).collect(),
+        value => string(value).into_iter().collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn convert(tool: Tool, name: &str, content: &str) -> (Config, String) {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join(name);
+        std::fs::write(&path, content).unwrap();
+        let mut out = Vec::new();
+        run(&mut out, tool, &path).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let config = toml::from_str(&out).unwrap();
+        (
+            config,
+            out.replace(&dir.path().display().to_string(), "DIR"),
+        )
+    }
+
+    #[test]
+    fn resticprofile_profiles() {
+        let (config, out) = convert(
+            Tool::Resticprofile,
+            "profiles.yaml",
+            r#"
+version: "1"
+global:
+  priority: low
+default:
+  password-file: key
+  retention:
+    keep-daily: 7
+    keep-within: 3m
+home:
+  inherit: default
+  repository: "local:/mnt/backup"
+  env:
+    AWS_ACCESS_KEY_ID: id
+    gogc: 20
+  backup:
+    source:
+      - /home
+    exclude: ["*.tmp"]
+    schedule: daily
+  check:
+    schedule: Sun 04:00
+"#,
+        );
+        assert_eq!(
+            out,
+            r#"# imported from DIR/profiles.yaml by restic-generator
+
+[[repositories]]
+name = "home"
+location = "local:/mnt/backup"
+password-file = "DIR/key"
+aws-access-key = "id"
+backup-calendar = "daily"
+check = true
+check-calendar = "Sun 04:00"
+keep-daily = 7
+keep-within = "3m"
+
+[repositories.environment]
+GOGC = "20"
+
+[[jobs]]
+name = "home"
+source = ["/home"]
+exclude = ["*.tmp"]
+repositories = ["home"]
+"#
+        );
+        assert_eq!(config.repositories[0].keep_daily, Some(7));
+    }
+
+    #[test]
+    fn autorestic_locations() {
+        let (config, _) = convert(
+            Tool::Autorestic,
+            ".autorestic.yml",
+            r#"
+version: 2
+backends:
+  hdd:
+    type: local
+    path: /mnt/hdd
+  remote:
+    type: s3
+    path: s3.amazonaws.com/bucket
+    key: secret
+locations:
+  home:
+    from: /home
+    to: [hdd, remote]
+    cron: "0 3 * * 0"
+    options:
+      backup:
+        exclude: ["*.iso"]
+      forget:
+        keep-weekly: 4
+"#,
+        );
+        let locations: Vec<_> = config
+            .repositories
+            .iter()
+            .map(|r| (r.location.as_str(), r.keep_weekly))
+            .collect();
+        assert_eq!(
+            locations,
+            vec![
+                ("/mnt/hdd", Some(4)),
+                ("s3:s3.amazonaws.com/bucket", Some(4))
+            ]
+        );
+        assert_eq!(config.jobs[0].source, vec!["/home"]);
+        assert_eq!(config.jobs[0].exclude, vec!["*.iso"]);
+        assert_eq!(config.jobs[0].repositories, vec!["hdd", "remote"]);
+    }
+
+    #[test]
+    fn tools() {
+        assert_eq!("autorestic".parse::<Tool>().unwrap(), Tool::Autorestic);
+        assert!("borgmatic".parse::<Tool>().is_err());
+    }
+}
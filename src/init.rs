@@ -0,0 +1,207 @@
+use anyhow::Context as _;
+use std::{
+    fs,
+    io::{self, BufRead, IsTerminal, Write},
+    path::Path,
+};
+
+/// The values of the starter configuration; the ones not given on the
+/// command line are asked interactively, or defaulted.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Options {
+    pub source: Option<String>,
+    pub name: Option<String>,
+    pub location: Option<String>,
+    pub password_file: Option<String>,
+    pub password_command: Option<String>,
+    pub force: bool,
+}
+
+const DEFAULT_SOURCE: &str = "/home";
+const DEFAULT_NAME: &str = "main";
+const DEFAULT_PASSWORD_FILE: &str = "/etc/restic-generator/password";
+
+/// Write a starter configuration to `path`
+pub fn run(path: &Path, mut options: Options) -> anyhow::Result<()> {
+    if path.exists() && !options.force {
+        anyhow::bail!(
+            "{}: file exists (use --force to overwrite it)",
+            path.display()
+        );
+    }
+    let stdin = io::stdin();
+    if stdin.is_terminal() {
+        let mut input = stdin.lock();
+        ask(
+            &mut input,
+            "Directory to back up",
+            DEFAULT_SOURCE,
+            &mut options.source,
+        )?;
+        ask(
+            &mut input,
+            "Repository name",
+            DEFAULT_NAME,
+            &mut options.name,
+        )?;
+        ask(&mut input, "Repository location", "", &mut options.location)?;
+        if options.password_command.is_none() {
+            ask(
+                &mut input,
+                "Password file",
+                DEFAULT_PASSWORD_FILE,
+                &mut options.password_file,
+            )?;
+        }
+    }
+    if options.location.as_deref().unwrap_or("").is_empty() {
+        anyhow::bail!("a repository location is required (use --location)");
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("{}: error creating directory", parent.display()))?;
+    }
+    fs::write(path, render(&options))
+        .with_context(|| format!("{}: error creating file", path.display()))?;
+    eprintln!("Wrote {}", path.display());
+    Ok(())
+}
+
+/// Prompt for a value unless it was already given
+fn ask(
+    input: &mut impl BufRead,
+    question: &str,
+    default: &str,
+    value: &mut Option<String>,
+) -> anyhow::Result<()> {
+    if value.is_some() {
+        return Ok(());
+    }
+    if default.is_empty() {
+        eprint!("{}: ", question);
+    } else {
+        eprint!("{} [{}]: ", question, default);
+    }
+    io::stderr().flush()?;
+    let mut line = String::new();
+    input.read_line(&mut line)?;
+    let line = line.trim();
+    *value = Some(if line.is_empty() { default } else { line }.to_string());
+    Ok(())
+}
+
+fn quote(value: &str) -> String {
+    toml::Value::String(value.to_string()).to_string()
+}
+
+/// The content of the starter configuration
+fn render(options: &Options) -> String {
+    let password = match (&options.password_command, &options.password_file) {
+        (Some(command), _) => format!("password-command = {}", quote(command)),
+        (None, file) => format!(
+            "password-file = {}",
+            quote(file.as_deref().unwrap_or(DEFAULT_PASSWORD_FILE))
+        ),
+    };
+    format!(
+        r#"# Configuration for restic-generator
+
+# The directory to back up, or a list of directories
+source = {source}
+
+# Patterns excluded from the backups, see `restic help backup`
+exclude = [
+  "/home/*/.cache",
+]
+
+# Each repository gets a backup unit, plus forget and prune units if it has a
+# retention policy.
+[[repositories]]
+name = {name}
+# A local path, or a remote location such as sftp:user@host:/srv/restic-repo
+location = {location}
+# Where restic finds the repository password. Use password-command instead
+# to get it from a password manager.
+{password}
+
+# Retention policy: which snapshots forget keeps
+keep-daily = 7
+keep-weekly = 4
+keep-monthly = 12
+"#,
+        source = quote(options.source.as_deref().unwrap_or(DEFAULT_SOURCE)),
+        name = quote(options.name.as_deref().unwrap_or(DEFAULT_NAME)),
+        location = quote(options.location.as_deref().unwrap_or("")),
+        password = password,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn render_is_a_valid_config() {
+        let options = Options {
+            location: Some("/media/backup".into()),
+            ..Default::default()
+        };
+        let config: Config = toml::from_str(&render(&options)).unwrap();
+        assert_eq!(config.source, vec!["/home"]);
+        let repository = &config.repositories[0];
+        assert_eq!(repository.name, "main");
+        assert_eq!(repository.location, "/media/backup");
+        assert_eq!(
+            repository.password_file.as_deref(),
+            Some("/etc/restic-generator/password")
+        );
+        assert!(repository.has_forget_policy());
+    }
+
+    #[test]
+    fn render_password_command() {
+        let options = Options {
+            location: Some("sftp:host:/srv/\"restic\"".into()),
+            password_command: Some("pass restic".into()),
+            ..Default::default()
+        };
+        let config: Config = toml::from_str(&render(&options)).unwrap();
+        let repository = &config.repositories[0];
+        assert_eq!(repository.location, "sftp:host:/srv/\"restic\"");
+        assert_eq!(repository.password_command.as_deref(), Some("pass restic"));
+        assert_eq!(repository.password_file, None);
+    }
+
+    #[test]
+    fn ask_keeps_given_value() {
+        let mut value = Some("given".to_string());
+        ask(&mut "typed\n".as_bytes(), "question", "default", &mut value).unwrap();
+        assert_eq!(value.as_deref(), Some("given"));
+    }
+
+    #[test]
+    fn ask_default() {
+        let mut value = None;
+        ask(&mut "\n".as_bytes(), "question", "default", &mut value).unwrap();
+        assert_eq!(value.as_deref(), Some("default"));
+        let mut value = None;
+        ask(
+            &mut " typed \n".as_bytes(),
+            "question",
+            "default",
+            &mut value,
+        )
+        .unwrap();
+        assert_eq!(value.as_deref(), Some("typed"));
+    }
+
+    #[test]
+    fn refuses_to_overwrite() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "mine").unwrap();
+        assert!(run(&path, Options::default()).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "mine");
+    }
+}
@@ -0,0 +1,231 @@
+use std::io::Write;
+
+use crate::config::Config;
+use crate::crontab;
+use crate::units::{self, Context, PlannedUnit, Unit, UnitKind};
+
+/// launchd starts jobs with a minimal PATH, which misses Homebrew's prefixes
+const PATH: &str = "/opt/homebrew/bin:/usr/local/bin:/usr/bin:/bin:/usr/sbin:/sbin";
+
+/// When the unit runs, as a StartCalendarInterval: the same times as its
+/// crontab entry
+fn calendar_interval(planned: &PlannedUnit) -> anyhow::Result<&'static [(&'static str, u32)]> {
+    Ok(match crontab::schedule(planned)? {
+        // Run when loaded instead
+        "@reboot" => &[],
+        "@hourly" => &[("Minute", 0)],
+        "@daily" => &[("Hour", 0), ("Minute", 0)],
+        "@weekly" => &[("Weekday", 0), ("Hour", 0), ("Minute", 0)],
+        "@monthly" => &[("Day", 1), ("Hour", 0), ("Minute", 0)],
+        "@yearly" => &[("Month", 1), ("Day", 1), ("Hour", 0), ("Minute", 0)],
+        schedule => unreachable!("unknown crontab schedule {}", schedule),
+    })
+}
+
+/// Render one property list per generated unit
+pub fn generate(context: &Context, config: &Config) -> anyhow::Result<Vec<Unit>> {
+    let mut jobs = Vec::new();
+    let plan = units::plan(context, config);
+    for planned in plan.iter() {
+        // The units without a timer only run by hand, except init at boot
+        let scheduled = planned.has_timer() || planned.kind == UnitKind::Init;
+        if planned.skipped.is_some() || !scheduled || planned.kind.is_systemd_only() {
+            continue;
+        }
+        let label = planned.name.trim_end_matches(".service").to_string();
+        jobs.push(Unit::render(format!("{}.plist", label), |out| {
+            write_job(out, context, &plan, planned, &label)
+        })?);
+    }
+    Ok(jobs)
+}
+
+fn write_job(
+    out: &mut impl Write,
+    context: &Context,
+    plan: &[PlannedUnit],
+    planned: &PlannedUnit,
+    label: &str,
+) -> anyhow::Result<()> {
+    // The chained forget and prune only run after a successful backup
+    let commands: Vec<_> = units::chained_commands(context, plan, planned)
+        .iter()
+        .map(|command| command.to_shell())
+        .collect();
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">"#
+    )?;
+    writeln!(
+        out,
+        "<!-- generated by {} from {} -->",
+        context.program_name,
+        escape(&context.config_path.display().to_string())
+    )?;
+    writeln!(out, r#"<plist version="1.0">"#)?;
+    writeln!(out, "<dict>")?;
+    write_string(out, 1, "Label", label)?;
+    writeln!(out, "  <key>ProgramArguments</key>")?;
+    writeln!(out, "  <array>")?;
+    for arg in ["/bin/sh", "-c", &commands.join(" && ")] {
+        writeln!(out, "    <string>{}</string>", escape(arg))?;
+    }
+    writeln!(out, "  </array>")?;
+    writeln!(out, "  <key>EnvironmentVariables</key>")?;
+    writeln!(out, "  <dict>")?;
+    write_string(out, 2, "PATH", PATH)?;
+    for (name, value) in planned.environment() {
+        write_string(out, 2, name, &value)?;
+    }
+    writeln!(out, "  </dict>")?;
+    let interval = calendar_interval(planned)?;
+    if interval.is_empty() {
+        writeln!(out, "  <key>RunAtLoad</key>")?;
+        writeln!(out, "  <true/>")?;
+    } else {
+        writeln!(out, "  <key>StartCalendarInterval</key>")?;
+        writeln!(out, "  <dict>")?;
+        for (key, value) in interval {
+            writeln!(out, "    <key>{}</key>", key)?;
+            writeln!(out, "    <integer>{}</integer>", value)?;
+        }
+        writeln!(out, "  </dict>")?;
+    }
+    writeln!(out, "  <key>Nice</key>")?;
+    writeln!(out, "  <integer>10</integer>")?;
+    writeln!(out, "  <key>LowPriorityIO</key>")?;
+    writeln!(out, "  <true/>")?;
+    writeln!(out, "</dict>")?;
+    writeln!(out, "</plist>")?;
+    Ok(())
+}
+
+fn write_string(out: &mut impl Write, depth: usize, key: &str, value: &str) -> anyhow::Result<()> {
+    let indent = "  ".repeat(depth);
+    writeln!(out, "{}<key>{}</key>", indent, escape(key))?;
+    writeln!(out, "{}<string>{}</string>", indent, escape(value))?;
+    Ok(())
+}
+
+/// Escape the XML special characters
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Maintenance, RepositoryConfig, Schedule};
+    use crate::units::tests::context;
+
+    #[test]
+    fn escapes_xml() {
+        assert_eq!(
+            escape(r#"a && "b" <c>"#),
+            "a &amp;&amp; &quot;b&quot; &lt;c&gt;"
+        );
+    }
+
+    #[test]
+    fn one_job_per_unit() {
+        let context = context();
+        let config = Config {
+            source: vec!["/Users".into()],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "/Volumes/backup".into(),
+                keep_daily: Some(7),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let jobs = generate(&context, &config).unwrap();
+        let names: Vec<_> = jobs.iter().map(|job| job.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "restic-myrepo-backup.plist",
+                "restic-myrepo-forget.plist",
+                "restic-myrepo-prune.plist"
+            ]
+        );
+        let backup = String::from_utf8(jobs[0].content.clone()).unwrap();
+        assert!(backup.contains(
+            "<string>restic unlock &amp;&amp; restic backup --host=laptop /Users</string>"
+        ));
+        assert!(
+            backup.contains("<key>RESTIC_REPOSITORY</key>\n    <string>/Volumes/backup</string>")
+        );
+    }
+
+    #[test]
+    fn manual_schedule() {
+        let config = Config {
+            source: vec!["/Users".into()],
+            repositories: vec![RepositoryConfig {
+                name: "archive".into(),
+                location: "/Volumes/archive".into(),
+                keep_daily: Some(7),
+                schedule: Schedule::Manual,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert!(generate(&context(), &config).unwrap().is_empty());
+    }
+
+    #[test]
+    fn chained_maintenance() {
+        let config = Config {
+            source: vec!["/Users".into()],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "/Volumes/backup".into(),
+                keep_daily: Some(7),
+                maintenance: Maintenance::Chained,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let jobs = generate(&context(), &config).unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert!(String::from_utf8(jobs[0].content.clone())
+            .unwrap()
+            .contains(
+                "<string>restic unlock &amp;&amp; restic backup --host=laptop /Users \
+             &amp;&amp; restic unlock &amp;&amp; restic forget --host=laptop --path=/Users \
+             --keep-daily=7 &amp;&amp; restic unlock &amp;&amp; restic prune</string>"
+            ));
+    }
+
+    #[test]
+    fn calendars() {
+        let config = Config {
+            source: vec!["/Users".into()],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "/Volumes/backup".into(),
+                backup_calendar: Some("hourly".parse().unwrap()),
+                keep_daily: Some(7),
+                forget_calendar: Some("yearly".parse().unwrap()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let jobs = generate(&context(), &config).unwrap();
+        let content = |index: usize| String::from_utf8(jobs[index].content.clone()).unwrap();
+        assert!(content(0).contains(
+            "<key>StartCalendarInterval</key>\n  <dict>\n\
+             \x20   <key>Minute</key>\n    <integer>0</integer>\n  </dict>\n"
+        ));
+        assert!(content(1).contains(
+            "<key>Month</key>\n    <integer>1</integer>\n\
+             \x20   <key>Day</key>\n    <integer>1</integer>\n"
+        ));
+    }
+}
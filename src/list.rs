@@ -0,0 +1,102 @@
+use serde::Serialize;
+use std::io::Write;
+
+use crate::units::{PlannedUnit, UnitKind};
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    repository: &'a str,
+    unit: &'a str,
+    #[serde(rename = "type")]
+    kind: UnitKind,
+    generated: bool,
+    reason: Option<&'a str>,
+}
+
+/// Print the planned units grouped by repository, or as a JSON array
+pub fn print(out: &mut impl Write, units: &[PlannedUnit], json: bool) -> anyhow::Result<()> {
+    if json {
+        let entries: Vec<_> = units
+            .iter()
+            .map(|unit| Entry {
+                repository: &unit.repository.name,
+                unit: &unit.name,
+                kind: unit.kind,
+                generated: unit.skipped.is_none(),
+                reason: unit.skipped,
+            })
+            .collect();
+        serde_json::to_writer_pretty(&mut *out, &entries)?;
+        writeln!(out)?;
+        return Ok(());
+    }
+    let mut repository = None;
+    for unit in units {
+        if repository != Some(&unit.repository.name) {
+            repository = Some(&unit.repository.name);
+            writeln!(
+                out,
+                "{} ({})",
+                unit.repository.name, unit.repository.location
+            )?;
+        }
+        match unit.skipped {
+            None => writeln!(out, "  {}", unit.name)?,
+            Some(reason) => writeln!(out, "  {} (skipped: {})", unit.name, reason)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, RepositoryConfig};
+    use crate::units::{plan, tests::context};
+
+    fn config() -> Config {
+        Config {
+            source: vec!["/".into()],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "/repo".into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn text() {
+        let mut out = Vec::new();
+        print(&mut out, &plan(&context(), &config()), false).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "myrepo (/repo)
+  restic-myrepo-backup.service
+  restic-myrepo-forget.service (skipped: no forget policy)
+  restic-myrepo-prune.service (skipped: no forget policy)
+  restic-myrepo-unlock.service
+"
+        );
+    }
+
+    #[test]
+    fn json() {
+        let mut out = Vec::new();
+        print(&mut out, &plan(&context(), &config()), true).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(
+            value[0],
+            serde_json::json!({
+                "repository": "myrepo",
+                "unit": "restic-myrepo-backup.service",
+                "type": "backup",
+                "generated": true,
+                "reason": null,
+            })
+        );
+        assert_eq!(value[1]["generated"], false);
+        assert_eq!(value[1]["reason"], "no forget policy");
+    }
+}
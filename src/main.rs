@@ -1,281 +1,504 @@
 use anyhow::{Context as _, Result};
 use std::{
-    env, fs,
-    io::Write,
+    env,
+    ffi::OsString,
+    fs,
+    io::{self, Write},
+    os::unix::{fs::PermissionsExt, process::CommandExt},
     path::{Path, PathBuf},
+    process,
 };
 
+mod cli;
+mod completions;
 mod config;
+mod crontab;
+mod de;
+mod diff;
+mod doctor;
+mod environment;
+mod freshness;
+mod glob;
+mod import;
+mod init;
+mod launchd;
+mod list;
+mod manifest;
+mod restic;
+mod schema;
+mod scripts;
+mod snapshot_diff;
+mod stats;
+mod status;
 mod sys;
+mod units;
+mod validate;
+mod verify;
 
-use config::{Config, RepositoryConfig};
-
-const USAGE: &str = "Usage: restig-generator <normal-dir> <early-dir> <late-dir>";
-
-#[derive(Debug)]
-struct Context {
-    config_path: PathBuf,
-    program_name: String,
-    hostname: String,
-}
+use cli::{Command, Overwrite, RenderTarget, Scope};
+use config::Config;
+use units::{Context, Unit};
 
 fn main() -> anyhow::Result<()> {
-    let Some(normal_dir) = env::args().nth(1).map(PathBuf::from) else {
-        eprintln!("{}", USAGE);
-        std::process::exit(1);
+    let args = match cli::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, cli::USAGE);
+            std::process::exit(1);
+        }
     };
-    let is_user = env::var("USER").is_ok(); // Indicate we're generating user-level units
-    let config_path = env::var("RESTIC_GENERATOR_CONFIG")
-        .map(PathBuf::from)
-        .unwrap_or(default_config_path(is_user)?);
+    match args.command {
+        Command::Help => {
+            println!("{}", cli::USAGE);
+            return Ok(());
+        }
+        Command::Version => {
+            println!("{} {}", env!("CARGO_BIN_NAME"), env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
+        _ => (),
+    }
+    // These commands don't read the configuration
+    if let Command::Completions { shell } = &args.command {
+        return completions::print(&mut io::stdout().lock(), shell);
+    }
+    if let Command::Schema = args.command {
+        return schema::print(&mut io::stdout().lock());
+    }
+    if let Command::Import { from, path } = &args.command {
+        return import::run(&mut io::stdout().lock(), *from, path);
+    }
+    // The generated units give the settings of the repository instead: the
+    // configuration may need variables or keys their environment lacks
+    if let Command::Freshness {
+        repository,
+        resolved: Some(resolved),
+    } = &args.command
+    {
+        let access = restic::Access {
+            options: resolved.options.clone(),
+            ..Default::default()
+        };
+        let max_age = resolved.max_age.as_ref().expect("required by --resolved");
+        let out = &mut io::stdout().lock();
+        return freshness::run(out, repository, &access, max_age, &resolved.hosts);
+    }
+    if let Command::Stats {
+        repository,
+        output,
+        resolved: Some(resolved),
+    } = &args.command
+    {
+        let access = restic::Access {
+            options: resolved.options.clone(),
+            ..Default::default()
+        };
+        let stats = stats::query(repository, &access)?;
+        stats::log(&stats);
+        return stats::write(&mut io::stdout().lock(), &stats, output.as_deref());
+    }
+    if let Command::SnapshotDiff {
+        resolved: Some(resolved),
+        ..
+    } = &args.command
+    {
+        let access = restic::Access {
+            options: resolved.options.clone(),
+            ..Default::default()
+        };
+        let out = &mut io::stdout().lock();
+        return snapshot_diff::run(out, &access, &resolved.paths, &resolved.hosts[0]);
+    }
+    // Without an explicit flag, guess whether we're generating user-level units
+    let is_user = match args.scope {
+        Some(scope) => scope == Scope::User,
+        None => env::var("USER").is_ok(),
+    };
+    let (config_path, origin) =
+        resolve_config_path(args.config, env::var_os("RESTIC_GENERATOR_CONFIG"), is_user)?;
+    if let Command::Init(options) = args.command {
+        return init::run(&config_path, options);
+    }
     let context = Context {
         config_path,
         program_name: env!("CARGO_BIN_NAME").into(),
+        program_path: env::current_exe().unwrap_or_else(|_| env!("CARGO_BIN_NAME").into()),
         hostname: sys::hostname()?,
     };
-    eprintln!("Using config file {}", context.config_path.display());
+    eprintln!(
+        "Using config file {} ({})",
+        context.config_path.display(),
+        origin
+    );
     let config: Config =
-        read_config(&context.config_path).with_context(|| "error reading config")?;
+        config::load(&context.config_path).with_context(|| "error reading config")?;
 
-    for repository in config.repositories.iter() {
-        generate_backup_service(
-            &normal_dir.join(format!("restic-{}-backup.service", repository.name)),
-            &context,
-            &config,
-            repository,
-        )?;
-        generate_forget_service(
-            &normal_dir.join(format!("restic-{}-forget.service", repository.name)),
-            &context,
-            &config,
-            repository,
-        )?;
-        generate_prune_service(
-            &normal_dir.join(format!("restic-{}-prune.service", repository.name)),
-            &context,
-            &config,
-            repository,
-        )?;
+    let mut stdout = io::stdout().lock();
+    match args.command {
+        Command::Help
+        | Command::Version
+        | Command::Completions { .. }
+        | Command::Init(_)
+        | Command::Schema
+        | Command::Import { .. } => unreachable!(),
+        Command::Generate {
+            normal_dir,
+            print_manifest,
+            ..
+        } => {
+            check(&context, &config)?;
+            write_units(&normal_dir, &units::generate(&context, &config)?)?;
+            let mut content = Vec::new();
+            for file in config.files.iter() {
+                content.extend(fs::read(file)?);
+            }
+            let manifest = manifest::Manifest::new(&normal_dir, &context, &content, &config);
+            if print_manifest {
+                manifest.write(&mut stdout)
+            } else {
+                let path = normal_dir.join(manifest::FILE_NAME);
+                let mut file = fs::File::create(&path)
+                    .with_context(|| format!("{}: error creating file", path.display()))?;
+                manifest.write(&mut file)
+            }
+        }
+        Command::Render {
+            target: RenderTarget::Stdout,
+            format,
+        } => {
+            check(&context, &config)?;
+            print_units(&mut stdout, &units::render(&context, &config, format)?)
+        }
+        Command::Render {
+            target: RenderTarget::Dir { path, overwrite },
+            format,
+        } => {
+            check(&context, &config)?;
+            render_units(
+                &mut stdout,
+                &path,
+                &units::render(&context, &config, format)?,
+                overwrite,
+            )
+        }
+        Command::Validate => {
+            let problems = validate::validate(&config);
+            for problem in problems.iter() {
+                writeln!(stdout, "{}: {}", context.config_path.display(), problem)?;
+            }
+            if !problems.is_empty() {
+                eprintln!("{} problem(s) found", problems.len());
+                std::process::exit(1);
+            }
+            eprintln!("No problems found");
+            Ok(())
+        }
+        Command::Verify => {
+            let reports = verify::run(&units::generate(&context, &config)?, is_user)?;
+            verify::print(&mut stdout, &reports)?;
+            if reports.iter().any(|report| !report.success) {
+                stdout.flush()?;
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Command::Diff { against } => {
+            let dir = against.unwrap_or_else(|| diff::generator_dir(is_user));
+            let diffs = diff::compare(
+                &dir,
+                &units::generate(&context, &config)?,
+                &context.program_name,
+            )?;
+            diff::print(&mut stdout, &diffs)?;
+            if diffs
+                .iter()
+                .any(|diff| diff.change != diff::Change::Unchanged)
+            {
+                stdout.flush()?;
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Command::List { json } => list::print(&mut stdout, &units::plan(&context, &config), json),
+        Command::Env { repository, format } => {
+            environment::print(&mut stdout, config.repository(&repository)?, format)
+        }
+        Command::Exec { repository, args } => {
+            let repository = config.repository(&repository)?;
+            let err = process::Command::new("restic")
+                .args(restic::option_args(repository))
+                .args(args)
+                .envs(restic::environment(repository))
+                .exec();
+            Err(err).with_context(|| "error running restic")
+        }
+        Command::PreviewForget { repository } => {
+            let repository = config.repository(&repository)?;
+            let plan = units::plan(&context, &config);
+            let (forgets, skipped): (Vec<_>, Vec<_>) = plan
+                .iter()
+                .filter(|unit| unit.kind == units::UnitKind::Forget)
+                .filter(|unit| unit.repository.name == repository.name)
+                .partition(|unit| unit.skipped.is_none());
+            if forgets.is_empty() {
+                anyhow::bail!(
+                    "repository {} has no forget unit ({})",
+                    repository.name,
+                    skipped
+                        .first()
+                        .and_then(|unit| unit.skipped)
+                        .unwrap_or("no job backs up to it")
+                );
+            }
+            for unit in forgets.iter() {
+                if forgets.len() > 1 {
+                    eprintln!("### {}", unit.name);
+                }
+                let forget = units::commands(&context, unit)
+                    .pop()
+                    .expect("forget units run forget last");
+                let status = process::Command::new("restic")
+                    .args(forget.args())
+                    .arg("--dry-run")
+                    .envs(restic::environment(repository))
+                    .status()
+                    .with_context(|| "error running restic")?;
+                if !status.success() {
+                    std::process::exit(status.code().unwrap_or(1));
+                }
+            }
+            Ok(())
+        }
+        Command::Stats {
+            repository, output, ..
+        } => {
+            let repository = config.repository(&repository)?;
+            let stats = stats::query(&repository.name, &restic::Access::of(repository))?;
+            stats::log(&stats);
+            stats::write(&mut stdout, &stats, output.as_deref())
+        }
+        Command::Freshness { repository, .. } => {
+            let repository = config.repository(&repository)?;
+            let max_age = repository
+                .max_age
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("repository {} has no max-age", repository.name))?;
+            let hosts = units::snapshot_hosts(&context, &config.jobs(), repository);
+            let access = restic::Access::of(repository);
+            freshness::run(&mut stdout, &repository.name, &access, max_age, &hosts)
+        }
+        Command::SnapshotDiff {
+            repository, job, ..
+        } => {
+            let repository = config.repository(&repository)?;
+            let jobs = config.jobs();
+            let job = jobs
+                .iter()
+                .filter(|candidate| candidate.targets(repository))
+                .find(|candidate| candidate.name == job.as_deref())
+                .ok_or_else(|| match &job {
+                    Some(job) => anyhow::anyhow!(
+                        "job {} does not back up to repository {}",
+                        job,
+                        repository.name
+                    ),
+                    None => anyhow::anyhow!("the configuration has no top-level source"),
+                })?;
+            let host = job.host.unwrap_or(&context.hostname);
+            let access = restic::Access::of(repository);
+            snapshot_diff::run(&mut stdout, &access, &job.snapshot_paths(), host)
+        }
+        Command::Status { json } => {
+            let statuses = status::query(&units::plan(&context, &config), is_user)?;
+            status::print(&mut stdout, &statuses, json)
+        }
+        Command::NextRuns => {
+            let runs = status::query_next_runs(&units::plan(&context, &config), is_user)?;
+            status::print_next_runs(&mut stdout, &runs)
+        }
+        Command::ListRepositories => {
+            for repository in config.repositories.iter() {
+                writeln!(stdout, "{}", repository.name)?;
+            }
+            Ok(())
+        }
+        Command::Doctor { check_credentials } => {
+            let checks = doctor::run(&config, &context.hostname, check_credentials);
+            for check in checks.iter() {
+                writeln!(stdout, "{}", check)?;
+            }
+            stdout.flush()?;
+            std::process::exit(doctor::exit_code(&checks));
+        }
     }
-    Ok(())
 }
 
-fn default_config_path(user: bool) -> Result<PathBuf> {
-    if user {
-        let home = env::var("HOME").with_context(|| "HOME environment variable not found")?;
-        Ok(PathBuf::from(home).join(".config/restic-generator/config.toml"))
-    } else {
-        Ok(PathBuf::from("/etc/restic-generator/config.toml"))
+/// Report the problems of the configuration before generating units from it,
+/// and fail unless the configuration only asks for warnings
+fn check(context: &Context, config: &Config) -> Result<()> {
+    let problems = validate::validate(config);
+    let warn = config.on_problems == config::OnProblems::Warn;
+    let mut errors = 0;
+    for problem in problems.iter() {
+        let error = !warn && !problem.warning;
+        errors += error as usize;
+        eprintln!(
+            "{}: {}: {}",
+            if error { "error" } else { "warning" },
+            context.config_path.display(),
+            problem
+        );
     }
+    if errors > 0 {
+        anyhow::bail!(
+            "{} problem(s) found in the configuration (set on-problems = \"warn\" to generate the units anyway)",
+            errors
+        );
+    }
+    Ok(())
 }
 
-fn read_config(path: &Path) -> Result<Config> {
-    let content = fs::read(path)?;
-    let config = toml::from_slice(&content)?;
-    Ok(config)
-}
-
-fn generate_backup_service(
-    path: &Path,
-    context: &Context,
-    config: &Config,
-    repository: &RepositoryConfig,
-) -> anyhow::Result<()> {
-    let mut file = fs::File::create(path)
-        .with_context(|| format!("{}: error creating file", path.display()))?;
-    writeln!(file, "# generated by {}", context.program_name)?;
-    writeln!(file, "[Unit]",)?;
-    writeln!(
-        file,
-        "Description=backup {} to {}",
-        &config.source, &repository.location
-    )?;
-    writeln!(file, "SourcePath={}", context.config_path.display())?;
-    writeln!(file, "ConditionPathExists={}", config.source)?;
-    if is_local_repository(&repository.location) {
-        writeln!(file, "ConditionPathExists={}", repository.location)?;
-    }
-    writeln!(file)?;
-    writeln!(file, "[Service]")?;
-    writeln!(
-        file,
-        "Environment=RESTIC_REPOSITORY=\"{}\"",
-        repository.location
-    )?;
-    if let Some(value) = &repository.password_file {
-        writeln!(file, "Environment=RESTIC_PASSWORD_FILE=\"{}\"", value)?;
+fn write_units(dir: &Path, units: &[Unit]) -> Result<()> {
+    for unit in units {
+        let path = dir.join(&unit.name);
+        fs::write(&path, &unit.content)
+            .with_context(|| format!("{}: error creating file", path.display()))?;
     }
-    if let Some(value) = &repository.password_command {
-        writeln!(file, "Environment=RESTIC_PASSWORD_COMMAND=\"{}\"", value)?;
-    }
-    if let Some(value) = &repository.aws_access_key {
-        writeln!(file, "Environment=AWS_ACCESS_KEY=\"{}\"", value)?;
-    }
-    if let Some(value) = &repository.aws_secret_access_key {
-        writeln!(file, "Environment=AWS_SECRET_ACCESS_KEY=\"{}\"", value)?;
+    // The generator equivalent of WantedBy=timers.target: enable the timers
+    let wants = dir.join("timers.target.wants");
+    for unit in units.iter().filter(|unit| unit.name.ends_with(".timer")) {
+        if !wants.exists() {
+            fs::create_dir(&wants)
+                .with_context(|| format!("{}: error creating directory", wants.display()))?;
+        }
+        let link = wants.join(&unit.name);
+        std::os::unix::fs::symlink(dir.join(&unit.name), &link)
+            .with_context(|| format!("{}: error creating symlink", link.display()))?;
     }
-    writeln!(file, "Type=oneshot")?;
-    writeln!(file, "ExecStartPre=restic unlock")?;
-    writeln!(
-        file,
-        "ExecStart={}",
-        backup_cmd(
-            &config.source,
-            config.host.as_deref().unwrap_or(&context.hostname),
-            config.exclude.as_slice()
-        )
-    )?;
-    // 3 is returned when a file cannot be read (e.g. it is removed during the backup.)
-    writeln!(file, "SuccessExitStatus=3",)?;
-    writeln!(file, "Nice=10",)?;
-    writeln!(file, "IOSchedulingClass=idle",)?;
     Ok(())
 }
 
-fn generate_forget_service(
-    path: &Path,
-    context: &Context,
-    config: &Config,
-    repository: &RepositoryConfig,
-) -> anyhow::Result<()> {
-    if !repository.has_forget_policy() {
-        return Ok(());
-    }
-    let mut file = fs::File::create(path)
-        .with_context(|| format!("{}: error creating file", path.display()))?;
-    writeln!(file, "# generated by {}", context.program_name)?;
-    writeln!(file, "[Unit]",)?;
-    writeln!(
-        file,
-        "Description=forget {} from {}",
-        &config.source, &repository.location
-    )?;
-    writeln!(file, "SourcePath={}", context.config_path.display())?;
-    writeln!(file)?;
-    writeln!(file, "[Service]")?;
-    writeln!(
-        file,
-        "Environment=RESTIC_REPOSITORY=\"{}\"",
-        repository.location
-    )?;
-    if let Some(value) = &repository.password_file {
-        writeln!(file, "Environment=RESTIC_PASSWORD_FILE=\"{}\"", value)?;
-    }
-    if let Some(value) = &repository.password_command {
-        writeln!(file, "Environment=RESTIC_PASSWORD_COMMAND=\"{}\"", value)?;
-    }
-    if let Some(value) = &repository.aws_access_key {
-        writeln!(file, "Environment=AWS_ACCESS_KEY=\"{}\"", value)?;
+/// Write the units to an arbitrary directory, creating it if needed, and
+/// report what was done with each file
+fn render_units(
+    out: &mut impl Write,
+    dir: &Path,
+    units: &[Unit],
+    overwrite: Overwrite,
+) -> Result<()> {
+    fs::create_dir_all(dir)
+        .with_context(|| format!("{}: error creating directory", dir.display()))?;
+    if overwrite == Overwrite::Fail {
+        for unit in units {
+            let path = dir.join(&unit.name);
+            if path.exists() {
+                anyhow::bail!(
+                    "{}: file exists (use --force to overwrite or --skip-existing to keep it)",
+                    path.display()
+                );
+            }
+        }
     }
-    if let Some(value) = &repository.aws_secret_access_key {
-        writeln!(file, "Environment=AWS_SECRET_ACCESS_KEY=\"{}\"", value)?;
+    let (mut written, mut skipped) = (0, 0);
+    for unit in units {
+        let path = dir.join(&unit.name);
+        if overwrite == Overwrite::Skip && path.exists() {
+            writeln!(out, "skipped {}", path.display())?;
+            skipped += 1;
+            continue;
+        }
+        fs::write(&path, &unit.content)
+            .with_context(|| format!("{}: error creating file", path.display()))?;
+        if unit.executable {
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+                .with_context(|| format!("{}: error making file executable", path.display()))?;
+        }
+        writeln!(out, "wrote {}", path.display())?;
+        written += 1;
     }
-    writeln!(file, "Type=oneshot")?;
-    writeln!(file, "ExecStartPre=restic unlock")?;
-    writeln!(
-        file,
-        "ExecStart={}",
-        forget_cmd(
-            config.host.as_deref().unwrap_or(&context.hostname),
-            &config.source,
-            repository
-        )
-    )?;
-    writeln!(file, "Nice=10",)?;
-    writeln!(file, "IOSchedulingClass=idle",)?;
+    writeln!(out, "{} file(s) written, {} skipped", written, skipped)?;
     Ok(())
 }
 
-fn generate_prune_service(
-    path: &Path,
-    context: &Context,
-    _config: &Config,
-    repository: &RepositoryConfig,
-) -> anyhow::Result<()> {
-    if !repository.has_forget_policy() {
-        return Ok(());
-    }
-    let mut file = fs::File::create(path)
-        .with_context(|| format!("{}: error creating file", path.display()))?;
-    writeln!(file, "# generated by {}", context.program_name)?;
-    writeln!(file, "[Unit]",)?;
-    writeln!(file, "Description=Prune {}", &repository.location)?;
-    writeln!(file, "SourcePath={}", context.config_path.display())?;
-    writeln!(file)?;
-    writeln!(file, "[Service]")?;
-    writeln!(
-        file,
-        "Environment=RESTIC_REPOSITORY=\"{}\"",
-        repository.location
-    )?;
-    if let Some(value) = &repository.password_file {
-        writeln!(file, "Environment=RESTIC_PASSWORD_FILE=\"{}\"", value)?;
-    }
-    if let Some(value) = &repository.password_command {
-        writeln!(file, "Environment=RESTIC_PASSWORD_COMMAND=\"{}\"", value)?;
-    }
-    if let Some(value) = &repository.aws_access_key {
-        writeln!(file, "Environment=AWS_ACCESS_KEY=\"{}\"", value)?;
-    }
-    if let Some(value) = &repository.aws_secret_access_key {
-        writeln!(file, "Environment=AWS_SECRET_ACCESS_KEY=\"{}\"", value)?;
+/// Print the units one after the other, each preceded by a header line with its name
+fn print_units(out: &mut impl Write, units: &[Unit]) -> Result<()> {
+    for (i, unit) in units.iter().enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        writeln!(out, "### {}", unit.name)?;
+        out.write_all(&unit.content)?;
     }
-    writeln!(file, "Type=oneshot")?;
-    writeln!(file, "ExecStartPre=restic unlock")?;
-    writeln!(file, "ExecStart=restic prune")?;
-    writeln!(file, "Nice=10")?;
-    writeln!(file, "IOSchedulingClass=idle")?;
     Ok(())
 }
 
-fn is_local_repository(location: &str) -> bool {
-    !location.starts_with("azure:")
-        && !location.starts_with("b2:")
-        && !location.starts_with("gs:")
-        && !location.starts_with("rclone:")
-        && !location.starts_with("s3:")
-        && !location.starts_with("sftp:")
-        && !location.starts_with("swift:")
+/// Where the path to the configuration file came from.
+#[derive(Debug, PartialEq, Eq)]
+enum ConfigOrigin {
+    Flag,
+    Env,
+    Default,
 }
 
-/// A macro that pushes the given value serialized with the given format if the value is Some
-macro_rules! pushopt {
-    ($vec:expr, $format:expr, $value:expr) => {
-        if let Some(value) = $value {
-            $vec.push(format!($format, value));
+impl std::fmt::Display for ConfigOrigin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigOrigin::Flag => write!(f, "from --config"),
+            ConfigOrigin::Env => write!(f, "from RESTIC_GENERATOR_CONFIG"),
+            ConfigOrigin::Default => write!(f, "default"),
         }
-    };
+    }
 }
 
-fn backup_cmd<T: AsRef<str>>(source: &str, host: &str, exclude: &[T]) -> String {
-    let mut result = vec![
-        format!("restic"),
-        format!("backup"),
-        format!("--host=\"{}\"", host),
-    ];
-    for pattern in exclude.iter() {
-        result.push(format!("--exclude=\"{}\"", pattern.as_ref()));
+/// Pick the configuration file: the --config flag wins over the environment
+/// variable, which wins over the default location.
+fn resolve_config_path(
+    flag: Option<PathBuf>,
+    env: Option<OsString>,
+    user: bool,
+) -> Result<(PathBuf, ConfigOrigin)> {
+    if let Some(path) = flag {
+        return Ok((path, ConfigOrigin::Flag));
+    }
+    match env {
+        Some(value) if !value.is_empty() => Ok((PathBuf::from(value), ConfigOrigin::Env)),
+        _ => Ok((default_config_path(user)?, ConfigOrigin::Default)),
     }
-    result.push(source.to_string());
-    result.join(" ")
 }
 
-fn forget_cmd(host: &str, path: &str, repository: &RepositoryConfig) -> String {
-    let mut result = vec![
-        format!("restic"),
-        format!("forget"),
-        format!("--host=\"{}\"", host),
-        format!("--path=\"{}\"", path),
-    ];
-    pushopt!(result, "--keep-last=\"{}\"", repository.keep_last);
-    pushopt!(result, "--keep-hourly=\"{}\"", repository.keep_hourly);
-    pushopt!(result, "--keep-daily=\"{}\"", repository.keep_daily);
-    pushopt!(result, "--keep-weekly=\"{}\"", repository.keep_weekly);
-    pushopt!(result, "--keep-monthly=\"{}\"", repository.keep_monthly);
-    pushopt!(result, "--keep-yearly=\"{}\"", repository.keep_yearly);
-    pushopt!(result, "--keep-tag=\"{}\"", &repository.keep_tag);
-    pushopt!(result, "--keep-within=\"{}\"", &repository.keep_within);
-    result.join(" ")
+/// The first configuration file of the search path that exists, or the
+/// first one if none does
+fn default_config_path(user: bool) -> Result<PathBuf> {
+    let candidates = search_path(user, env::var_os("XDG_CONFIG_HOME"), env::var_os("HOME"))?;
+    Ok(candidates
+        .iter()
+        .find(|path| path.exists())
+        .unwrap_or(&candidates[0])
+        .clone())
+}
+
+/// Where the configuration is looked for: in `$XDG_CONFIG_HOME` then
+/// `~/.config` for user units, then in `/etc`
+fn search_path(
+    user: bool,
+    xdg_config_home: Option<OsString>,
+    home: Option<OsString>,
+) -> Result<Vec<PathBuf>> {
+    let mut candidates = Vec::new();
+    if user {
+        // Relative values are invalid according to the XDG specification
+        let xdg_config_home = xdg_config_home
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute());
+        let home = home.filter(|home| !home.is_empty()).map(PathBuf::from);
+        if xdg_config_home.is_none() && home.is_none() {
+            anyhow::bail!("HOME environment variable not found");
+        }
+        candidates.extend(xdg_config_home.map(|dir| dir.join("restic-generator/config.toml")));
+        candidates.extend(home.map(|home| home.join(".config/restic-generator/config.toml")));
+    }
+    candidates.push(PathBuf::from("/etc/restic-generator/config.toml"));
+    Ok(candidates)
 }
 
 #[cfg(test)]
@@ -283,73 +506,54 @@ mod tests {
     use super::*;
 
     #[test]
-    fn backup_cmd_default() {
-        assert_eq!(
-            backup_cmd::<&str>("/", "laptop", &[]),
-            r#"restic backup --host="laptop" /"#
-        );
+    fn config_flag_wins_over_env() {
+        let (path, origin) =
+            resolve_config_path(Some("flag.toml".into()), Some("env.toml".into()), false).unwrap();
+        assert_eq!(path, PathBuf::from("flag.toml"));
+        assert_eq!(origin, ConfigOrigin::Flag);
     }
 
     #[test]
-    fn backup_cmd_exclude() {
-        assert_eq!(
-            backup_cmd::<&str>("/", "laptop", &["foo", "bar.baz"]),
-            r#"restic backup --host="laptop" --exclude="foo" --exclude="bar.baz" /"#
-        );
+    fn config_env_wins_over_default() {
+        let (path, origin) = resolve_config_path(None, Some("env.toml".into()), false).unwrap();
+        assert_eq!(path, PathBuf::from("env.toml"));
+        assert_eq!(origin, ConfigOrigin::Env);
     }
 
     #[test]
-    fn backup_cmd_with_host() {
-        assert_eq!(
-            backup_cmd::<&str>("/", "laptop", &[]),
-            r#"restic backup --host="laptop" /"#
-        );
+    fn config_empty_env_is_ignored() {
+        let (path, origin) = resolve_config_path(None, Some("".into()), false).unwrap();
+        assert_eq!(path, PathBuf::from("/etc/restic-generator/config.toml"));
+        assert_eq!(origin, ConfigOrigin::Default);
     }
 
-    macro_rules! test_forget_cmd {
-        ($testname:ident, $attr:ident: $value:expr, $expected:expr) => {
-            #[test]
-            fn $testname() {
-                let repo = RepositoryConfig {
-                    $attr: Some($value),
-                    ..Default::default()
-                };
-                assert_eq!(forget_cmd("laptop", "/", &repo), $expected);
-            }
-        };
+    #[test]
+    fn user_search_path() {
+        let paths = search_path(true, Some("/xdg".into()), Some("/home/me".into())).unwrap();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("/xdg/restic-generator/config.toml"),
+                PathBuf::from("/home/me/.config/restic-generator/config.toml"),
+                PathBuf::from("/etc/restic-generator/config.toml"),
+            ]
+        );
+        let paths = search_path(true, Some("relative".into()), Some("/home/me".into())).unwrap();
+        assert_eq!(
+            paths[0],
+            PathBuf::from("/home/me/.config/restic-generator/config.toml")
+        );
+        assert!(search_path(true, None, None).is_err());
+        assert_eq!(
+            search_path(false, Some("/xdg".into()), None).unwrap(),
+            vec![PathBuf::from("/etc/restic-generator/config.toml")]
+        );
     }
 
-    test_forget_cmd!(forget_cmd_keep_last, keep_last: 42, r#"restic forget --host="laptop" --path="/" --keep-last="42""#);
-    test_forget_cmd!(forget_cmd_keep_hourly, keep_hourly: 42, r#"restic forget --host="laptop" --path="/" --keep-hourly="42""#);
-    test_forget_cmd!(forget_cmd_keep_daily, keep_daily: 42, r#"restic forget --host="laptop" --path="/" --keep-daily="42""#);
-    test_forget_cmd!(forget_cmd_keep_weekly, keep_weekly: 42, r#"restic forget --host="laptop" --path="/" --keep-weekly="42""#);
-    test_forget_cmd!(forget_cmd_keep_monthly, keep_monthly: 42, r#"restic forget --host="laptop" --path="/" --keep-monthly="42""#);
-    test_forget_cmd!(forget_cmd_keep_yearly, keep_yearly: 42, r#"restic forget --host="laptop" --path="/" --keep-yearly="42""#);
-    test_forget_cmd!(forget_cmd_keep_tag, keep_tag: "important".into(), r#"restic forget --host="laptop" --path="/" --keep-tag="important""#);
-    test_forget_cmd!(forget_cmd_keep_within, keep_within: "2y5m7d3h".into(), r#"restic forget --host="laptop" --path="/" --keep-within="2y5m7d3h""#);
-
-    macro_rules! test_is_local_repository {
-        ($name:ident, $location:expr) => {
-            #[test]
-            fn $name() {
-                assert!(is_local_repository($location));
-            }
-        };
-        (!$name:ident, $location:expr) => {
-            #[test]
-            fn $name() {
-                assert!(!is_local_repository($location));
-            }
-        };
+    #[test]
+    fn config_system_default() {
+        let (path, origin) = resolve_config_path(None, None, false).unwrap();
+        assert_eq!(path, PathBuf::from("/etc/restic-generator/config.toml"));
+        assert_eq!(origin, ConfigOrigin::Default);
     }
-
-    test_is_local_repository!(abs_path_is_local, "/media/backup");
-    test_is_local_repository!(systmed_home_is_local, "%h/backup");
-    test_is_local_repository!(!sftp_is_not_local, "sftp:user@host:/srv/restic-repo");
-    test_is_local_repository!(!s3_is_not_local, "s3:s3.amazonaws.com/bucket_name");
-    test_is_local_repository!(!swift_is_not_local, "swift:container_name:/path");
-    test_is_local_repository!(!b2_is_not_local, "b2:bucketname:path/to/repo");
-    test_is_local_repository!(!azure_is_not_local, "azure:foo:/");
-    test_is_local_repository!(!gs_is_not_local, "gs:foo:/");
-    test_is_local_repository!(!rclone_is_not_local, "rclone:foo:bar");
 }
@@ -0,0 +1,119 @@
+use serde::Serialize;
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::config::Config;
+use crate::units::{self, Context, UnitKind};
+
+/// The name of the manifest written next to the generated units
+pub const FILE_NAME: &str = "restic-generator.manifest.json";
+
+/// The inventory of the files produced from a configuration
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Manifest {
+    pub generator: String,
+    pub config: PathBuf,
+    /// A hash of the configuration files, to tell which version the units come from
+    pub config_hash: String,
+    pub files: Vec<File>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct File {
+    pub path: PathBuf,
+    #[serde(rename = "type")]
+    pub kind: UnitKind,
+    pub repository: String,
+}
+
+impl Manifest {
+    /// Describe the units generated from `config` in `dir`
+    pub fn new(dir: &Path, context: &Context, content: &[u8], config: &Config) -> Manifest {
+        let files = units::plan(context, config)
+            .into_iter()
+            .filter(|planned| planned.skipped.is_none())
+            .map(|planned| File {
+                path: dir.join(&planned.name),
+                kind: planned.kind,
+                repository: planned.repository.name.clone(),
+            })
+            .collect();
+        Manifest {
+            generator: format!("{} {}", env!("CARGO_BIN_NAME"), env!("CARGO_PKG_VERSION")),
+            config: context.config_path.clone(),
+            config_hash: format!("{:016x}", fnv1a(content)),
+            files,
+        }
+    }
+
+    pub fn write(&self, out: &mut impl Write) -> anyhow::Result<()> {
+        serde_json::to_writer_pretty(&mut *out, self)?;
+        writeln!(out)?;
+        Ok(())
+    }
+}
+
+/// The 64-bit FNV-1a hash, which is stable across releases unlike `std`'s hasher
+fn fnv1a(data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in data {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RepositoryConfig;
+    use crate::units::tests::context;
+
+    #[test]
+    fn fnv1a_reference_values() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn lists_generated_units() {
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "/repo".into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let manifest = Manifest::new(
+            Path::new("/run/systemd/generator"),
+            &context(),
+            b"",
+            &config,
+        );
+        let mut out = Vec::new();
+        manifest.write(&mut out).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json["config-hash"], "cbf29ce484222325");
+        assert_eq!(
+            json["files"],
+            serde_json::json!([
+                {
+                    "path": "/run/systemd/generator/restic-myrepo-backup.service",
+                    "type": "backup",
+                    "repository": "myrepo",
+                },
+                {
+                    "path": "/run/systemd/generator/restic-myrepo-unlock.service",
+                    "type": "unlock",
+                    "repository": "myrepo",
+                },
+            ])
+        );
+    }
+}
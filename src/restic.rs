@@ -0,0 +1,992 @@
+use crate::config::{DataSubset, RepositoryConfig, Retention, Size};
+
+use std::fmt;
+
+use crate::environment::shell_quote;
+
+/// A macro that adds the given option to the command if the value is Some
+macro_rules! pushopt {
+    ($cmd:expr, $name:expr, $value:expr) => {
+        if let Some(value) = $value {
+            $cmd.option($name, value);
+        }
+    };
+}
+
+/// A restic command line. It is displayed the way it is written in the
+/// units, with option values quoted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cmd {
+    args: Vec<Arg>,
+    /// The words after `--`, following the options whenever they are added
+    trailing: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Arg {
+    Word(String),
+    Option(&'static str, String),
+    /// A systemd specifier, e.g. `%i`, written as is in the units
+    Specifier(&'static str),
+}
+
+impl Cmd {
+    pub fn new(subcommand: &str) -> Cmd {
+        Cmd {
+            args: vec![Arg::Word(subcommand.to_string())],
+            trailing: Vec::new(),
+        }
+    }
+
+    pub fn word(&mut self, value: impl Into<String>) -> &mut Cmd {
+        self.args.push(Arg::Word(value.into()));
+        self
+    }
+
+    /// Add a word after `--`, e.g. an argument of the command run by restic
+    pub fn trailing(&mut self, value: impl Into<String>) -> &mut Cmd {
+        self.trailing.push(value.into());
+        self
+    }
+
+    pub fn specifier(&mut self, specifier: &'static str) -> &mut Cmd {
+        self.args.push(Arg::Specifier(specifier));
+        self
+    }
+
+    pub fn option(&mut self, name: &'static str, value: impl fmt::Display) -> &mut Cmd {
+        self.args.push(Arg::Option(name, value.to_string()));
+        self
+    }
+
+    /// Add the backend options of `repository`
+    pub fn backend_options(&mut self, repository: &RepositoryConfig) -> &mut Cmd {
+        for (name, value) in repository.options.iter() {
+            self.option("--option", format!("{}={}", name, value));
+        }
+        self
+    }
+
+    /// Change the value of an option
+    pub fn set_option(&mut self, name: &'static str, value: impl fmt::Display) -> &mut Cmd {
+        for arg in self.args.iter_mut() {
+            if let Arg::Option(option, current) = arg {
+                if *option == name {
+                    *current = value.to_string();
+                }
+            }
+        }
+        self
+    }
+
+    pub fn subcommand(&self) -> &str {
+        match &self.args[0] {
+            Arg::Word(word) => word,
+            _ => unreachable!("commands start with the subcommand"),
+        }
+    }
+
+    /// The arguments to give to the restic executable
+    pub fn args(&self) -> Vec<String> {
+        let mut result: Vec<_> = self
+            .args
+            .iter()
+            .map(|arg| match arg {
+                Arg::Word(word) => word.clone(),
+                Arg::Option(name, value) => format!("{}={}", name, value),
+                Arg::Specifier(specifier) => specifier.to_string(),
+            })
+            .collect();
+        if !self.trailing.is_empty() {
+            result.push("--".into());
+            result.extend(self.trailing.iter().cloned());
+        }
+        result
+    }
+
+    /// The command line for a POSIX shell
+    pub fn to_shell(&self) -> String {
+        let mut result = String::from("restic");
+        for arg in self.args() {
+            result.push(' ');
+            result.push_str(&shell_quote(&arg));
+        }
+        result
+    }
+}
+
+impl fmt::Display for Cmd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "restic")?;
+        for arg in self.args.iter() {
+            match arg {
+                Arg::Word(word) => write!(f, " {}", quote_word(word))?,
+                Arg::Option(name, value) => {
+                    let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+                    let value = value.replace('%', "%%");
+                    write!(f, " {}=\"{}\"", name, value)?
+                }
+                Arg::Specifier(specifier) => write!(f, " {}", specifier)?,
+            }
+        }
+        if !self.trailing.is_empty() {
+            write!(f, " --")?;
+        }
+        for word in self.trailing.iter() {
+            write!(f, " {}", quote_word(word))?;
+        }
+        Ok(())
+    }
+}
+
+/// Quote a word for the Exec lines of a unit if it would be split or
+/// unescaped otherwise, and escape the `%` specifiers
+pub fn quote_word(word: &str) -> String {
+    let word = word.replace('%', "%%");
+    let special = |c: char| c.is_whitespace() || "\"'\\;".contains(c);
+    if !word.is_empty() && !word.contains(special) {
+        return word;
+    }
+    format!("\"{}\"", word.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The environment variables telling restic how to access the repository
+pub fn environment(repository: &RepositoryConfig) -> Vec<(&str, String)> {
+    let mut result = vec![("RESTIC_REPOSITORY", repository.location.clone())];
+    let optional = [
+        ("RESTIC_PASSWORD_FILE", &repository.password_file),
+        ("RESTIC_PASSWORD_COMMAND", &repository.password_command),
+    ];
+    for (name, value) in optional {
+        if let Some(value) = value {
+            result.push((name, value.clone()));
+        }
+    }
+    result.extend(credentials(repository));
+    if let Some(size) = repository.pack_size {
+        result.push(("RESTIC_PACK_SIZE", size.to_string()));
+    }
+    for (name, value) in repository.environment.iter() {
+        result.push((name, value.clone()));
+    }
+    result
+}
+
+/// The credentials of the cloud backends of `repository`
+pub fn credentials(repository: &RepositoryConfig) -> Vec<(&'static str, String)> {
+    let optional = [
+        ("AWS_ACCESS_KEY", &repository.aws_access_key),
+        ("AWS_SECRET_ACCESS_KEY", &repository.aws_secret_access_key),
+        ("AZURE_ACCOUNT_NAME", &repository.azure_account_name),
+        ("AZURE_ACCOUNT_KEY", &repository.azure_account_key),
+        ("AZURE_ACCOUNT_SAS", &repository.azure_account_sas),
+    ];
+    let optional = optional
+        .iter()
+        .filter_map(|(name, value)| Some((*name, (*value).clone()?)));
+    optional.collect()
+}
+
+/// The backend options of `repository`, as arguments of restic
+pub fn option_args(repository: &RepositoryConfig) -> Vec<String> {
+    let options = repository.options.iter();
+    options
+        .map(|(name, value)| format!("--option={}={}", name, value))
+        .collect()
+}
+
+/// How the commands of restic-generator itself reach a repository
+#[derive(Debug, Default)]
+pub struct Access {
+    /// The backend options, e.g. `s3.region=eu-west-1`
+    pub options: Vec<String>,
+    /// The environment variables set besides the inherited ones
+    pub environment: Vec<(String, String)>,
+}
+
+impl Access {
+    /// The access given by the configuration of `repository`
+    pub fn of(repository: &RepositoryConfig) -> Access {
+        let options = repository.options.iter();
+        let environment = environment(repository).into_iter();
+        Access {
+            options: options
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect(),
+            environment: environment
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+        }
+    }
+
+    /// The backend options, as arguments of restic
+    pub fn option_args(&self) -> Vec<String> {
+        let options = self.options.iter();
+        options
+            .map(|option| format!("--option={}", option))
+            .collect()
+    }
+}
+
+/// The environment variables telling restic copy where to copy from
+///
+/// restic reads the credentials and the `environment` of both repositories
+/// from the same variables, so they are exported under their own names.
+pub fn from_environment(repository: &RepositoryConfig) -> Vec<(&str, String)> {
+    let mut result = vec![("RESTIC_FROM_REPOSITORY", repository.location.clone())];
+    let optional = [
+        ("RESTIC_FROM_PASSWORD_FILE", &repository.password_file),
+        ("RESTIC_FROM_PASSWORD_COMMAND", &repository.password_command),
+    ];
+    for (name, value) in optional {
+        if let Some(value) = value {
+            result.push((name, value.clone()));
+        }
+    }
+    result.extend(credentials(repository));
+    for (name, value) in repository.environment.iter() {
+        result.push((name, value.clone()));
+    }
+    result
+}
+
+/// The backup options besides the host and exclude patterns
+#[derive(Debug, Default)]
+pub struct BackupOptions<'a> {
+    pub dry_run: bool,
+    pub files_from: &'a [String],
+    pub files_from_verbatim: &'a [String],
+    pub iexclude: Vec<&'a str>,
+    pub exclude_files: Vec<&'a str>,
+    pub iexclude_files: Vec<&'a str>,
+    pub exclude_caches: bool,
+    pub exclude_if_present: Vec<&'a str>,
+    pub exclude_larger_than: Option<&'a Size>,
+    pub one_file_system: bool,
+    pub read_concurrency: Option<u32>,
+    pub no_scan: bool,
+    pub ignore_inode: bool,
+    pub ignore_ctime: bool,
+    pub with_atime: bool,
+    pub skip_if_unchanged: bool,
+    pub tags: &'a [String],
+}
+
+pub fn backup_cmd<S: AsRef<str>, T: AsRef<str>>(
+    source: &[S],
+    host: &str,
+    exclude: &[T],
+    options: &BackupOptions,
+) -> Cmd {
+    let mut result = Cmd::new("backup");
+    if options.dry_run {
+        result.word("--dry-run");
+    }
+    result.option("--host", host);
+    for pattern in exclude.iter() {
+        result.option("--exclude", pattern.as_ref());
+    }
+    for pattern in options.iexclude.iter() {
+        result.option("--iexclude", pattern);
+    }
+    for path in options.exclude_files.iter() {
+        result.option("--exclude-file", path);
+    }
+    for path in options.iexclude_files.iter() {
+        result.option("--iexclude-file", path);
+    }
+    if options.exclude_caches {
+        result.word("--exclude-caches");
+    }
+    for name in options.exclude_if_present.iter() {
+        result.option("--exclude-if-present", name);
+    }
+    if let Some(size) = options.exclude_larger_than {
+        result.option("--exclude-larger-than", size);
+    }
+    if options.one_file_system {
+        result.word("--one-file-system");
+    }
+    pushopt!(result, "--read-concurrency", options.read_concurrency);
+    if options.no_scan {
+        result.word("--no-scan");
+    }
+    for path in options.files_from.iter() {
+        result.option("--files-from", path);
+    }
+    for path in options.files_from_verbatim.iter() {
+        result.option("--files-from-verbatim", path);
+    }
+    if options.ignore_inode {
+        result.word("--ignore-inode");
+    }
+    if options.ignore_ctime {
+        result.word("--ignore-ctime");
+    }
+    if options.with_atime {
+        result.word("--with-atime");
+    }
+    if options.skip_if_unchanged {
+        result.word("--skip-if-unchanged");
+    }
+    for tag in options.tags.iter() {
+        result.option("--tag", tag);
+    }
+    for path in source {
+        result.word(path.as_ref());
+    }
+    result
+}
+
+/// Back up the output of `command`, stored in the snapshot as `filename`.
+/// Of the options, only the ones not about the files apply.
+pub fn stdin_backup_cmd(
+    command: &[String],
+    filename: Option<&str>,
+    host: &str,
+    options: &BackupOptions,
+) -> Cmd {
+    let mut result = Cmd::new("backup");
+    if options.dry_run {
+        result.word("--dry-run");
+    }
+    result.option("--host", host);
+    result.word("--stdin-from-command");
+    pushopt!(result, "--stdin-filename", filename);
+    for tag in options.tags.iter() {
+        result.option("--tag", tag);
+    }
+    for word in command {
+        result.trailing(word.as_str());
+    }
+    result
+}
+
+/// Remove the excluded files from the snapshots of `source` made by `host`,
+/// replacing the original snapshots
+///
+/// Only the patterns of the `options` apply: restic cannot tell the caches,
+/// the directories with a marker file or the large files apart afterwards.
+pub fn rewrite_cmd<S: AsRef<str>, T: AsRef<str>>(
+    host: &str,
+    source: &[S],
+    exclude: &[T],
+    options: &BackupOptions,
+) -> Cmd {
+    let mut result = Cmd::new("rewrite");
+    result.option("--host", host);
+    for path in source {
+        result.option("--path", path.as_ref());
+    }
+    for pattern in exclude.iter() {
+        result.option("--exclude", pattern.as_ref());
+    }
+    for pattern in options.iexclude.iter() {
+        result.option("--iexclude", pattern);
+    }
+    for path in options.exclude_files.iter() {
+        result.option("--exclude-file", path);
+    }
+    for path in options.iexclude_files.iter() {
+        result.option("--iexclude-file", path);
+    }
+    result.word("--forget");
+    result
+}
+
+/// Remove the stale locks, or all of them with `remove_all`
+pub fn unlock_cmd(remove_all: bool) -> Cmd {
+    let mut result = Cmd::new("unlock");
+    if remove_all {
+        result.word("--remove-all");
+    }
+    result
+}
+
+/// List the `count` latest snapshots of each set of paths backed up by
+/// `host`, only the ones of `source` if not empty
+pub fn latest_snapshots_cmd<S: AsRef<str>>(host: &str, source: &[S], count: usize) -> Cmd {
+    let mut result = Cmd::new("snapshots");
+    result.option("--host", host);
+    for path in source {
+        result.option("--path", path.as_ref());
+    }
+    result.option("--latest", count).word("--json");
+    result
+}
+
+/// Forget the snapshots of `source` made by `host`, only the ones with all
+/// the `tags` if there are some
+pub fn forget_cmd<S: AsRef<str>>(
+    host: &str,
+    source: &[S],
+    tags: &[&str],
+    retention: &Retention,
+) -> Cmd {
+    let mut result = Cmd::new("forget");
+    result.option("--host", host);
+    for path in source {
+        result.option("--path", path.as_ref());
+    }
+    if !tags.is_empty() {
+        // Separated by commas, the tags must all be present
+        result.option("--tag", tags.join(","));
+    }
+    pushopt!(result, "--keep-last", retention.keep_last);
+    pushopt!(result, "--keep-hourly", retention.keep_hourly);
+    pushopt!(result, "--keep-daily", retention.keep_daily);
+    pushopt!(result, "--keep-weekly", retention.keep_weekly);
+    pushopt!(result, "--keep-monthly", retention.keep_monthly);
+    pushopt!(result, "--keep-yearly", retention.keep_yearly);
+    for tag in retention.keep_tag.iter().flatten() {
+        result.option("--keep-tag", tag);
+    }
+    pushopt!(result, "--keep-within", &retention.keep_within);
+    pushopt!(
+        result,
+        "--keep-within-hourly",
+        &retention.keep_within_hourly
+    );
+    pushopt!(result, "--keep-within-daily", &retention.keep_within_daily);
+    pushopt!(
+        result,
+        "--keep-within-weekly",
+        &retention.keep_within_weekly
+    );
+    pushopt!(
+        result,
+        "--keep-within-monthly",
+        &retention.keep_within_monthly
+    );
+    pushopt!(
+        result,
+        "--keep-within-yearly",
+        &retention.keep_within_yearly
+    );
+    pushopt!(result, "--group-by", &retention.group_by);
+    result
+}
+
+pub fn prune_cmd(repository: &RepositoryConfig) -> Cmd {
+    let mut result = Cmd::new("prune");
+    pushopt!(result, "--max-unused", &repository.max_unused);
+    if repository.repack_uncompressed {
+        result.word("--repack-uncompressed");
+    }
+    if repository.repack_small {
+        result.word("--repack-small");
+    }
+    pushopt!(result, "--max-repack-size", &repository.max_repack_size);
+    result
+}
+
+pub fn check_cmd(repository: &RepositoryConfig) -> Cmd {
+    let mut result = Cmd::new("check");
+    if repository.check_with_cache {
+        result.word("--with-cache");
+    }
+    match &repository.check_read_data_subset {
+        // The units read the parts in turn, other schedulers as many random
+        // subsets of the same size
+        Some(DataSubset::Rotation(parts)) => {
+            let percentage = format!("{:.2}", 100.0 / *parts as f64);
+            let percentage = percentage.trim_end_matches('0').trim_end_matches('.');
+            result.option("--read-data-subset", format!("{}%", percentage));
+        }
+        Some(DataSubset::Random(subset)) => {
+            result.option("--read-data-subset", subset);
+        }
+        None if repository.check_read_data => {
+            result.word("--read-data");
+        }
+        None => (),
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn environment_minimal() {
+        let repo = RepositoryConfig {
+            location: "/repo".into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            environment(&repo),
+            vec![("RESTIC_REPOSITORY", "/repo".into())]
+        );
+    }
+
+    #[test]
+    fn environment_full() {
+        let repo = RepositoryConfig {
+            location: "s3:host/bucket".into(),
+            password_file: Some("/password".into()),
+            password_command: Some("pass restic".into()),
+            aws_access_key: Some("ABCDEF".into()),
+            aws_secret_access_key: Some("s3cr3ts".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            environment(&repo),
+            vec![
+                ("RESTIC_REPOSITORY", "s3:host/bucket".into()),
+                ("RESTIC_PASSWORD_FILE", "/password".into()),
+                ("RESTIC_PASSWORD_COMMAND", "pass restic".into()),
+                ("AWS_ACCESS_KEY", "ABCDEF".into()),
+                ("AWS_SECRET_ACCESS_KEY", "s3cr3ts".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn environment_azure() {
+        let repo = RepositoryConfig {
+            location: "azure:backups:/".into(),
+            azure_account_name: Some("myaccount".into()),
+            azure_account_sas: Some("sv=2021&sig=abc".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            environment(&repo),
+            vec![
+                ("RESTIC_REPOSITORY", "azure:backups:/".into()),
+                ("AZURE_ACCOUNT_NAME", "myaccount".into()),
+                ("AZURE_ACCOUNT_SAS", "sv=2021&sig=abc".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn environment_pack_size() {
+        let repo = RepositoryConfig {
+            location: "s3:host/bucket".into(),
+            pack_size: Some(64),
+            ..Default::default()
+        };
+        assert_eq!(
+            environment(&repo),
+            vec![
+                ("RESTIC_REPOSITORY", "s3:host/bucket".into()),
+                ("RESTIC_PACK_SIZE", "64".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn backend_options() {
+        let repo = RepositoryConfig {
+            location: "s3:host/bucket".into(),
+            options: [("s3.storage-class".to_string(), "STANDARD_IA".to_string())].into(),
+            ..Default::default()
+        };
+        let mut cmd = Cmd::new("snapshots");
+        cmd.backend_options(&repo);
+        assert_eq!(
+            cmd.to_string(),
+            r#"restic snapshots --option="s3.storage-class=STANDARD_IA""#
+        );
+        assert_eq!(
+            option_args(&repo),
+            vec!["--option=s3.storage-class=STANDARD_IA"]
+        );
+    }
+
+    #[test]
+    fn environment_extra_variables() {
+        let repo = RepositoryConfig {
+            location: "/repo".into(),
+            environment: [("GOGC".to_string(), "20".to_string())].into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            environment(&repo),
+            vec![("RESTIC_REPOSITORY", "/repo".into()), ("GOGC", "20".into())]
+        );
+    }
+
+    #[test]
+    fn from_environment_variables() {
+        let repo = RepositoryConfig {
+            location: "/repo".into(),
+            password_file: Some("/password".into()),
+            aws_access_key: Some("ABCDEF".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            from_environment(&repo),
+            vec![
+                ("RESTIC_FROM_REPOSITORY", "/repo".into()),
+                ("RESTIC_FROM_PASSWORD_FILE", "/password".into()),
+                ("AWS_ACCESS_KEY", "ABCDEF".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn backup_cmd_default() {
+        assert_eq!(
+            backup_cmd::<_, &str>(&["/"], "laptop", &[], &BackupOptions::default()).to_string(),
+            r#"restic backup --host="laptop" /"#
+        );
+    }
+
+    #[test]
+    fn backup_cmd_exclude() {
+        assert_eq!(
+            backup_cmd::<_, &str>(
+                &["/"],
+                "laptop",
+                &["foo", "bar.baz"],
+                &BackupOptions::default()
+            )
+            .to_string(),
+            r#"restic backup --host="laptop" --exclude="foo" --exclude="bar.baz" /"#
+        );
+    }
+
+    #[test]
+    fn backup_cmd_with_host() {
+        assert_eq!(
+            backup_cmd::<_, &str>(&["/"], "laptop", &[], &BackupOptions::default()).to_string(),
+            r#"restic backup --host="laptop" /"#
+        );
+    }
+
+    #[test]
+    fn backup_cmd_tags() {
+        let tags = vec!["job:photos".to_string()];
+        let options = BackupOptions {
+            tags: &tags,
+            ..Default::default()
+        };
+        assert_eq!(
+            backup_cmd(&["/"], "laptop", &["*~"], &options).to_string(),
+            r#"restic backup --host="laptop" --exclude="*~" --tag="job:photos" /"#
+        );
+    }
+
+    #[test]
+    fn backup_cmd_exclude_files() {
+        let options = BackupOptions {
+            exclude_files: vec!["/etc/restic/excludes"],
+            iexclude_files: vec!["/etc/restic/iexcludes"],
+            exclude_caches: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            backup_cmd::<_, &str>(&["/"], "laptop", &[], &options).to_string(),
+            r#"restic backup --host="laptop" --exclude-file="/etc/restic/excludes" --iexclude-file="/etc/restic/iexcludes" --exclude-caches /"#
+        );
+    }
+
+    #[test]
+    fn backup_cmd_exclude_if_present() {
+        let options = BackupOptions {
+            exclude_if_present: vec![".nobackup", "CACHEDIR.TAG:Signature"],
+            ..Default::default()
+        };
+        assert_eq!(
+            backup_cmd::<_, &str>(&["/"], "laptop", &[], &options).to_string(),
+            r#"restic backup --host="laptop" --exclude-if-present=".nobackup" --exclude-if-present="CACHEDIR.TAG:Signature" /"#
+        );
+    }
+
+    #[test]
+    fn backup_cmd_exclude_larger_than() {
+        let size = "1G".parse().unwrap();
+        let options = BackupOptions {
+            exclude_larger_than: Some(&size),
+            ..Default::default()
+        };
+        assert_eq!(
+            backup_cmd::<_, &str>(&["/"], "laptop", &[], &options).to_string(),
+            r#"restic backup --host="laptop" --exclude-larger-than="1G" /"#
+        );
+    }
+
+    #[test]
+    fn backup_cmd_one_file_system() {
+        let options = BackupOptions {
+            one_file_system: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            backup_cmd::<_, &str>(&["/", "/home"], "laptop", &[], &options).to_string(),
+            r#"restic backup --host="laptop" --one-file-system / /home"#
+        );
+    }
+
+    #[test]
+    fn backup_cmd_files_from() {
+        let files_from = ["/etc/restic/include list.txt".to_string()];
+        let files_from_verbatim = ["/var/lib/restic/paths".to_string()];
+        let options = BackupOptions {
+            files_from: &files_from,
+            files_from_verbatim: &files_from_verbatim,
+            ..Default::default()
+        };
+        assert_eq!(
+            backup_cmd::<&str, &str>(&[], "laptop", &[], &options).to_string(),
+            r#"restic backup --host="laptop" --files-from="/etc/restic/include list.txt" --files-from-verbatim="/var/lib/restic/paths""#
+        );
+    }
+
+    #[test]
+    fn backup_cmd_dry_run() {
+        let options = BackupOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            backup_cmd::<_, &str>(&["/"], "laptop", &[], &options).to_string(),
+            r#"restic backup --dry-run --host="laptop" /"#
+        );
+    }
+
+    #[test]
+    fn backup_cmd_read_concurrency() {
+        let options = BackupOptions {
+            read_concurrency: Some(8),
+            ..Default::default()
+        };
+        assert_eq!(
+            backup_cmd::<_, &str>(&["/"], "laptop", &[], &options).to_string(),
+            r#"restic backup --host="laptop" --read-concurrency="8" /"#
+        );
+    }
+
+    #[test]
+    fn backup_cmd_no_scan() {
+        let options = BackupOptions {
+            no_scan: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            backup_cmd::<_, &str>(&["/"], "laptop", &[], &options).to_string(),
+            r#"restic backup --host="laptop" --no-scan /"#
+        );
+    }
+
+    #[test]
+    fn backup_cmd_change_detection() {
+        let options = BackupOptions {
+            ignore_inode: true,
+            ignore_ctime: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            backup_cmd::<_, &str>(&["/mnt/nfs"], "laptop", &[], &options).to_string(),
+            r#"restic backup --host="laptop" --ignore-inode --ignore-ctime /mnt/nfs"#
+        );
+    }
+
+    #[test]
+    fn backup_cmd_with_atime() {
+        let options = BackupOptions {
+            with_atime: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            backup_cmd::<_, &str>(&["/"], "laptop", &[], &options).to_string(),
+            r#"restic backup --host="laptop" --with-atime /"#
+        );
+    }
+
+    #[test]
+    fn backup_cmd_skip_if_unchanged() {
+        let options = BackupOptions {
+            skip_if_unchanged: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            backup_cmd::<_, &str>(&["/"], "laptop", &[], &options).to_string(),
+            r#"restic backup --host="laptop" --skip-if-unchanged /"#
+        );
+    }
+
+    #[test]
+    fn stdin_backup_cmd_options() {
+        let command = ["pg_dumpall".to_string(), "--clean".to_string()];
+        let tags = ["db".to_string()];
+        let options = BackupOptions {
+            tags: &tags,
+            one_file_system: true,
+            ..Default::default()
+        };
+        let mut cmd = stdin_backup_cmd(&command, Some("db.sql"), "server", &options);
+        cmd.option("--retry-lock", "300s");
+        assert_eq!(
+            cmd.to_string(),
+            r#"restic backup --host="server" --stdin-from-command --stdin-filename="db.sql" --tag="db" --retry-lock="300s" -- pg_dumpall --clean"#
+        );
+        assert_eq!(
+            cmd.to_shell(),
+            "restic backup --host=server --stdin-from-command --stdin-filename=db.sql --tag=db --retry-lock=300s -- pg_dumpall --clean"
+        );
+    }
+
+    #[test]
+    fn backup_cmd_iexclude() {
+        let options = BackupOptions {
+            iexclude: vec!["*.iso"],
+            ..Default::default()
+        };
+        assert_eq!(
+            backup_cmd(&["/"], "laptop", &["*~"], &options).to_string(),
+            r#"restic backup --host="laptop" --exclude="*~" --iexclude="*.iso" /"#
+        );
+    }
+
+    #[test]
+    fn multiple_paths() {
+        assert_eq!(
+            backup_cmd::<_, &str>(&["/home", "/etc"], "laptop", &[], &BackupOptions::default())
+                .to_string(),
+            r#"restic backup --host="laptop" /home /etc"#
+        );
+        assert_eq!(
+            forget_cmd("laptop", &["/home", "/etc"], &[], &Retention::default()).to_string(),
+            r#"restic forget --host="laptop" --path="/home" --path="/etc""#
+        );
+        assert_eq!(
+            forget_cmd(
+                "laptop",
+                &["/"],
+                &["system", "nightly"],
+                &Retention::default()
+            )
+            .to_string(),
+            r#"restic forget --host="laptop" --path="/" --tag="system,nightly""#
+        );
+    }
+
+    #[test]
+    fn backup_cmd_args() {
+        assert_eq!(
+            backup_cmd::<_, &str>(&["/"], "laptop", &["*~"], &BackupOptions::default()).args(),
+            vec!["backup", "--host=laptop", "--exclude=*~", "/"]
+        );
+    }
+
+    #[test]
+    fn backup_cmd_shell() {
+        assert_eq!(
+            backup_cmd::<_, &str>(&["/"], "laptop", &["*~", "it's"], &BackupOptions::default())
+                .to_shell(),
+            r#"restic backup --host=laptop '--exclude=*~' '--exclude=it'\''s' /"#
+        );
+    }
+
+    #[test]
+    fn cmd_escapes_quotes() {
+        let mut cmd = Cmd::new("backup");
+        cmd.option("--exclude", r#"a"b\c"#);
+        assert_eq!(cmd.to_string(), r#"restic backup --exclude="a\"b\\c""#);
+    }
+
+    #[test]
+    fn cmd_quotes_words() {
+        let mut cmd = Cmd::new("backup");
+        cmd.word("--tag").word("my tag").word(r#"a"b"#).word("");
+        assert_eq!(cmd.to_string(), r#"restic backup --tag "my tag" "a\"b" """#);
+    }
+
+    #[test]
+    fn prune_cmd_max_unused() {
+        let mut repo = RepositoryConfig::default();
+        assert_eq!(prune_cmd(&repo).to_string(), "restic prune");
+        repo.max_unused = Some("10%".parse().unwrap());
+        assert_eq!(
+            prune_cmd(&repo).to_string(),
+            r#"restic prune --max-unused="10%%""#
+        );
+    }
+
+    #[test]
+    fn prune_cmd_repack() {
+        let repo = RepositoryConfig {
+            repack_uncompressed: true,
+            repack_small: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            prune_cmd(&repo).to_string(),
+            "restic prune --repack-uncompressed --repack-small"
+        );
+    }
+
+    #[test]
+    fn prune_cmd_max_repack_size() {
+        let repo = RepositoryConfig {
+            max_repack_size: Some("10G".parse().unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(
+            prune_cmd(&repo).to_string(),
+            r#"restic prune --max-repack-size="10G""#
+        );
+    }
+
+    #[test]
+    fn check_cmd_subsets() {
+        let repo = |subset: &str| RepositoryConfig {
+            check_read_data_subset: Some(subset.parse().unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(
+            check_cmd(&repo("1/30")).to_string(),
+            r#"restic check --read-data-subset="3.33%%""#
+        );
+        assert_eq!(
+            check_cmd(&repo("1/4")).to_string(),
+            r#"restic check --read-data-subset="25%%""#
+        );
+        assert_eq!(
+            check_cmd(&repo("1G")).to_string(),
+            r#"restic check --read-data-subset="1G""#
+        );
+    }
+
+    #[test]
+    fn cmd_specifiers() {
+        let mut cmd = Cmd::new("restore");
+        cmd.specifier("%i").option("--target", "/");
+        assert_eq!(cmd.to_string(), r#"restic restore %i --target="/""#);
+    }
+
+    macro_rules! test_forget_cmd {
+        ($testname:ident, $attr:ident: $value:expr, $expected:expr) => {
+            #[test]
+            fn $testname() {
+                let retention = Retention {
+                    $attr: Some($value),
+                    ..Default::default()
+                };
+                assert_eq!(
+                    forget_cmd("laptop", &["/"], &[], &retention).to_string(),
+                    $expected
+                );
+            }
+        };
+    }
+
+    test_forget_cmd!(forget_cmd_keep_last, keep_last: 42, r#"restic forget --host="laptop" --path="/" --keep-last="42""#);
+    test_forget_cmd!(forget_cmd_keep_hourly, keep_hourly: 42, r#"restic forget --host="laptop" --path="/" --keep-hourly="42""#);
+    test_forget_cmd!(forget_cmd_keep_daily, keep_daily: 42, r#"restic forget --host="laptop" --path="/" --keep-daily="42""#);
+    test_forget_cmd!(forget_cmd_keep_weekly, keep_weekly: 42, r#"restic forget --host="laptop" --path="/" --keep-weekly="42""#);
+    test_forget_cmd!(forget_cmd_keep_monthly, keep_monthly: 42, r#"restic forget --host="laptop" --path="/" --keep-monthly="42""#);
+    test_forget_cmd!(forget_cmd_keep_yearly, keep_yearly: 42, r#"restic forget --host="laptop" --path="/" --keep-yearly="42""#);
+    test_forget_cmd!(forget_cmd_keep_tag, keep_tag: vec!["important".into()], r#"restic forget --host="laptop" --path="/" --keep-tag="important""#);
+    test_forget_cmd!(forget_cmd_keep_tags, keep_tag: vec!["pre-upgrade".into(), "legal-hold".into()], r#"restic forget --host="laptop" --path="/" --keep-tag="pre-upgrade" --keep-tag="legal-hold""#);
+    test_forget_cmd!(forget_cmd_keep_within, keep_within: "2y5m7d3h".parse().unwrap(), r#"restic forget --host="laptop" --path="/" --keep-within="2y5m7d3h""#);
+    test_forget_cmd!(forget_cmd_keep_within_hourly, keep_within_hourly: "7d".parse().unwrap(), r#"restic forget --host="laptop" --path="/" --keep-within-hourly="7d""#);
+    test_forget_cmd!(forget_cmd_keep_within_daily, keep_within_daily: "7d".parse().unwrap(), r#"restic forget --host="laptop" --path="/" --keep-within-daily="7d""#);
+    test_forget_cmd!(forget_cmd_keep_within_weekly, keep_within_weekly: "7d".parse().unwrap(), r#"restic forget --host="laptop" --path="/" --keep-within-weekly="7d""#);
+    test_forget_cmd!(forget_cmd_keep_within_monthly, keep_within_monthly: "7d".parse().unwrap(), r#"restic forget --host="laptop" --path="/" --keep-within-monthly="7d""#);
+    test_forget_cmd!(forget_cmd_keep_within_yearly, keep_within_yearly: "7d".parse().unwrap(), r#"restic forget --host="laptop" --path="/" --keep-within-yearly="7d""#);
+    test_forget_cmd!(forget_cmd_group_by, group_by: "host,tags".parse().unwrap(), r#"restic forget --host="laptop" --path="/" --group-by="host,tags""#);
+}
@@ -0,0 +1,447 @@
+//! A JSON Schema of the configuration. It is found by deserializing a
+//! `Config` from a deserializer that records what it is asked for, so it
+//! follows the serde model. Values that accept several types, like `source`,
+//! are left unconstrained.
+
+use serde::de::{self, value::Error, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor};
+use serde_json::{json, Map, Value};
+use std::{cell::RefCell, collections::HashMap, io::Write};
+
+use crate::config::Config;
+
+#[derive(Debug)]
+enum Schema {
+    Any,
+    Boolean,
+    Integer { unsigned: bool },
+    Number,
+    String,
+    Enum(&'static [&'static str]),
+    Array(Box<Schema>),
+    Map(Box<Schema>),
+    Object(Object),
+}
+
+#[derive(Debug)]
+struct Object {
+    /// Where the object is, e.g. `repositories[]`
+    path: String,
+    properties: Vec<Property>,
+    /// Whether unknown keys are rejected
+    strict: bool,
+}
+
+#[derive(Debug)]
+struct Property {
+    name: &'static str,
+    schema: Schema,
+    required: bool,
+}
+
+/// What to change in the document the model is deserialized from, to find
+/// out about the fields that are required and the objects that are strict
+#[derive(Debug)]
+enum Probe {
+    None,
+    /// Leave out the field at this path
+    Omit(String),
+    /// Add an unknown key to the object at this path
+    Extra(String),
+}
+
+/// The strings given to the visitors, in turn until one is accepted: the
+/// types parsed from strings, like durations, reject the empty one
+const EXAMPLES: &[&str] = &["", "1d", "daily", "02:00..06:00", "1/2", "1G"];
+
+/// One deserialization of the model
+struct Run {
+    probe: Probe,
+    /// The example given at each path, the first one if not listed
+    examples: HashMap<String, usize>,
+    /// The path of the last string given to a visitor
+    last_string: RefCell<Option<String>>,
+}
+
+impl Run {
+    fn example(&self, path: &str) -> &'static str {
+        *self.last_string.borrow_mut() = Some(path.to_string());
+        EXAMPLES[self.examples.get(path).copied().unwrap_or(0)]
+    }
+}
+
+pub fn print(out: &mut impl Write) -> anyhow::Result<()> {
+    serde_json::to_writer_pretty(&mut *out, &schema::<Config>()?)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+fn schema<T: DeserializeOwned>() -> anyhow::Result<Value> {
+    let mut run = Run {
+        probe: Probe::None,
+        examples: HashMap::new(),
+        last_string: RefCell::new(None),
+    };
+    let mut schema = loop {
+        match trace::<T>(&run) {
+            Ok(schema) => break schema,
+            Err(err) => {
+                // Try the next example for the string that was rejected
+                let Some(path) = run.last_string.take() else {
+                    return Err(err.into());
+                };
+                let index = run.examples.entry(path).or_insert(0);
+                *index += 1;
+                if *index == EXAMPLES.len() {
+                    return Err(err.into());
+                }
+            }
+        }
+    };
+    complete::<T>(&mut schema, &mut run);
+    let mut document = to_json(&schema);
+    let document = document
+        .as_object_mut()
+        .expect("the configuration is an object");
+    document.insert(
+        "$schema".into(),
+        "http://json-schema.org/draft-07/schema#".into(),
+    );
+    document.insert("title".into(), "restic-generator configuration".into());
+    Ok(Value::Object(document.clone()))
+}
+
+fn trace<T: DeserializeOwned>(run: &Run) -> Result<Schema, Error> {
+    let mut schema = Schema::Any;
+    T::deserialize(Tracer {
+        path: String::new(),
+        run,
+        schema: &mut schema,
+    })?;
+    Ok(schema)
+}
+
+/// Find out which fields are required and which objects are strict
+fn complete<T: DeserializeOwned>(schema: &mut Schema, run: &mut Run) {
+    match schema {
+        Schema::Array(item) | Schema::Map(item) => complete::<T>(item, run),
+        Schema::Object(object) => {
+            run.probe = Probe::Extra(object.path.clone());
+            object.strict = match trace::<T>(run) {
+                Ok(_) => false,
+                Err(err) => err.to_string().starts_with("unknown field"),
+            };
+            for property in object.properties.iter_mut() {
+                run.probe = Probe::Omit(format!("{}.{}", object.path, property.name));
+                property.required = match trace::<T>(run) {
+                    Ok(_) => false,
+                    Err(err) => err.to_string().starts_with("missing field"),
+                };
+                complete::<T>(&mut property.schema, run);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn to_json(schema: &Schema) -> Value {
+    match schema {
+        Schema::Any => json!({}),
+        Schema::Boolean => json!({"type": "boolean"}),
+        Schema::Integer { unsigned: false } => json!({"type": "integer"}),
+        Schema::Integer { unsigned: true } => json!({"type": "integer", "minimum": 0}),
+        Schema::Number => json!({"type": "number"}),
+        Schema::String => json!({"type": "string"}),
+        Schema::Enum(variants) => json!({"enum": variants}),
+        Schema::Array(item) => json!({"type": "array", "items": to_json(item)}),
+        Schema::Map(value) => json!({"type": "object", "additionalProperties": to_json(value)}),
+        Schema::Object(object) => {
+            let properties: Map<_, _> = object
+                .properties
+                .iter()
+                .map(|property| (property.name.to_string(), to_json(&property.schema)))
+                .collect();
+            let required: Vec<_> = object
+                .properties
+                .iter()
+                .filter(|property| property.required)
+                .map(|property| property.name)
+                .collect();
+            let mut json = json!({
+                "type": "object",
+                "properties": properties,
+                "additionalProperties": !object.strict,
+            });
+            if !required.is_empty() {
+                json["required"] = json!(required);
+            }
+            json
+        }
+    }
+}
+
+/// Records the type asked for in `schema`, and gives the visitor a
+/// placeholder value of that type
+struct Tracer<'a> {
+    path: String,
+    run: &'a Run,
+    schema: &'a mut Schema,
+}
+
+macro_rules! record {
+    ($($method:ident => $schema:expr, $visit:ident($($value:expr)?);)+) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                *self.schema = $schema;
+                visitor.$visit($($value)?)
+            }
+        )+
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Tracer<'_> {
+    type Error = Error;
+
+    record! {
+        deserialize_bool => Schema::Boolean, visit_bool(false);
+        deserialize_i8 => Schema::Integer { unsigned: false }, visit_i64(0);
+        deserialize_i16 => Schema::Integer { unsigned: false }, visit_i64(0);
+        deserialize_i32 => Schema::Integer { unsigned: false }, visit_i64(0);
+        deserialize_i64 => Schema::Integer { unsigned: false }, visit_i64(0);
+        deserialize_u8 => Schema::Integer { unsigned: true }, visit_u64(0);
+        deserialize_u16 => Schema::Integer { unsigned: true }, visit_u64(0);
+        deserialize_u32 => Schema::Integer { unsigned: true }, visit_u64(0);
+        deserialize_u64 => Schema::Integer { unsigned: true }, visit_u64(0);
+        deserialize_f32 => Schema::Number, visit_f64(0.0);
+        deserialize_f64 => Schema::Number, visit_f64(0.0);
+        deserialize_char => Schema::String, visit_char(' ');
+        deserialize_identifier => Schema::String, visit_str("");
+        deserialize_bytes => Schema::Any, visit_bytes(&[]);
+        deserialize_byte_buf => Schema::Any, visit_bytes(&[]);
+        deserialize_unit => Schema::Any, visit_unit();
+        deserialize_ignored_any => Schema::Any, visit_unit();
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        *self.schema = Schema::Any;
+        visitor.visit_str(self.run.example(&self.path))
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        *self.schema = Schema::String;
+        visitor.visit_str(self.run.example(&self.path))
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let mut item = Schema::Any;
+        let result = visitor.visit_seq(ItemAccess(Some(Tracer {
+            path: format!("{}[]", self.path),
+            run: self.run,
+            schema: &mut item,
+        })));
+        *self.schema = Schema::Array(Box::new(item));
+        result
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let mut value = Schema::Any;
+        let result = visitor.visit_map(EntryAccess(Some(Tracer {
+            path: format!("{}.*", self.path),
+            run: self.run,
+            schema: &mut value,
+        })));
+        *self.schema = Schema::Map(Box::new(value));
+        result
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let mut access = FieldAccess {
+            keys: Vec::new(),
+            properties: Vec::new(),
+            path: self.path.clone(),
+            run: self.run,
+        };
+        for field in fields {
+            match &self.run.probe {
+                Probe::Omit(path) if *path == format!("{}.{}", self.path, field) => {}
+                _ => access.keys.push(field),
+            }
+        }
+        if matches!(&self.run.probe, Probe::Extra(path) if *path == self.path) {
+            access.keys.push("\0unknown");
+        }
+        access.keys.reverse();
+        let result = visitor.visit_map(&mut access);
+        *self.schema = Schema::Object(Object {
+            path: self.path,
+            properties: access.properties,
+            strict: false,
+        });
+        result
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        *self.schema = Schema::Enum(variants);
+        visitor.visit_enum(variants[0].into_deserializer())
+    }
+
+    serde::forward_to_deserialize_any! { i128 u128 }
+}
+
+/// The one element of a list
+struct ItemAccess<'a>(Option<Tracer<'a>>);
+
+impl<'de> de::SeqAccess<'de> for ItemAccess<'_> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        self.0
+            .take()
+            .map(|tracer| seed.deserialize(tracer))
+            .transpose()
+    }
+}
+
+/// The one entry of a map, with an empty key
+struct EntryAccess<'a>(Option<Tracer<'a>>);
+
+impl<'de> de::MapAccess<'de> for EntryAccess<'_> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        if self.0.is_none() {
+            return Ok(None);
+        }
+        seed.deserialize("".into_deserializer()).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let tracer = self.0.take().expect("the value follows the key");
+        seed.deserialize(tracer)
+    }
+}
+
+/// The fields of a struct
+struct FieldAccess<'a> {
+    /// The keys left, last first
+    keys: Vec<&'static str>,
+    properties: Vec<Property>,
+    path: String,
+    run: &'a Run,
+}
+
+impl<'de> de::MapAccess<'de> for FieldAccess<'_> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        let Some(key) = self.keys.last() else {
+            return Ok(None);
+        };
+        seed.deserialize(key.into_deserializer()).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let name = self.keys.pop().expect("the value follows the key");
+        let mut schema = Schema::Any;
+        let result = seed.deserialize(Tracer {
+            path: format!("{}.{}", self.path, name),
+            run: self.run,
+            schema: &mut schema,
+        });
+        self.properties.push(Property {
+            name,
+            schema,
+            required: false,
+        });
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_schema() {
+        let schema = schema::<Config>().unwrap();
+        assert_eq!(schema["type"], "object");
+        assert_eq!(schema["additionalProperties"], false);
+        assert_eq!(schema["properties"]["host"], json!({"type": "string"}));
+        let repository = &schema["properties"]["repositories"]["items"];
+        assert_eq!(repository["required"], json!(["name", "location"]));
+        assert_eq!(
+            repository["properties"]["keep-daily"],
+            json!({"type": "integer", "minimum": 0})
+        );
+        assert_eq!(
+            schema["properties"]["include"],
+            json!({"type": "array", "items": {"type": "string"}})
+        );
+        assert!(schema["properties"].get("files").is_none());
+        assert_eq!(
+            repository["properties"]["keep-within"],
+            json!({"type": "string"})
+        );
+        let job = &schema["properties"]["jobs"]["items"];
+        assert_eq!(job["required"], json!(["name", "repositories"]));
+    }
+}
@@ -0,0 +1,94 @@
+use std::io::Write;
+
+use crate::config::Config;
+use crate::environment::{self, Format};
+use crate::restic;
+use crate::units::{self, Context, PlannedUnit, Unit};
+
+/// Render one executable script per generated unit, named after the kind of
+/// unit, the job and the repository, e.g. `backup-myrepo.sh`
+pub fn generate(context: &Context, config: &Config) -> anyhow::Result<Vec<Unit>> {
+    let mut scripts = Vec::new();
+    for planned in units::plan(context, config) {
+        // The instance of the templates is a systemd specifier
+        if planned.skipped.is_some() || planned.kind.is_template() || planned.kind.is_systemd_only()
+        {
+            continue;
+        }
+        let name = format!("{}-{}.sh", planned.kind.as_str(), planned.qualified_name());
+        let mut script = Unit::render(name, |out| write_script(out, context, &planned))?;
+        script.executable = true;
+        scripts.push(script);
+    }
+    Ok(scripts)
+}
+
+fn write_script(
+    out: &mut impl Write,
+    context: &Context,
+    planned: &PlannedUnit,
+) -> anyhow::Result<()> {
+    writeln!(out, "#!/bin/sh")?;
+    writeln!(
+        out,
+        "# generated by {} from {}",
+        context.program_name,
+        context.config_path.display()
+    )?;
+    writeln!(out, "set -e")?;
+    environment::print(out, planned.repository, Format::Shell)?;
+    if planned.from.is_some() {
+        // The variables of the source that the destination doesn't set
+        let destination = restic::environment(planned.repository).len();
+        for (name, value) in planned.environment().into_iter().skip(destination) {
+            writeln!(out, "export {}={}", name, environment::shell_quote(&value))?;
+        }
+    }
+    for command in units::commands(context, planned) {
+        write!(out, "nice -n 10 {}", command.to_shell())?;
+        if command.subcommand() == "backup" {
+            // 3 is returned when a file cannot be read (e.g. it is removed during the backup.)
+            write!(out, " || [ $? -eq 3 ]")?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RepositoryConfig;
+    use crate::units::tests::context;
+
+    #[test]
+    fn backup_script() {
+        let context = context();
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "/repo".into(),
+                password_file: Some("/etc/restic/password".into()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let scripts = generate(&context, &config).unwrap();
+        assert_eq!(scripts.len(), 2);
+        assert_eq!(scripts[0].name, "backup-myrepo.sh");
+        assert_eq!(scripts[1].name, "unlock-myrepo.sh");
+        assert!(scripts[0].executable);
+        assert_eq!(
+            String::from_utf8(scripts[0].content.clone()).unwrap(),
+            "#!/bin/sh
+# generated by restic-generator from config.toml
+set -e
+export RESTIC_REPOSITORY=/repo
+export RESTIC_PASSWORD_FILE=/etc/restic/password
+nice -n 10 restic unlock
+nice -n 10 restic backup --host=laptop / || [ $? -eq 3 ]
+"
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use anyhow::Context as _;
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use crate::freshness::{parse_time, Snapshot};
+use crate::restic::{self, Access};
+
+/// Run restic diff on the last two snapshots of `paths` by `host`, printing
+/// what changed between them
+pub fn run(
+    out: &mut impl Write,
+    access: &Access,
+    paths: &[String],
+    host: &str,
+) -> anyhow::Result<()> {
+    let output = Command::new("restic")
+        .args(restic::latest_snapshots_cmd(host, paths, 2).args())
+        .args(access.option_args())
+        .envs(access.environment.clone())
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| "error running restic")?;
+    if !output.status.success() {
+        anyhow::bail!("restic snapshots failed ({})", output.status);
+    }
+    let snapshots: Vec<Snapshot> = serde_json::from_slice(&output.stdout)
+        .with_context(|| "unexpected output of restic snapshots")?;
+    let Some((previous, latest)) = to_compare(out, &snapshots)? else {
+        return Ok(());
+    };
+    let status = Command::new("restic")
+        .args(["diff", previous, latest])
+        .args(access.option_args())
+        .envs(access.environment.clone())
+        .status()
+        .with_context(|| "error running restic")?;
+    if !status.success() {
+        anyhow::bail!("restic diff failed ({})", status);
+    }
+    Ok(())
+}
+
+/// The IDs of the last two snapshots, oldest first, or `None` after telling
+/// `out` there are less than two
+fn to_compare<'a>(
+    out: &mut impl Write,
+    snapshots: &'a [Snapshot],
+) -> anyhow::Result<Option<(&'a str, &'a str)>> {
+    let result = last_two(snapshots)?;
+    if result.is_none() {
+        writeln!(
+            out,
+            "nothing to compare: the job has less than two snapshots"
+        )?;
+    }
+    Ok(result)
+}
+
+/// The IDs of the last two snapshots, oldest first
+fn last_two(snapshots: &[Snapshot]) -> anyhow::Result<Option<(&str, &str)>> {
+    let mut sorted = Vec::new();
+    for snapshot in snapshots {
+        let time = parse_time(&snapshot.time)
+            .ok_or_else(|| anyhow::anyhow!("invalid snapshot time {:?}", snapshot.time))?;
+        sorted.push((time, snapshot.short_id.as_str()));
+    }
+    sorted.sort();
+    Ok(match sorted[..] {
+        [.., (_, previous), (_, latest)] => Some((previous, latest)),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_the_last_two_snapshots() {
+        let snapshot = |time: &str, short_id: &str| Snapshot {
+            time: time.into(),
+            short_id: short_id.into(),
+        };
+        let snapshots = vec![
+            snapshot("2023-05-02T10:00:00+02:00", "b"),
+            snapshot("2023-05-02T09:30:00Z", "c"),
+            snapshot("2023-05-01T10:00:00Z", "a"),
+        ];
+        assert_eq!(last_two(&snapshots).unwrap(), Some(("b", "c")));
+        assert_eq!(last_two(&snapshots[..1]).unwrap(), None);
+    }
+
+    #[test]
+    fn less_than_two_snapshots() {
+        let snapshots = vec![Snapshot {
+            time: "2023-05-01T10:00:00Z".into(),
+            short_id: "a".into(),
+        }];
+        let mut out = Vec::new();
+        assert_eq!(to_compare(&mut out, &snapshots).unwrap(), None);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "nothing to compare: the job has less than two snapshots\n"
+        );
+    }
+}
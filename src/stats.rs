@@ -0,0 +1,152 @@
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+use std::{
+    env, fs,
+    io::Write,
+    os::unix::net::UnixDatagram,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use crate::restic::Access;
+
+/// Where journald listens for structured entries
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// The sizes of a repository, as written by the stats units
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Stats {
+    pub repository: String,
+    /// The size of the (deduplicated, compressed) data in the repository
+    pub raw_size: u64,
+    /// The size of the files a restore of every snapshot would write
+    pub restore_size: u64,
+    pub snapshots: u64,
+}
+
+/// The part of the output of `restic stats --json` the stats use, in both
+/// the restore-size and raw-data modes
+#[derive(Debug, Deserialize)]
+struct ResticStats {
+    total_size: u64,
+    snapshots_count: u64,
+}
+
+/// Run restic stats on the repository named `name` in both modes
+pub fn query(name: &str, access: &Access) -> anyhow::Result<Stats> {
+    let restore = restic_stats(access, "restore-size")?;
+    let raw = restic_stats(access, "raw-data")?;
+    Ok(Stats {
+        repository: name.to_string(),
+        raw_size: raw.total_size,
+        restore_size: restore.total_size,
+        snapshots: restore.snapshots_count,
+    })
+}
+
+fn restic_stats(access: &Access, mode: &str) -> anyhow::Result<ResticStats> {
+    let output = Command::new("restic")
+        .args(["stats", "--json", "--mode", mode])
+        .args(access.option_args())
+        .envs(access.environment.clone())
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| "error running restic")?;
+    if !output.status.success() {
+        anyhow::bail!("restic stats --mode {} failed ({})", mode, output.status);
+    }
+    parse(&output.stdout)
+        .with_context(|| format!("unexpected output of restic stats --mode {}", mode))
+}
+
+fn parse(output: &[u8]) -> anyhow::Result<ResticStats> {
+    // Older releases print progress lines before the result
+    let last = output
+        .split(|&byte| byte == b'\n')
+        .rfind(|line| !line.is_empty())
+        .unwrap_or_default();
+    Ok(serde_json::from_slice(last)?)
+}
+
+/// Write `stats` as JSON to `path`, or to `out` if there is none. The file is
+/// replaced at once, so a failed run leaves the previous stats in place.
+pub fn write(out: &mut impl Write, stats: &Stats, path: Option<&Path>) -> anyhow::Result<()> {
+    let Some(path) = path else {
+        serde_json::to_writer_pretty(&mut *out, stats)?;
+        writeln!(out)?;
+        return Ok(());
+    };
+    let mut content = serde_json::to_vec_pretty(stats)?;
+    content.push(b'\n');
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, content).with_context(|| format!("{}: error writing file", tmp.display()))?;
+    fs::rename(&tmp, path).with_context(|| format!("{}: error writing file", path.display()))?;
+    Ok(())
+}
+
+/// Log `stats` to the journal with one field per value when running under
+/// systemd, or as a plain message on stderr otherwise.
+pub fn log(stats: &Stats) {
+    let message = format!(
+        "repository {}: {} snapshots, raw size {} bytes, restore size {} bytes",
+        stats.repository, stats.snapshots, stats.raw_size, stats.restore_size
+    );
+    if env::var_os("JOURNAL_STREAM").is_some() && journal_send(&journal_entry(stats, &message)) {
+        return;
+    }
+    eprintln!("{}", message);
+}
+
+fn journal_entry(stats: &Stats, message: &str) -> String {
+    [
+        ("MESSAGE", message.to_string()),
+        ("SYSLOG_IDENTIFIER", env!("CARGO_BIN_NAME").to_string()),
+        ("RESTIC_REPOSITORY_NAME", stats.repository.clone()),
+        ("RESTIC_RAW_SIZE", stats.raw_size.to_string()),
+        ("RESTIC_RESTORE_SIZE", stats.restore_size.to_string()),
+        ("RESTIC_SNAPSHOTS", stats.snapshots.to_string()),
+    ]
+    .iter()
+    // Repository names and numbers never contain newlines, so the simple
+    // KEY=VALUE form of the native protocol is enough
+    .map(|(key, value)| format!("{}={}\n", key, value))
+    .collect()
+}
+
+fn journal_send(entry: &str) -> bool {
+    UnixDatagram::unbound()
+        .and_then(|socket| socket.send_to(entry.as_bytes(), JOURNAL_SOCKET))
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_restic_output() {
+        let output = br#"{"total_size":1024,"total_file_count":3,"snapshots_count":2}
+"#;
+        let stats = parse(output).unwrap();
+        assert_eq!(stats.total_size, 1024);
+        assert_eq!(stats.snapshots_count, 2);
+        let output =
+            b"scanning...\n{\"total_size\":10,\"total_blob_count\":1,\"snapshots_count\":2}\n";
+        assert_eq!(parse(output).unwrap().total_size, 10);
+        assert!(parse(b"").is_err());
+    }
+
+    #[test]
+    fn journal_fields() {
+        let stats = Stats {
+            repository: "nas".into(),
+            raw_size: 10,
+            restore_size: 20,
+            snapshots: 3,
+        };
+        let entry = journal_entry(&stats, "hello");
+        assert!(entry.starts_with("MESSAGE=hello\n"));
+        assert!(entry.contains("RESTIC_RAW_SIZE=10\nRESTIC_RESTORE_SIZE=20\nRESTIC_SNAPSHOTS=3\n"));
+    }
+}
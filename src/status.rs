@@ -0,0 +1,300 @@
+use anyhow::Context as _;
+use serde::Serialize;
+use std::{collections::HashMap, io::Write, process::Command};
+
+use crate::units::{PlannedUnit, UnitKind};
+
+const PROPERTIES: &[&str] = &[
+    "ActiveState",
+    "Result",
+    "ExecMainStartTimestamp",
+    "ExecMainExitTimestamp",
+];
+
+/// The outcome of the last run of a unit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum State {
+    NeverRun,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct UnitStatus {
+    pub repository: String,
+    pub unit: String,
+    #[serde(rename = "type")]
+    pub kind: UnitKind,
+    pub state: State,
+    /// The systemd result of the last run, e.g. `exit-code` when it failed
+    pub result: Option<String>,
+    pub started: Option<String>,
+    pub finished: Option<String>,
+}
+
+const TIMER_PROPERTIES: &[&str] = &["LoadState", "NextElapseUSecRealtime"];
+
+/// Ask systemd about the last run of each generated unit
+pub fn query(units: &[PlannedUnit], user: bool) -> anyhow::Result<Vec<UnitStatus>> {
+    let mut statuses = Vec::new();
+    for unit in units.iter().filter(|unit| unit.skipped.is_none()) {
+        let stdout = systemctl_show(&unit.name, PROPERTIES, user)?;
+        statuses.push(status(unit, &parse_show(&stdout)));
+    }
+    Ok(statuses)
+}
+
+/// When the timer of a generated unit fires next
+#[derive(Debug, PartialEq, Eq)]
+pub struct NextRun {
+    pub repository: String,
+    pub kind: UnitKind,
+    pub timer: String,
+    /// `None` if the timer does not exist or is not scheduled
+    pub next: Option<String>,
+}
+
+/// Ask systemd when the timer of each generated unit elapses next
+pub fn query_next_runs(units: &[PlannedUnit], user: bool) -> anyhow::Result<Vec<NextRun>> {
+    let mut runs = Vec::new();
+    let scheduled = |unit: &&PlannedUnit| unit.skipped.is_none() && unit.has_timer();
+    for unit in units.iter().filter(scheduled) {
+        let timer = timer_name(&unit.name);
+        let stdout = systemctl_show(&timer, TIMER_PROPERTIES, user)?;
+        runs.push(next_run(unit, timer, &parse_show(&stdout)));
+    }
+    Ok(runs)
+}
+
+fn timer_name(service: &str) -> String {
+    format!("{}.timer", service.trim_end_matches(".service"))
+}
+
+fn next_run(unit: &PlannedUnit, timer: String, properties: &HashMap<&str, &str>) -> NextRun {
+    let loaded = properties.get("LoadState") == Some(&"loaded");
+    let next = properties
+        .get("NextElapseUSecRealtime")
+        .filter(|value| loaded && !value.is_empty() && **value != "0")
+        .map(|value| value.to_string());
+    NextRun {
+        repository: unit.repository.name.clone(),
+        kind: unit.kind,
+        timer,
+        next,
+    }
+}
+
+fn systemctl_show(unit: &str, properties: &[&str], user: bool) -> anyhow::Result<String> {
+    let mut cmd = Command::new("systemctl");
+    if user {
+        cmd.arg("--user");
+    }
+    cmd.arg("show").arg(unit);
+    for property in properties {
+        cmd.arg("--property").arg(property);
+    }
+    let output = cmd.output().with_context(|| "error running systemctl")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "systemctl show {} failed: {}",
+            unit,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Parse the `Key=value` lines printed by `systemctl show`
+fn parse_show(output: &str) -> HashMap<&str, &str> {
+    output
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .collect()
+}
+
+fn status(unit: &PlannedUnit, properties: &HashMap<&str, &str>) -> UnitStatus {
+    let get = |name: &str| {
+        properties
+            .get(name)
+            .filter(|value| !value.is_empty())
+            .map(|value| value.to_string())
+    };
+    let started = get("ExecMainStartTimestamp");
+    let result = get("Result");
+    let state = match (get("ActiveState").as_deref(), &started, result.as_deref()) {
+        (Some("activating") | Some("active") | Some("deactivating"), Some(_), _) => State::Running,
+        (_, None, _) => State::NeverRun,
+        (_, Some(_), Some("success")) => State::Succeeded,
+        (_, Some(_), _) => State::Failed,
+    };
+    UnitStatus {
+        repository: unit.repository.name.clone(),
+        unit: unit.name.clone(),
+        kind: unit.kind,
+        state,
+        result: result.filter(|_| state == State::Failed),
+        finished: get("ExecMainExitTimestamp").filter(|_| state != State::Running),
+        started,
+    }
+}
+
+pub fn print(out: &mut impl Write, statuses: &[UnitStatus], json: bool) -> anyhow::Result<()> {
+    if json {
+        serde_json::to_writer_pretty(&mut *out, statuses)?;
+        writeln!(out)?;
+        return Ok(());
+    }
+    let mut repository = None;
+    for status in statuses {
+        if repository != Some(&status.repository) {
+            repository = Some(&status.repository);
+            writeln!(out, "{}", status.repository)?;
+        }
+        let description = match status.state {
+            State::NeverRun => "never run".to_string(),
+            State::Running => format!("running since {}", status.started.as_deref().unwrap_or("?")),
+            State::Succeeded => {
+                format!("succeeded at {}", status.finished.as_deref().unwrap_or("?"))
+            }
+            State::Failed => format!(
+                "failed ({}) at {}",
+                status.result.as_deref().unwrap_or("?"),
+                status.finished.as_deref().unwrap_or("?")
+            ),
+        };
+        writeln!(out, "  {:<8}{}", status.kind.as_str(), description)?;
+    }
+    Ok(())
+}
+
+pub fn print_next_runs(out: &mut impl Write, runs: &[NextRun]) -> anyhow::Result<()> {
+    let mut repository = None;
+    for run in runs {
+        if repository != Some(&run.repository) {
+            repository = Some(&run.repository);
+            writeln!(out, "{}", run.repository)?;
+        }
+        match &run.next {
+            Some(next) => writeln!(out, "  {:<8}{} ({})", run.kind.as_str(), next, run.timer)?,
+            None => writeln!(
+                out,
+                "  {:<8}not scheduled ({})",
+                run.kind.as_str(),
+                run.timer
+            )?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RepositoryConfig;
+
+    fn with_unit<T>(f: impl FnOnce(&PlannedUnit) -> T) -> T {
+        let repository = RepositoryConfig {
+            name: "myrepo".into(),
+            ..Default::default()
+        };
+        let unit = PlannedUnit {
+            repository: &repository,
+            job: None,
+            from: None,
+            kind: UnitKind::Backup,
+            name: "restic-myrepo-backup.service".into(),
+            skipped: None,
+            stagger: None,
+            on_success: Vec::new(),
+            hosts: Vec::new(),
+        };
+        f(&unit)
+    }
+
+    fn status_of(output: &str) -> UnitStatus {
+        with_unit(|unit| status(unit, &parse_show(output)))
+    }
+
+    fn next_run_of(output: &str) -> NextRun {
+        with_unit(|unit| next_run(unit, timer_name(&unit.name), &parse_show(output)))
+    }
+
+    #[test]
+    fn next_run_scheduled() {
+        let run =
+            next_run_of("LoadState=loaded\nNextElapseUSecRealtime=Thu 2026-10-15 02:00:00 CEST\n");
+        assert_eq!(run.timer, "restic-myrepo-backup.timer");
+        assert_eq!(run.next.as_deref(), Some("Thu 2026-10-15 02:00:00 CEST"));
+        let mut out = Vec::new();
+        print_next_runs(&mut out, &[run]).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "myrepo\n  backup  Thu 2026-10-15 02:00:00 CEST (restic-myrepo-backup.timer)\n"
+        );
+    }
+
+    #[test]
+    fn next_run_without_timer() {
+        let run = next_run_of("LoadState=not-found\nNextElapseUSecRealtime=\n");
+        assert_eq!(run.next, None);
+    }
+
+    #[test]
+    fn never_run() {
+        let status = status_of(
+            "ActiveState=inactive\nResult=success\nExecMainStartTimestamp=\nExecMainExitTimestamp=\n",
+        );
+        assert_eq!(status.state, State::NeverRun);
+        assert_eq!(status.started, None);
+    }
+
+    #[test]
+    fn succeeded() {
+        let status = status_of(
+            "ActiveState=inactive
+Result=success
+ExecMainStartTimestamp=Wed 2026-10-14 02:00:01 CEST
+ExecMainExitTimestamp=Wed 2026-10-14 02:10:42 CEST
+",
+        );
+        assert_eq!(status.state, State::Succeeded);
+        assert_eq!(
+            status.finished.as_deref(),
+            Some("Wed 2026-10-14 02:10:42 CEST")
+        );
+        assert_eq!(status.result, None);
+    }
+
+    #[test]
+    fn failed() {
+        let status = status_of(
+            "ActiveState=failed
+Result=exit-code
+ExecMainStartTimestamp=Wed 2026-10-14 02:00:01 CEST
+ExecMainExitTimestamp=Wed 2026-10-14 02:00:02 CEST
+",
+        );
+        assert_eq!(status.state, State::Failed);
+        assert_eq!(status.result.as_deref(), Some("exit-code"));
+    }
+
+    #[test]
+    fn running() {
+        let status = status_of(
+            "ActiveState=activating
+Result=success
+ExecMainStartTimestamp=Wed 2026-10-14 02:00:01 CEST
+ExecMainExitTimestamp=
+",
+        );
+        assert_eq!(status.state, State::Running);
+        let mut out = Vec::new();
+        print(&mut out, &[status], false).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "myrepo\n  backup  running since Wed 2026-10-14 02:00:01 CEST\n"
+        );
+    }
+}
@@ -0,0 +1,2931 @@
+use serde::Serialize;
+use std::{io::Write, path::PathBuf};
+
+use crate::config::{
+    CacheCleanup, Calendar, Config, DataSubset, Job, Maintenance, Pruning, RepositoryConfig,
+    Schedule, TimeSpan, Unlock, Window,
+};
+use crate::restic::{
+    self, backup_cmd, check_cmd, forget_cmd, latest_snapshots_cmd, prune_cmd, quote_word,
+    rewrite_cmd, stdin_backup_cmd, unlock_cmd, BackupOptions, Cmd,
+};
+use crate::{crontab, launchd, scripts};
+
+#[derive(Debug)]
+pub struct Context {
+    pub config_path: PathBuf,
+    pub program_name: String,
+    /// The executable run by the units that need restic-generator itself
+    pub program_path: PathBuf,
+    pub hostname: String,
+}
+
+/// A rendered unit file
+#[derive(Debug)]
+pub struct Unit {
+    pub name: String,
+    pub content: Vec<u8>,
+    /// Whether the file is written with the executable bits set
+    pub executable: bool,
+}
+
+impl Unit {
+    pub fn render<F>(name: String, write: F) -> anyhow::Result<Unit>
+    where
+        F: FnOnce(&mut Vec<u8>) -> anyhow::Result<()>,
+    {
+        let mut content = Vec::new();
+        write(&mut content)?;
+        Ok(Unit {
+            name,
+            content,
+            executable: false,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnitKind {
+    Backup,
+    Forget,
+    Prune,
+    Check,
+    Copy,
+    Init,
+    Stats,
+    Freshness,
+    Mount,
+    Restore,
+    RepairIndex,
+    RepairSnapshots,
+    Unlock,
+    Rewrite,
+    Migrate,
+    Diff,
+}
+
+impl UnitKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UnitKind::Backup => "backup",
+            UnitKind::Forget => "forget",
+            UnitKind::Prune => "prune",
+            UnitKind::Check => "check",
+            UnitKind::Copy => "copy",
+            UnitKind::Init => "init",
+            UnitKind::Stats => "stats",
+            UnitKind::Freshness => "freshness",
+            UnitKind::Mount => "mount",
+            UnitKind::Restore => "restore",
+            UnitKind::RepairIndex => "repair-index",
+            UnitKind::RepairSnapshots => "repair-snapshots",
+            UnitKind::Unlock => "unlock",
+            UnitKind::Rewrite => "rewrite",
+            UnitKind::Migrate => "migrate",
+            UnitKind::Diff => "diff",
+        }
+    }
+
+    /// Whether the units are only started by hand
+    pub fn is_on_demand(&self) -> bool {
+        matches!(
+            self,
+            UnitKind::Mount
+                | UnitKind::Restore
+                | UnitKind::RepairIndex
+                | UnitKind::RepairSnapshots
+                | UnitKind::Unlock
+                | UnitKind::Rewrite
+                | UnitKind::Migrate
+        )
+    }
+
+    /// Whether the units rely on systemd to report their failures, and are
+    /// left out of the other formats
+    pub fn is_systemd_only(&self) -> bool {
+        matches!(self, UnitKind::Freshness | UnitKind::Diff)
+    }
+
+    /// Whether the units are templates, started with an instance name
+    pub fn is_template(&self) -> bool {
+        *self == UnitKind::Restore
+    }
+}
+
+/// What `render` produces from the configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// systemd service units
+    Systemd,
+    /// A crontab running the same commands
+    Crontab,
+    /// launchd property lists, for macOS
+    Launchd,
+    /// Executable shell scripts
+    Scripts,
+}
+
+impl std::str::FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Format> {
+        match s {
+            "systemd" => Ok(Format::Systemd),
+            "crontab" => Ok(Format::Crontab),
+            "launchd" => Ok(Format::Launchd),
+            "scripts" => Ok(Format::Scripts),
+            _ => anyhow::bail!(
+                "unknown format {} (expected systemd, crontab, launchd or scripts)",
+                s
+            ),
+        }
+    }
+}
+
+/// A unit that the configuration calls for, and whether it is generated
+#[derive(Debug)]
+pub struct PlannedUnit<'a> {
+    pub repository: &'a RepositoryConfig,
+    /// The job backed up or forgotten, `None` for the other units
+    pub job: Option<Job<'a>>,
+    /// Where copy units copy the snapshots from
+    pub from: Option<CopyFrom<'a>>,
+    pub kind: UnitKind,
+    pub name: String,
+    /// Why the unit is not generated, if it isn't
+    pub skipped: Option<&'static str>,
+    /// The delay to spread the unit over, if its timer has the same
+    /// calendar as one of another repository
+    pub stagger: Option<TimeSpan>,
+    /// The unit started when this one succeeds, with chained maintenance
+    pub on_success: Vec<String>,
+    /// The hosts of the snapshots made in the repository from this machine
+    pub hosts: Vec<String>,
+}
+
+/// The source of a copy unit, whose repository is the destination
+#[derive(Debug)]
+pub struct CopyFrom<'a> {
+    pub repository: &'a RepositoryConfig,
+    pub calendar: Option<&'a Calendar>,
+}
+
+impl PlannedUnit<'_> {
+    /// The job and repository names, e.g. `home-myrepo`, or just the
+    /// repository name for the top-level job and the repository units,
+    /// or both repository names for copy units, e.g. `disk-to-b2`
+    pub fn qualified_name(&self) -> String {
+        if let Some(from) = &self.from {
+            return format!("{}-to-{}", from.repository.name, self.repository.name);
+        }
+        match self.job.as_ref().and_then(|job| job.name) {
+            Some(job) => format!("{}-{}", job, self.repository.name),
+            None => self.repository.name.clone(),
+        }
+    }
+
+    /// The name of the timer starting the unit
+    pub fn timer_name(&self) -> String {
+        format!("{}.timer", self.name.trim_end_matches(".service"))
+    }
+
+    /// Whether the unit is only ever started by hand, whatever the schedule
+    pub fn is_on_demand(&self) -> bool {
+        self.kind.is_on_demand()
+            || self.kind == UnitKind::Prune && self.repository.prune == Pruning::Manual
+    }
+
+    /// Whether a timer starts the unit, otherwise it is only started by hand
+    pub fn has_timer(&self) -> bool {
+        let chained = self.repository.maintenance == Maintenance::Chained
+            && match self.kind {
+                UnitKind::Forget => true,
+                UnitKind::Prune => self.repository.prune == Pruning::WithForget,
+                _ => false,
+            };
+        let untimed = matches!(self.kind, UnitKind::Init | UnitKind::Diff) || self.is_on_demand();
+        self.repository.schedule != Schedule::Manual && !chained && !untimed
+    }
+
+    /// The window the unit must run in, if any
+    pub fn window(&self) -> Option<&Window> {
+        match self.kind {
+            UnitKind::Prune => self.repository.prune_window.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// When the timer starts the unit
+    pub fn calendar(&self) -> Calendar {
+        if let Some(window) = self.window() {
+            return window.start.clone();
+        }
+        let repository = self.repository;
+        let (calendar, default) = match self.kind {
+            UnitKind::Backup => (&repository.backup_calendar, "daily"),
+            UnitKind::Forget => (&repository.forget_calendar, "weekly"),
+            UnitKind::Prune => (&repository.prune_calendar, "monthly"),
+            UnitKind::Check => (&repository.check_calendar, "monthly"),
+            UnitKind::Stats => (&repository.stats_calendar, "weekly"),
+            UnitKind::Freshness => (&None, "daily"),
+            UnitKind::Copy => (&self.copy_from().calendar.cloned(), "daily"),
+            UnitKind::Init
+            | UnitKind::Mount
+            | UnitKind::Restore
+            | UnitKind::RepairIndex
+            | UnitKind::RepairSnapshots
+            | UnitKind::Unlock
+            | UnitKind::Rewrite
+            | UnitKind::Migrate
+            | UnitKind::Diff => {
+                unreachable!("the unit has no timer")
+            }
+        };
+        calendar.clone().unwrap_or_else(|| Calendar::new(default))
+    }
+
+    /// How much the timer delays the unit at random, so that the machines
+    /// backing up to a remote repository don't all start at the same time
+    pub fn randomized_delay(&self) -> Option<TimeSpan> {
+        let repository = self.repository;
+        if self.window().is_some() {
+            // A delay would only eat into the window
+            return None;
+        }
+        match &repository.randomized_delay {
+            Some(delay) => Some(delay.clone()),
+            None if self.stagger.is_some() => self.stagger.clone(),
+            None if !is_local_repository(&repository.location) => Some(TimeSpan::new("30min")),
+            None => None,
+        }
+    }
+
+    /// Whether the timer catches up on the runs missed while the machine
+    /// was off, never outside of its window
+    pub fn persistent(&self) -> bool {
+        self.window().is_none()
+            && self
+                .repository
+                .persistent
+                .unwrap_or(self.kind == UnitKind::Backup)
+    }
+
+    /// The environment of the commands
+    pub fn environment(&self) -> Vec<(&str, String)> {
+        let mut result = restic::environment(self.repository);
+        if let Some(from) = &self.from {
+            // The variables shared with the destination are set once, the
+            // validation reports the ones with different values
+            for (name, value) in restic::from_environment(from.repository) {
+                if !result.iter().any(|(set, _)| *set == name) {
+                    result.push((name, value));
+                }
+            }
+        }
+        result
+    }
+
+    fn copy_from(&self) -> &CopyFrom<'_> {
+        self.from.as_ref().expect("copy units have a source")
+    }
+
+    fn job(&self) -> &Job<'_> {
+        self.job
+            .as_ref()
+            .expect("backup, forget and rewrite units belong to a job")
+    }
+
+    /// The options of the backups of the job
+    fn backup_options(&self) -> BackupOptions<'_> {
+        let (job, repository) = (self.job(), self.repository);
+        BackupOptions {
+            dry_run: repository.dry_run,
+            files_from: job.files_from,
+            files_from_verbatim: job.files_from_verbatim,
+            iexclude: job
+                .iexclude
+                .iter()
+                .copied()
+                .chain(repository.iexclude.iter().map(String::as_str))
+                .collect(),
+            exclude_files: job
+                .exclude_file
+                .iter()
+                .copied()
+                .chain(repository.exclude_file.iter().map(String::as_str))
+                .collect(),
+            iexclude_files: job
+                .iexclude_file
+                .iter()
+                .copied()
+                .chain(repository.iexclude_file.iter().map(String::as_str))
+                .collect(),
+            exclude_caches: job.exclude_caches || repository.exclude_caches,
+            exclude_if_present: job
+                .exclude_if_present
+                .iter()
+                .copied()
+                .chain(repository.exclude_if_present.iter().map(String::as_str))
+                .collect(),
+            // The smallest limit applies
+            exclude_larger_than: job
+                .exclude_larger_than
+                .into_iter()
+                .chain(repository.exclude_larger_than.as_ref())
+                .min_by_key(|size| size.bytes()),
+            one_file_system: job.one_file_system,
+            read_concurrency: job.read_concurrency,
+            no_scan: job.no_scan,
+            ignore_inode: job.ignore_inode,
+            ignore_ctime: job.ignore_ctime,
+            with_atime: job.with_atime,
+            skip_if_unchanged: job.skip_if_unchanged,
+            tags: &job.tags,
+        }
+    }
+
+    /// The patterns excluded from the backups of the job
+    fn exclude(&self) -> Vec<&str> {
+        let job = self.job();
+        job.exclude
+            .iter()
+            .copied()
+            .chain(self.repository.exclude.iter().map(String::as_str))
+            .collect()
+    }
+}
+
+/// The hosts of the snapshots that the `jobs` running on this machine make in
+/// `repository`, the hostname if none does
+pub fn snapshot_hosts(
+    context: &Context,
+    jobs: &[Job],
+    repository: &RepositoryConfig,
+) -> Vec<String> {
+    let mut hosts = Vec::new();
+    let local = jobs
+        .iter()
+        .filter(|job| job.targets(repository) && job.runs_on(&context.hostname));
+    for job in local {
+        let host = job.host.unwrap_or(&context.hostname);
+        if !hosts.iter().any(|known| known == host) {
+            hosts.push(host.to_string());
+        }
+    }
+    if hosts.is_empty() {
+        hosts.push(context.hostname.clone());
+    }
+    hosts
+}
+
+/// Whether the backups of `job` in `repository` exclude files by patterns,
+/// which restic rewrite can also remove from the existing snapshots
+fn has_exclude_patterns(job: &Job, repository: &RepositoryConfig) -> bool {
+    let job_patterns = [
+        &job.exclude,
+        &job.iexclude,
+        &job.exclude_file,
+        &job.iexclude_file,
+    ];
+    let repository_patterns = [
+        &repository.exclude,
+        &repository.iexclude,
+        &repository.exclude_file,
+        &repository.iexclude_file,
+    ];
+    job_patterns.iter().any(|patterns| !patterns.is_empty())
+        || repository_patterns
+            .iter()
+            .any(|patterns| !patterns.is_empty())
+}
+
+/// List the units for every repository, including those that are skipped
+pub fn plan<'a>(context: &Context, config: &'a Config) -> Vec<PlannedUnit<'a>> {
+    const OTHER_HOST: &str = "not for this host";
+    const NO_POLICY: &str = "no forget policy";
+    let jobs = config.jobs();
+    let mut units = Vec::new();
+    for repository in config.repositories.iter() {
+        let excluded = if !repository.is_enabled() {
+            Some("disabled")
+        } else if !repository.runs_on(&context.hostname) {
+            Some(OTHER_HOST)
+        } else {
+            None
+        };
+        let hosts = snapshot_hosts(context, &jobs, repository);
+        let mut add = |job: Option<Job<'a>>, kind: UnitKind, skipped| {
+            let mut unit = PlannedUnit {
+                repository,
+                job,
+                from: None,
+                kind,
+                name: String::new(),
+                skipped,
+                stagger: None,
+                on_success: Vec::new(),
+                hosts: hosts.clone(),
+            };
+            let template = if kind.is_template() { "@" } else { "" };
+            unit.name = format!(
+                "restic-{}-{}{}.service",
+                unit.qualified_name(),
+                kind.as_str(),
+                template
+            );
+            units.push(unit);
+        };
+        let mut targeted = false;
+        let mut local = false;
+        // Whether a job forgets snapshots, which pruning removes
+        let mut policies = false;
+        for job in jobs.iter().filter(|job| job.targets(repository)) {
+            let skipped = if job.runs_on(&context.hostname) {
+                local = true;
+                excluded
+            } else {
+                excluded.or(Some(OTHER_HOST))
+            };
+            add(Some(job.clone()), UnitKind::Backup, skipped);
+            if repository.diff == Some(true) {
+                add(Some(job.clone()), UnitKind::Diff, skipped);
+            }
+            let no_policy = if job.has_forget_policy(repository) {
+                policies = true;
+                None
+            } else {
+                Some(NO_POLICY)
+            };
+            add(Some(job.clone()), UnitKind::Forget, skipped.or(no_policy));
+            if repository.rewrite == Some(true)
+                && has_exclude_patterns(job, repository)
+                && job.command.is_empty()
+            {
+                add(Some(job.clone()), UnitKind::Rewrite, skipped);
+            }
+            targeted = true;
+        }
+        if targeted {
+            // Pruning is needed if one of the jobs runs here
+            let skipped = if local {
+                excluded
+            } else {
+                excluded.or(Some(OTHER_HOST))
+            };
+            let no_policy = match repository.prune {
+                Pruning::WithForget if !policies => Some(NO_POLICY),
+                _ => None,
+            };
+            if repository.prune != Pruning::Never {
+                add(None, UnitKind::Prune, skipped.or(no_policy));
+            }
+            if repository.check == Some(true) {
+                add(None, UnitKind::Check, skipped);
+            }
+            if repository.init == Some(true) {
+                add(None, UnitKind::Init, skipped);
+            }
+            if repository.stats == Some(true) {
+                add(None, UnitKind::Stats, skipped);
+            }
+            if repository.max_age.is_some() {
+                add(None, UnitKind::Freshness, skipped);
+            }
+            if repository.mountpoint.is_some() {
+                add(None, UnitKind::Mount, skipped);
+            }
+            if repository.restore_target.is_some() {
+                add(None, UnitKind::Restore, skipped);
+            }
+            if repository.repair == Some(true) {
+                add(None, UnitKind::RepairIndex, skipped);
+                add(None, UnitKind::RepairSnapshots, skipped);
+            }
+            add(None, UnitKind::Unlock, skipped);
+            if repository.migrate == Some(true) {
+                add(None, UnitKind::Migrate, skipped);
+            }
+        }
+    }
+    for copy in config.copies.iter() {
+        let (Ok(from), Ok(to)) = (config.repository(&copy.from), config.repository(&copy.to))
+        else {
+            continue;
+        };
+        let skipped = if !from.is_enabled() || !to.is_enabled() {
+            Some("disabled")
+        } else if !from.runs_on(&context.hostname) || !to.runs_on(&context.hostname) {
+            Some(OTHER_HOST)
+        } else {
+            None
+        };
+        units.push(PlannedUnit {
+            repository: to,
+            job: None,
+            from: Some(CopyFrom {
+                repository: from,
+                calendar: copy.calendar.as_ref(),
+            }),
+            kind: UnitKind::Copy,
+            name: format!("restic-{}-to-{}-copy.service", from.name, to.name),
+            skipped,
+            stagger: None,
+            on_success: Vec::new(),
+            hosts: Vec::new(),
+        });
+    }
+    chain(&mut units);
+    if let Some(stagger) = &config.stagger {
+        // The schedule of each unit that has a timer
+        let schedules: Vec<_> = units
+            .iter()
+            .map(|unit| {
+                let scheduled =
+                    unit.skipped.is_none() && unit.has_timer() && unit.window().is_none();
+                scheduled.then(|| (unit.kind, unit.calendar(), unit.repository.name.as_str()))
+            })
+            .collect();
+        for (unit, schedule) in units.iter_mut().zip(schedules.iter()) {
+            let Some((kind, calendar, repository)) = schedule else {
+                continue;
+            };
+            let shared = schedules.iter().flatten().any(|(other_kind, other, name)| {
+                other_kind == kind && other == calendar && name != repository
+            });
+            if shared {
+                unit.stagger = Some(stagger.clone());
+            }
+        }
+    }
+    units
+}
+
+/// Render all the units described by the configuration
+pub fn generate(context: &Context, config: &Config) -> anyhow::Result<Vec<Unit>> {
+    let mut units = Vec::new();
+    let mut backups = Vec::new();
+    for planned in plan(context, config) {
+        if planned.skipped.is_some() {
+            continue;
+        }
+        if planned.kind == UnitKind::Backup {
+            backups.push(planned.name.clone());
+        }
+        units.push(Unit::render(planned.name.clone(), |out| {
+            match planned.kind {
+                UnitKind::Backup => write_backup_service(out, context, &planned),
+                UnitKind::Forget => write_forget_service(out, context, &planned),
+                UnitKind::Rewrite => write_rewrite_service(out, context, &planned),
+                UnitKind::Diff => write_diff_service(out, context, &planned),
+                UnitKind::Migrate => write_migrate_service(out, context, &planned),
+                UnitKind::Prune => write_prune_service(out, context, &planned),
+                UnitKind::Check => write_check_service(out, context, &planned),
+                UnitKind::Copy => write_copy_service(out, context, &planned),
+                UnitKind::Init => write_init_service(out, context, &planned),
+                UnitKind::Stats => write_stats_service(out, context, &planned),
+                UnitKind::Freshness => write_freshness_service(out, context, &planned),
+                UnitKind::Mount => write_mount_service(out, context, &planned),
+                UnitKind::Restore => write_restore_service(out, context, &planned),
+                UnitKind::RepairIndex | UnitKind::RepairSnapshots | UnitKind::Unlock => {
+                    write_repair_service(out, context, &planned)
+                }
+            }
+        })?);
+        if planned.has_timer() {
+            units.push(Unit::render(planned.timer_name(), |out| {
+                write_timer(out, context, &planned)
+            })?);
+        }
+    }
+    if !backups.is_empty() {
+        units.push(Unit::render(BACKUPS_TARGET.to_string(), |out| {
+            write_backups_target(out, context, &backups)
+        })?);
+    }
+    if let Some(cleanup) = &config.cache_cleanup {
+        units.push(Unit::render(CACHE_CLEANUP.to_string(), |out| {
+            write_cache_cleanup_service(out, context, cleanup)
+        })?);
+        units.push(Unit::render(
+            CACHE_CLEANUP.replace(".service", ".timer"),
+            |out| {
+                let calendar = cleanup.calendar.clone();
+                let calendar = calendar.unwrap_or_else(|| Calendar::new("weekly"));
+                write_timer_head(out, context, CACHE_CLEANUP, &calendar)?;
+                writeln!(out, "Persistent=true")?;
+                Ok(())
+            },
+        )?);
+    }
+    Ok(units)
+}
+
+/// The name of the unit cleaning up the cache, shared by the repositories
+const CACHE_CLEANUP: &str = "restic-cache-cleanup.service";
+
+/// The name of the target starting all the backups at once
+const BACKUPS_TARGET: &str = "restic-backups.target";
+
+/// Start the diff unit of the job when a backup succeeds, and the next
+/// generated unit of the repository when a backup or forget unit with
+/// chained maintenance succeeds
+fn chain(units: &mut [PlannedUnit]) {
+    for index in 0..units.len() {
+        let unit = &units[index];
+        if unit.skipped.is_some() || !matches!(unit.kind, UnitKind::Backup | UnitKind::Forget) {
+            continue;
+        }
+        let job = unit.job.as_ref().map(|job| job.name);
+        let same_job = |next: &PlannedUnit| next.job.as_ref().map(|job| job.name) == job;
+        let mut later = units[index + 1..].iter().filter(|next| {
+            std::ptr::eq(next.repository, unit.repository) && next.skipped.is_none()
+        });
+        let mut on_success = Vec::new();
+        if unit.kind == UnitKind::Backup {
+            let diff = later
+                .clone()
+                .find(|next| next.kind == UnitKind::Diff && same_job(next));
+            on_success.extend(diff.map(|diff| diff.name.clone()));
+        }
+        if unit.repository.maintenance == Maintenance::Chained {
+            let next = later.find(|next| match next.kind {
+                UnitKind::Forget => unit.kind == UnitKind::Backup && same_job(next),
+                UnitKind::Prune => unit.repository.prune == Pruning::WithForget,
+                _ => false,
+            });
+            on_success.extend(next.map(|next| next.name.clone()));
+        }
+        units[index].on_success = on_success;
+    }
+}
+
+/// Render the files described by the configuration in the given format
+pub fn render(context: &Context, config: &Config, format: Format) -> anyhow::Result<Vec<Unit>> {
+    match format {
+        Format::Systemd => generate(context, config),
+        Format::Crontab => Ok(vec![Unit::render(crontab::FILE_NAME.to_string(), |out| {
+            crontab::write(out, context, config)
+        })?]),
+        Format::Launchd => launchd::generate(context, config),
+        Format::Scripts => scripts::generate(context, config),
+    }
+}
+
+/// The commands of `unit` followed by the ones of the units of `plan` it
+/// starts when it succeeds, for the formats without `OnSuccess=`
+pub fn chained_commands(context: &Context, plan: &[PlannedUnit], unit: &PlannedUnit) -> Vec<Cmd> {
+    let mut result = commands(context, unit);
+    for name in unit.on_success.iter() {
+        let next = plan.iter().find(|next| &next.name == name);
+        if let Some(next) = next.filter(|next| !next.kind.is_systemd_only()) {
+            result.extend(chained_commands(context, plan, next));
+        }
+    }
+    result
+}
+
+/// The restic commands run by a unit, in order
+pub fn commands(context: &Context, unit: &PlannedUnit) -> Vec<Cmd> {
+    let mut command = match unit.kind {
+        UnitKind::Backup => {
+            let job = unit.job();
+            let host = job.host.unwrap_or(&context.hostname);
+            let options = unit.backup_options();
+            if job.command.is_empty() {
+                backup_cmd(job.source, host, &unit.exclude(), &options)
+            } else {
+                stdin_backup_cmd(job.command, job.stdin_filename, host, &options)
+            }
+        }
+        UnitKind::Forget => {
+            let job = unit.job();
+            let host = job.host.unwrap_or(&context.hostname);
+            forget_cmd(
+                host,
+                &job.forget_paths(),
+                &job.forget_tags,
+                &job.retention(unit.repository),
+            )
+        }
+        UnitKind::Rewrite => {
+            let job = unit.job();
+            let host = job.host.unwrap_or(&context.hostname);
+            rewrite_cmd(host, job.source, &unit.exclude(), &unit.backup_options())
+        }
+        UnitKind::Prune => prune_cmd(unit.repository),
+        UnitKind::Check => check_cmd(unit.repository),
+        UnitKind::Copy => Cmd::new("copy"),
+        UnitKind::Init => Cmd::new("init"),
+        UnitKind::Stats => {
+            let mut stats = Cmd::new("stats");
+            stats.word("--json");
+            stats
+        }
+        UnitKind::Freshness => latest_snapshots_cmd::<&str>(&unit.hosts[0], &[], 1),
+        UnitKind::Diff => {
+            let job = unit.job();
+            let host = job.host.unwrap_or(&context.hostname);
+            latest_snapshots_cmd(host, &job.snapshot_paths(), 2)
+        }
+        UnitKind::RepairIndex => {
+            let mut repair = Cmd::new("repair");
+            repair.word("index");
+            repair
+        }
+        UnitKind::RepairSnapshots => {
+            // Without --forget, the damaged snapshots are kept next to the
+            // repaired ones
+            let mut repair = Cmd::new("repair");
+            repair.word("snapshots");
+            repair
+        }
+        UnitKind::Migrate => {
+            let mut migrate = Cmd::new("migrate");
+            migrate.word("upgrade_repo_v2");
+            migrate
+        }
+        // Started by hand, e.g. when the policy never removes the locks
+        UnitKind::Unlock => unlock_cmd(unit.repository.unlock == Some(Unlock::All)),
+        UnitKind::Mount => {
+            let mut mount = Cmd::new("mount");
+            mount.word(unit.repository.mountpoint.as_deref().unwrap_or_default());
+            mount
+        }
+        UnitKind::Restore => {
+            let mut restore = Cmd::new("restore");
+            let target = unit.repository.restore_target.as_deref();
+            restore
+                .specifier("%i")
+                .option("--target", target.unwrap_or_default());
+            restore
+        }
+    };
+    let extra_args: &[String] = match unit.kind {
+        UnitKind::Backup => &unit.repository.backup_extra_args,
+        UnitKind::Forget => &unit.repository.forget_extra_args,
+        UnitKind::Prune => &unit.repository.prune_extra_args,
+        UnitKind::Check => &unit.repository.check_extra_args,
+        UnitKind::Copy
+        | UnitKind::Init
+        | UnitKind::Stats
+        | UnitKind::Freshness
+        | UnitKind::Mount
+        | UnitKind::Restore
+        | UnitKind::RepairIndex
+        | UnitKind::RepairSnapshots
+        | UnitKind::Unlock
+        | UnitKind::Rewrite
+        | UnitKind::Migrate
+        | UnitKind::Diff => &[],
+    };
+    let locks = !matches!(unit.kind, UnitKind::Init | UnitKind::Unlock);
+    if let Some(retry) = unit.repository.retry_lock.as_ref().filter(|_| locks) {
+        // restic takes a Go duration, which doesn't know all the systemd units
+        command.option("--retry-lock", format!("{}s", retry.seconds().ceil()));
+    }
+    let transfers = matches!(
+        unit.kind,
+        UnitKind::Backup | UnitKind::Prune | UnitKind::Check | UnitKind::Copy | UnitKind::Restore
+    );
+    if let Some(limit) = unit.repository.limit_upload.filter(|_| transfers) {
+        command.option("--limit-upload", limit);
+    }
+    if let Some(limit) = unit.repository.limit_download.filter(|_| transfers) {
+        command.option("--limit-download", limit);
+    }
+    command.backend_options(unit.repository);
+    if let Some(from) = &unit.from {
+        for (name, value) in from.repository.options.iter() {
+            if !unit.repository.options.contains_key(name) {
+                command.option("--option", format!("{}={}", name, value));
+            }
+        }
+    }
+    for arg in extra_args {
+        command.word(arg.as_str());
+    }
+    match unit.kind {
+        // There is nothing to unlock before the repository exists
+        UnitKind::Init | UnitKind::Unlock => return vec![command],
+        // Listing the snapshots only takes a shared lock
+        UnitKind::Diff => return vec![command],
+        UnitKind::Freshness => {
+            let listing = |host: &String| command.clone().set_option("--host", host).clone();
+            return unit.hosts.iter().map(listing).collect();
+        }
+        _ => (),
+    }
+    let mut unlock = match unit.repository.unlock.unwrap_or_default() {
+        Unlock::Stale => unlock_cmd(false),
+        Unlock::All => unlock_cmd(true),
+        Unlock::Never => return vec![command],
+    };
+    unlock.backend_options(unit.repository);
+    vec![unlock, command]
+}
+
+/// The command printing the config of the repository, for the shell of the
+/// ExecCondition lines
+fn cat_config(repository: &RepositoryConfig) -> String {
+    let mut cat = Cmd::new("cat");
+    cat.word("config").backend_options(repository);
+    // Within the double quotes of the line
+    let shell = cat.to_shell().replace('\\', "\\\\").replace('"', "\\\"");
+    shell.replace('%', "%%")
+}
+
+pub fn write_backup_service(
+    file: &mut impl Write,
+    context: &Context,
+    unit: &PlannedUnit,
+) -> anyhow::Result<()> {
+    let (job, repository) = (unit.job(), unit.repository);
+    writeln!(file, "# generated by {}", context.program_name)?;
+    writeln!(file, "[Unit]",)?;
+    writeln!(
+        file,
+        "Description=backup {} to {}",
+        job.description(),
+        &repository.location
+    )?;
+    writeln!(file, "SourcePath={}", context.config_path.display())?;
+    if !unit.on_success.is_empty() {
+        writeln!(file, "OnSuccess={}", unit.on_success.join(" "))?;
+    }
+    for path in job.source {
+        writeln!(file, "ConditionPathExists={}", path)?;
+    }
+    // The file options don't apply to the backups of commands
+    let options = if job.command.is_empty() {
+        unit.backup_options()
+    } else {
+        Default::default()
+    };
+    let lists = options.files_from.iter().chain(options.files_from_verbatim);
+    for path in lists.map(String::as_str) {
+        writeln!(file, "ConditionPathExists={}", path)?;
+    }
+    for path in options.exclude_files.iter().chain(&options.iexclude_files) {
+        writeln!(file, "ConditionPathExists={}", path)?;
+    }
+    if is_local_repository(&repository.location) {
+        writeln!(file, "ConditionPathExists={}", repository.location)?;
+    }
+    if repository.require_ac_power == Some(true) {
+        writeln!(file, "ConditionACPower=true")?;
+    }
+    if repository.init == Some(true) {
+        let init = format!("restic-{}-init.service", repository.name);
+        writeln!(file, "Wants={}", init)?;
+        writeln!(file, "After={}", init)?;
+    }
+    writeln!(file)?;
+    writeln!(file, "[Service]")?;
+    write_environment(file, repository)?;
+    writeln!(file, "Type=oneshot")?;
+    write_exec(file, &commands(context, unit))?;
+    // 3 is returned when a file cannot be read (e.g. it is removed during the backup.)
+    writeln!(file, "SuccessExitStatus=3",)?;
+    writeln!(file, "Nice=10",)?;
+    writeln!(file, "IOSchedulingClass=idle",)?;
+    Ok(())
+}
+
+pub fn write_forget_service(
+    file: &mut impl Write,
+    context: &Context,
+    unit: &PlannedUnit,
+) -> anyhow::Result<()> {
+    let (job, repository) = (unit.job(), unit.repository);
+    writeln!(file, "# generated by {}", context.program_name)?;
+    writeln!(file, "[Unit]",)?;
+    writeln!(
+        file,
+        "Description=forget {} from {}",
+        job.description(),
+        &repository.location
+    )?;
+    writeln!(file, "SourcePath={}", context.config_path.display())?;
+    if !unit.on_success.is_empty() {
+        writeln!(file, "OnSuccess={}", unit.on_success.join(" "))?;
+    }
+    writeln!(file)?;
+    writeln!(file, "[Service]")?;
+    write_environment(file, repository)?;
+    writeln!(file, "Type=oneshot")?;
+    write_exec(file, &commands(context, unit))?;
+    writeln!(file, "Nice=10",)?;
+    writeln!(file, "IOSchedulingClass=idle",)?;
+    Ok(())
+}
+
+/// The diff units run restic-generator itself, which finds the last two
+/// snapshots of the job and logs what changed between them
+pub fn write_diff_service(
+    file: &mut impl Write,
+    context: &Context,
+    unit: &PlannedUnit,
+) -> anyhow::Result<()> {
+    let (job, repository) = (unit.job(), unit.repository);
+    writeln!(file, "# generated by {}", context.program_name)?;
+    writeln!(file, "[Unit]",)?;
+    writeln!(
+        file,
+        "Description=Show what the last backup of {} to {} changed",
+        job.description(),
+        &repository.location
+    )?;
+    writeln!(file, "SourcePath={}", context.config_path.display())?;
+    writeln!(file)?;
+    writeln!(file, "[Service]")?;
+    write_environment(file, repository)?;
+    writeln!(file, "Type=oneshot")?;
+    write!(
+        file,
+        "ExecStart={} snapshot-diff {} --resolved --host {}",
+        quote_word(&context.program_path.display().to_string()),
+        quote_word(&repository.name),
+        quote_word(job.host.unwrap_or(&context.hostname))
+    )?;
+    for path in job.snapshot_paths() {
+        write!(file, " --path {}", quote_word(&path))?;
+    }
+    write_resolved_options(file, repository)?;
+    writeln!(file)?;
+    writeln!(file, "Nice=10")?;
+    writeln!(file, "IOSchedulingClass=idle")?;
+    Ok(())
+}
+
+pub fn write_rewrite_service(
+    file: &mut impl Write,
+    context: &Context,
+    unit: &PlannedUnit,
+) -> anyhow::Result<()> {
+    let (job, repository) = (unit.job(), unit.repository);
+    writeln!(file, "# generated by {}", context.program_name)?;
+    writeln!(file, "[Unit]",)?;
+    writeln!(
+        file,
+        "Description=remove the excluded files of {} from the snapshots in {}",
+        job.description(),
+        &repository.location
+    )?;
+    writeln!(file, "SourcePath={}", context.config_path.display())?;
+    writeln!(file)?;
+    writeln!(file, "[Service]")?;
+    write_environment(file, repository)?;
+    writeln!(file, "Type=oneshot")?;
+    write_exec(file, &commands(context, unit))?;
+    writeln!(file, "Nice=10",)?;
+    writeln!(file, "IOSchedulingClass=idle",)?;
+    Ok(())
+}
+
+pub fn write_prune_service(
+    file: &mut impl Write,
+    context: &Context,
+    unit: &PlannedUnit,
+) -> anyhow::Result<()> {
+    let repository = unit.repository;
+    writeln!(file, "# generated by {}", context.program_name)?;
+    writeln!(file, "[Unit]",)?;
+    writeln!(file, "Description=Prune {}", &repository.location)?;
+    writeln!(file, "SourcePath={}", context.config_path.display())?;
+    writeln!(file)?;
+    writeln!(file, "[Service]")?;
+    write_environment(file, repository)?;
+    writeln!(file, "Type=oneshot")?;
+    write_exec(file, &commands(context, unit))?;
+    if let Some(window) = unit.window() {
+        // The start of a oneshot service lasts until it exits, which is what
+        // TimeoutStartSec= limits (RuntimeMaxSec= only counts after it)
+        writeln!(file, "TimeoutStartSec={}s", window.seconds)?;
+    }
+    writeln!(file, "Nice=10")?;
+    writeln!(file, "IOSchedulingClass=idle")?;
+    Ok(())
+}
+
+pub fn write_check_service(
+    file: &mut impl Write,
+    context: &Context,
+    unit: &PlannedUnit,
+) -> anyhow::Result<()> {
+    let repository = unit.repository;
+    writeln!(file, "# generated by {}", context.program_name)?;
+    writeln!(file, "[Unit]",)?;
+    writeln!(file, "Description=Check {}", &repository.location)?;
+    writeln!(file, "SourcePath={}", context.config_path.display())?;
+    writeln!(file)?;
+    writeln!(file, "[Service]")?;
+    write_environment(file, repository)?;
+    writeln!(file, "Type=oneshot")?;
+    let mut commands = commands(context, unit);
+    if let Some(DataSubset::Rotation(parts)) = &repository.check_read_data_subset {
+        // The part read next is kept in a state file, which overrides the
+        // Environment= lines
+        let state = format!(
+            "%S/restic-generator/{}.part",
+            unit.name.trim_end_matches(".service")
+        );
+        writeln!(file, "StateDirectory=restic-generator")?;
+        writeln!(file, "Environment=RESTIC_CHECK_PART=1")?;
+        writeln!(file, "EnvironmentFile=-{}", state)?;
+        if let Some(check) = commands.last_mut() {
+            check.set_option(
+                "--read-data-subset",
+                format!("${{RESTIC_CHECK_PART}}/{}", parts),
+            );
+        }
+        write_exec(file, &commands)?;
+        writeln!(
+            file,
+            "ExecStartPost=/bin/sh -c 'echo RESTIC_CHECK_PART=$$(( ${{RESTIC_CHECK_PART}} %% {} + 1 )) > \"{}\"'",
+            parts, state
+        )?;
+    } else {
+        write_exec(file, &commands)?;
+    }
+    writeln!(file, "Nice=10")?;
+    writeln!(file, "IOSchedulingClass=idle")?;
+    Ok(())
+}
+
+pub fn write_copy_service(
+    file: &mut impl Write,
+    context: &Context,
+    unit: &PlannedUnit,
+) -> anyhow::Result<()> {
+    let (from, to) = (unit.copy_from().repository, unit.repository);
+    writeln!(file, "# generated by {}", context.program_name)?;
+    writeln!(file, "[Unit]",)?;
+    writeln!(
+        file,
+        "Description=Copy {} to {}",
+        &from.location, &to.location
+    )?;
+    writeln!(file, "SourcePath={}", context.config_path.display())?;
+    writeln!(file)?;
+    writeln!(file, "[Service]")?;
+    for (name, value) in unit.environment() {
+        writeln!(file, "{}", environment_line(name, &value))?;
+    }
+    writeln!(file, "Type=oneshot")?;
+    write_exec(file, &commands(context, unit))?;
+    writeln!(file, "Nice=10")?;
+    writeln!(file, "IOSchedulingClass=idle")?;
+    Ok(())
+}
+
+pub fn write_init_service(
+    file: &mut impl Write,
+    context: &Context,
+    unit: &PlannedUnit,
+) -> anyhow::Result<()> {
+    let repository = unit.repository;
+    let local = is_local_repository(&repository.location);
+    writeln!(file, "# generated by {}", context.program_name)?;
+    writeln!(file, "[Unit]",)?;
+    writeln!(file, "Description=Initialize {}", &repository.location)?;
+    writeln!(file, "SourcePath={}", context.config_path.display())?;
+    if local {
+        writeln!(file, "ConditionPathExists=!{}/config", repository.location)?;
+    }
+    writeln!(file)?;
+    writeln!(file, "[Service]")?;
+    write_environment(file, repository)?;
+    writeln!(file, "Type=oneshot")?;
+    if !local {
+        // Only initialize the repository if its config cannot be read
+        writeln!(
+            file,
+            "ExecCondition=/bin/sh -c \"! {}\"",
+            cat_config(repository)
+        )?;
+    }
+    write_exec(file, &commands(context, unit))?;
+    Ok(())
+}
+
+/// The stats units run restic-generator itself, which parses the output of
+/// restic stats and writes it to a state file and the journal
+pub fn write_stats_service(
+    file: &mut impl Write,
+    context: &Context,
+    unit: &PlannedUnit,
+) -> anyhow::Result<()> {
+    let repository = unit.repository;
+    writeln!(file, "# generated by {}", context.program_name)?;
+    writeln!(file, "[Unit]",)?;
+    writeln!(
+        file,
+        "Description=Record the size of {}",
+        &repository.location
+    )?;
+    writeln!(file, "SourcePath={}", context.config_path.display())?;
+    writeln!(file)?;
+    writeln!(file, "[Service]")?;
+    write_environment(file, repository)?;
+    writeln!(file, "Type=oneshot")?;
+    writeln!(file, "StateDirectory=restic-generator")?;
+    write!(
+        file,
+        "ExecStart={} stats {} --output %S/restic-generator/{}.json --resolved",
+        quote_word(&context.program_path.display().to_string()),
+        quote_word(&repository.name),
+        unit.name.trim_end_matches(".service")
+    )?;
+    write_resolved_options(file, repository)?;
+    writeln!(file)?;
+    writeln!(file, "Nice=10")?;
+    writeln!(file, "IOSchedulingClass=idle")?;
+    Ok(())
+}
+
+pub fn write_freshness_service(
+    file: &mut impl Write,
+    context: &Context,
+    unit: &PlannedUnit,
+) -> anyhow::Result<()> {
+    let repository = unit.repository;
+    writeln!(file, "# generated by {}", context.program_name)?;
+    writeln!(file, "[Unit]",)?;
+    writeln!(
+        file,
+        "Description=Check that {} has recent snapshots",
+        &repository.location
+    )?;
+    writeln!(file, "SourcePath={}", context.config_path.display())?;
+    writeln!(file)?;
+    writeln!(file, "[Service]")?;
+    write_environment(file, repository)?;
+    writeln!(file, "Type=oneshot")?;
+    let max_age = repository
+        .max_age
+        .as_ref()
+        .expect("freshness units have a max-age");
+    write!(
+        file,
+        "ExecStart={} freshness {} --resolved --max-age {}",
+        quote_word(&context.program_path.display().to_string()),
+        quote_word(&repository.name),
+        quote_word(&max_age.to_string())
+    )?;
+    for host in unit.hosts.iter() {
+        write!(file, " --host {}", quote_word(host))?;
+    }
+    write_resolved_options(file, repository)?;
+    writeln!(file)?;
+    Ok(())
+}
+
+pub fn write_mount_service(
+    file: &mut impl Write,
+    context: &Context,
+    unit: &PlannedUnit,
+) -> anyhow::Result<()> {
+    let repository = unit.repository;
+    let mountpoint = quote_word(repository.mountpoint.as_deref().unwrap_or_default());
+    writeln!(file, "# generated by {}", context.program_name)?;
+    writeln!(file, "[Unit]",)?;
+    writeln!(file, "Description=Mount {}", &repository.location)?;
+    writeln!(file, "SourcePath={}", context.config_path.display())?;
+    writeln!(file)?;
+    writeln!(file, "[Service]")?;
+    write_environment(file, repository)?;
+    writeln!(file, "Type=exec")?;
+    writeln!(file, "ExecStartPre=mkdir -p {}", mountpoint)?;
+    write_exec(file, &commands(context, unit))?;
+    // restic exits once the file system is unmounted
+    writeln!(file, "ExecStop=fusermount -u {}", mountpoint)?;
+    writeln!(file, "ExecStopPost=-fusermount -u -z {}", mountpoint)?;
+    Ok(())
+}
+
+pub fn write_restore_service(
+    file: &mut impl Write,
+    context: &Context,
+    unit: &PlannedUnit,
+) -> anyhow::Result<()> {
+    let repository = unit.repository;
+    writeln!(file, "# generated by {}", context.program_name)?;
+    writeln!(file, "[Unit]",)?;
+    writeln!(
+        file,
+        "Description=Restore snapshot %i of {}",
+        &repository.location
+    )?;
+    writeln!(file, "SourcePath={}", context.config_path.display())?;
+    writeln!(file)?;
+    writeln!(file, "[Service]")?;
+    write_environment(file, repository)?;
+    writeln!(file, "Type=oneshot")?;
+    write_exec(file, &commands(context, unit))?;
+    Ok(())
+}
+
+pub fn write_repair_service(
+    file: &mut impl Write,
+    context: &Context,
+    unit: &PlannedUnit,
+) -> anyhow::Result<()> {
+    let repository = unit.repository;
+    let description = match unit.kind {
+        UnitKind::RepairIndex => "Repair the index of",
+        UnitKind::RepairSnapshots => "Repair the snapshots of",
+        _ => "Remove the locks of",
+    };
+    writeln!(file, "# generated by {}", context.program_name)?;
+    writeln!(file, "[Unit]",)?;
+    writeln!(file, "Description={} {}", description, &repository.location)?;
+    writeln!(file, "SourcePath={}", context.config_path.display())?;
+    writeln!(file)?;
+    writeln!(file, "[Service]")?;
+    write_environment(file, repository)?;
+    writeln!(file, "Type=oneshot")?;
+    write_exec(file, &commands(context, unit))?;
+    Ok(())
+}
+
+pub fn write_migrate_service(
+    file: &mut impl Write,
+    context: &Context,
+    unit: &PlannedUnit,
+) -> anyhow::Result<()> {
+    let repository = unit.repository;
+    writeln!(file, "# generated by {}", context.program_name)?;
+    writeln!(file, "[Unit]",)?;
+    writeln!(
+        file,
+        "Description=Upgrade {} to the repository format 2",
+        &repository.location
+    )?;
+    writeln!(file, "SourcePath={}", context.config_path.display())?;
+    writeln!(file)?;
+    writeln!(file, "[Service]")?;
+    write_environment(file, repository)?;
+    writeln!(file, "Type=oneshot")?;
+    // Only migrate repositories still in the format 1
+    writeln!(
+        file,
+        "ExecCondition=/bin/sh -c \"{} | grep -q '\\\"version\\\": *1[^0-9]'\"",
+        cat_config(repository)
+    )?;
+    write_exec(file, &commands(context, unit))?;
+    Ok(())
+}
+
+pub fn write_timer(
+    file: &mut impl Write,
+    context: &Context,
+    unit: &PlannedUnit,
+) -> anyhow::Result<()> {
+    write_timer_head(file, context, &unit.name, &unit.calendar())?;
+    if let Some(delay) = unit.randomized_delay() {
+        writeln!(file, "RandomizedDelaySec={}", delay)?;
+        if unit.stagger.is_some() || unit.repository.fixed_random_delay == Some(true) {
+            writeln!(file, "FixedRandomDelay=true")?;
+        }
+    }
+    if let Some(accuracy) = &unit.repository.accuracy {
+        writeln!(file, "AccuracySec={}", accuracy)?;
+    }
+    if unit.persistent() {
+        writeln!(file, "Persistent=true")?;
+    }
+    if unit.repository.wake_system {
+        writeln!(file, "WakeSystem=true")?;
+    }
+    Ok(())
+}
+
+/// Write a timer starting `service`, up to its OnCalendar= line
+fn write_timer_head(
+    file: &mut impl Write,
+    context: &Context,
+    service: &str,
+    calendar: &Calendar,
+) -> anyhow::Result<()> {
+    writeln!(file, "# generated by {}", context.program_name)?;
+    writeln!(file, "[Unit]")?;
+    writeln!(file, "Description=Start {} on schedule", service)?;
+    writeln!(file, "SourcePath={}", context.config_path.display())?;
+    writeln!(file)?;
+    writeln!(file, "[Timer]")?;
+    writeln!(file, "OnCalendar={}", calendar)?;
+    Ok(())
+}
+
+fn write_backups_target(
+    file: &mut impl Write,
+    context: &Context,
+    backups: &[String],
+) -> anyhow::Result<()> {
+    writeln!(file, "# generated by {}", context.program_name)?;
+    writeln!(file, "[Unit]",)?;
+    writeln!(file, "Description=All restic backups")?;
+    writeln!(file, "SourcePath={}", context.config_path.display())?;
+    for backup in backups {
+        writeln!(file, "Wants={}", backup)?;
+    }
+    Ok(())
+}
+
+fn write_cache_cleanup_service(
+    file: &mut impl Write,
+    context: &Context,
+    cleanup: &CacheCleanup,
+) -> anyhow::Result<()> {
+    let mut command = Cmd::new("cache");
+    command.word("--cleanup");
+    command.option("--max-age", cleanup.max_age.unwrap_or(30));
+    writeln!(file, "# generated by {}", context.program_name)?;
+    writeln!(file, "[Unit]",)?;
+    writeln!(file, "Description=Remove the old restic cache directories")?;
+    writeln!(file, "SourcePath={}", context.config_path.display())?;
+    writeln!(file)?;
+    writeln!(file, "[Service]")?;
+    writeln!(file, "Type=oneshot")?;
+    write_exec(file, &[command])?;
+    writeln!(file, "Nice=10")?;
+    writeln!(file, "IOSchedulingClass=idle")?;
+    Ok(())
+}
+
+/// Write the commands as ExecStartPre= lines followed by the ExecStart= line
+fn write_exec(file: &mut impl Write, commands: &[Cmd]) -> anyhow::Result<()> {
+    if let Some((last, pre)) = commands.split_last() {
+        for command in pre {
+            writeln!(file, "ExecStartPre={}", command)?;
+        }
+        writeln!(file, "ExecStart={}", last)?;
+    }
+    Ok(())
+}
+
+/// The backend options of `repository`, for the `--resolved` commands of
+/// restic-generator
+fn write_resolved_options(
+    file: &mut impl Write,
+    repository: &RepositoryConfig,
+) -> anyhow::Result<()> {
+    for (name, value) in repository.options.iter() {
+        write!(
+            file,
+            " --option {}",
+            quote_word(&format!("{}={}", name, value))
+        )?;
+    }
+    Ok(())
+}
+
+pub fn write_environment(
+    file: &mut impl Write,
+    repository: &RepositoryConfig,
+) -> anyhow::Result<()> {
+    for (name, value) in restic::environment(repository) {
+        writeln!(file, "{}", environment_line(name, &value))?;
+    }
+    Ok(())
+}
+
+/// An Environment= line setting `name` to `value`, whose `%` would otherwise
+/// start a specifier, e.g. in the `%3A` of an Azure shared access signature
+fn environment_line(name: &str, value: &str) -> String {
+    format!("Environment={}=\"{}\"", name, value.replace('%', "%%"))
+}
+
+pub fn is_local_repository(location: &str) -> bool {
+    !location.starts_with("azure:")
+        && !location.starts_with("b2:")
+        && !location.starts_with("gs:")
+        && !location.starts_with("rclone:")
+        && !location.starts_with("s3:")
+        && !location.starts_with("sftp:")
+        && !location.starts_with("swift:")
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::config::CopyConfig;
+
+    /// The context of the tests of every output format
+    pub(crate) fn context() -> Context {
+        Context {
+            config_path: "config.toml".into(),
+            program_name: "restic-generator".into(),
+            program_path: "/usr/lib/systemd/system-generators/restic-generator".into(),
+            hostname: "laptop".into(),
+        }
+    }
+
+    /// The names of the services, without their timers
+    fn names(units: &[Unit]) -> Vec<&str> {
+        units
+            .iter()
+            .map(|unit| unit.name.as_str())
+            .filter(|name| name.ends_with(".service"))
+            .collect()
+    }
+
+    #[test]
+    fn timers() {
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "/repo".into(),
+                keep_last: Some(3),
+                backup_calendar: Some("hourly".parse().unwrap()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        let timers: Vec<_> = units
+            .iter()
+            .filter(|unit| unit.name.ends_with(".timer"))
+            .map(|unit| {
+                let content = String::from_utf8(unit.content.clone()).unwrap();
+                let calendar = content.lines().find(|line| line.starts_with("OnCalendar="));
+                (unit.name.as_str(), calendar.unwrap().to_string())
+            })
+            .collect();
+        assert_eq!(
+            timers,
+            vec![
+                ("restic-myrepo-backup.timer", "OnCalendar=hourly".into()),
+                ("restic-myrepo-forget.timer", "OnCalendar=weekly".into()),
+                ("restic-myrepo-prune.timer", "OnCalendar=monthly".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn timer_delays() {
+        let repository = |location: &str, delay: Option<&str>| RepositoryConfig {
+            name: "myrepo".into(),
+            location: location.into(),
+            randomized_delay: delay.map(|delay| delay.parse().unwrap()),
+            accuracy: Some("1h".parse().unwrap()),
+            ..Default::default()
+        };
+        let timer = |repository| {
+            let config = Config {
+                source: vec!["/".into()],
+                repositories: vec![repository],
+                ..Default::default()
+            };
+            let units = generate(&context(), &config).unwrap();
+            String::from_utf8(units[1].content.clone()).unwrap()
+        };
+        let local = timer(repository("/repo", None));
+        assert!(!local.contains("RandomizedDelaySec="));
+        assert!(local.contains("AccuracySec=1h\n"));
+        let remote = timer(repository("s3:host/bucket", None));
+        assert!(remote.contains("RandomizedDelaySec=30min\n"));
+        let remote = timer(repository("s3:host/bucket", Some("0")));
+        assert!(remote.contains("RandomizedDelaySec=0\n"));
+        assert!(!remote.contains("FixedRandomDelay="));
+        let fixed = timer(RepositoryConfig {
+            fixed_random_delay: Some(true),
+            ..repository("s3:host/bucket", None)
+        });
+        assert!(fixed.contains("RandomizedDelaySec=30min\nFixedRandomDelay=true\n"));
+        let local = timer(RepositoryConfig {
+            fixed_random_delay: Some(true),
+            ..repository("/repo", None)
+        });
+        assert!(!local.contains("FixedRandomDelay="));
+    }
+
+    #[test]
+    fn persistent_timers() {
+        let persistent = |persistent| {
+            let config = Config {
+                source: vec!["/".into()],
+                repositories: vec![RepositoryConfig {
+                    name: "myrepo".into(),
+                    location: "/repo".into(),
+                    keep_last: Some(3),
+                    persistent,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            };
+            let units = generate(&context(), &config).unwrap();
+            units
+                .iter()
+                .filter(|unit| unit.name.ends_with(".timer"))
+                .map(|unit| String::from_utf8_lossy(&unit.content).contains("Persistent=true\n"))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(persistent(None), vec![true, false, false]);
+        assert_eq!(persistent(Some(true)), vec![true, true, true]);
+        assert_eq!(persistent(Some(false)), vec![false, false, false]);
+    }
+
+    #[test]
+    fn prune_window() {
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "s3:host/bucket".into(),
+                keep_last: Some(3),
+                persistent: Some(true),
+                prune_window: Some("Sat 02:00..06:00".parse().unwrap()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        let content = |name: &str| {
+            let unit = units.iter().find(|unit| unit.name == name).unwrap();
+            String::from_utf8(unit.content.clone()).unwrap()
+        };
+        assert!(content("restic-myrepo-prune.service").contains("TimeoutStartSec=14400s\n"));
+        let timer = content("restic-myrepo-prune.timer");
+        assert!(timer.contains("OnCalendar=Sat 02:00\n"));
+        assert!(!timer.contains("RandomizedDelaySec="));
+        assert!(!timer.contains("Persistent="));
+        assert!(content("restic-myrepo-backup.timer").contains("Persistent=true\n"));
+    }
+
+    #[test]
+    fn stagger() {
+        let repository = |name: &str, calendar: &str| RepositoryConfig {
+            name: name.into(),
+            location: format!("/{}", name),
+            backup_calendar: Some(calendar.parse().unwrap()),
+            ..Default::default()
+        };
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![
+                repository("disk", "daily"),
+                repository("nas", "daily"),
+                repository("usb", "hourly"),
+            ],
+            stagger: Some("1h".parse().unwrap()),
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        let timer = |name: &str| {
+            let unit = units.iter().find(|unit| unit.name == name).unwrap();
+            String::from_utf8(unit.content.clone()).unwrap()
+        };
+        for name in ["restic-disk-backup.timer", "restic-nas-backup.timer"] {
+            let timer = timer(name);
+            assert!(timer.contains("RandomizedDelaySec=1h\nFixedRandomDelay=true\n"));
+        }
+        assert!(!timer("restic-usb-backup.timer").contains("RandomizedDelaySec="));
+    }
+
+    #[test]
+    fn manual_schedule() {
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![RepositoryConfig {
+                name: "archive".into(),
+                location: "/archive".into(),
+                schedule: Schedule::Manual,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        let names: Vec<_> = units.iter().map(|unit| unit.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "restic-archive-backup.service",
+                "restic-archive-unlock.service",
+                "restic-backups.target"
+            ]
+        );
+    }
+
+    #[test]
+    fn chained_maintenance() {
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "/repo".into(),
+                keep_last: Some(3),
+                maintenance: Maintenance::Chained,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        let names: Vec<_> = units.iter().map(|unit| unit.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "restic-myrepo-backup.service",
+                "restic-myrepo-backup.timer",
+                "restic-myrepo-forget.service",
+                "restic-myrepo-prune.service",
+                "restic-myrepo-unlock.service",
+                "restic-backups.target",
+            ]
+        );
+        let content = |index: usize| String::from_utf8(units[index].content.clone()).unwrap();
+        assert!(content(0).contains("OnSuccess=restic-myrepo-forget.service\n"));
+        assert!(content(2).contains("OnSuccess=restic-myrepo-prune.service\n"));
+        assert!(!content(3).contains("OnSuccess="));
+    }
+
+    #[test]
+    fn pruning() {
+        let config = |prune, maintenance| Config {
+            source: vec!["/".into()],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "/repo".into(),
+                prune,
+                maintenance,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let names = |config: &Config| -> Vec<String> {
+            let units = generate(&context(), config).unwrap();
+            units.into_iter().map(|unit| unit.name).collect()
+        };
+        let backup = ["restic-myrepo-backup.service", "restic-myrepo-backup.timer"];
+        let with = |others: &[&str]| -> Vec<String> {
+            backup
+                .iter()
+                .chain(others)
+                .chain(&["restic-myrepo-unlock.service", "restic-backups.target"])
+                .map(|name| name.to_string())
+                .collect()
+        };
+        assert_eq!(
+            names(&config(Pruning::WithForget, Maintenance::Timers)),
+            with(&[])
+        );
+        assert_eq!(
+            names(&config(Pruning::Timer, Maintenance::Timers)),
+            with(&["restic-myrepo-prune.service", "restic-myrepo-prune.timer"])
+        );
+        assert_eq!(
+            names(&config(Pruning::Manual, Maintenance::Timers)),
+            with(&["restic-myrepo-prune.service"])
+        );
+
+        let mut chained = config(Pruning::Timer, Maintenance::Chained);
+        chained.repositories[0].keep_last = Some(3);
+        let units = generate(&context(), &chained).unwrap();
+        let forget = units
+            .iter()
+            .find(|unit| unit.name == "restic-myrepo-forget.service")
+            .unwrap();
+        assert!(!String::from_utf8_lossy(&forget.content).contains("OnSuccess="));
+        assert!(units
+            .iter()
+            .any(|unit| unit.name == "restic-myrepo-prune.timer"));
+        chained.repositories[0].prune = Pruning::Never;
+        assert!(!names(&chained).iter().any(|name| name.contains("prune")));
+
+        let mut manual = config(Pruning::Manual, Maintenance::Timers);
+        manual.repositories[0].keep_last = Some(3);
+        let mut crontab = Vec::new();
+        crate::crontab::write(&mut crontab, &context(), &manual).unwrap();
+        let crontab = String::from_utf8(crontab).unwrap();
+        assert!(crontab.contains("# restic-myrepo-forget\n"));
+        assert!(!crontab.contains("prune"));
+    }
+
+    #[test]
+    fn backups_target() {
+        let repository = |name: &str, enabled| RepositoryConfig {
+            name: name.into(),
+            location: format!("/{}", name),
+            enabled,
+            ..Default::default()
+        };
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![
+                repository("disk", None),
+                repository("nas", None),
+                repository("usb", Some(false)),
+            ],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        let target = units
+            .iter()
+            .find(|unit| unit.name == "restic-backups.target")
+            .unwrap();
+        assert!(String::from_utf8_lossy(&target.content)
+            .ends_with("Wants=restic-disk-backup.service\nWants=restic-nas-backup.service\n"));
+    }
+
+    #[test]
+    fn diff_units() {
+        let config = Config {
+            source: vec!["/".into()],
+            jobs: vec![crate::config::JobConfig {
+                name: "photos".into(),
+                source: vec!["/photos".into()],
+                repositories: vec!["myrepo".into()],
+                ..Default::default()
+            }],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "/repo".into(),
+                keep_last: Some(3),
+                maintenance: Maintenance::Chained,
+                diff: Some(true),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        let content = |name: &str| {
+            let unit = units.iter().find(|unit| unit.name == name).unwrap();
+            String::from_utf8(unit.content.clone()).unwrap()
+        };
+        assert!(content("restic-photos-myrepo-backup.service").contains(
+            "OnSuccess=restic-photos-myrepo-diff.service restic-photos-myrepo-forget.service\n"
+        ));
+        assert!(content("restic-photos-myrepo-diff.service").contains(
+            "Environment=RESTIC_REPOSITORY=\"/repo\"\nType=oneshot\n\
+             ExecStart=/usr/lib/systemd/system-generators/restic-generator snapshot-diff myrepo \
+             --resolved --host laptop --path /photos\n"
+        ));
+        assert!(content("restic-myrepo-diff.service")
+            .contains("snapshot-diff myrepo --resolved --host laptop --path /\n"));
+        assert!(!units.iter().any(|unit| unit.name.ends_with("diff.timer")));
+    }
+
+    #[test]
+    fn exclude_files() {
+        let config = Config {
+            source: vec!["/home".into()],
+            exclude_file: vec!["/etc/restic/excludes".into()],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "/repo".into(),
+                iexclude_file: vec!["/etc/restic/myrepo".into()],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        let backup = String::from_utf8_lossy(&units[0].content);
+        assert!(backup.contains(
+            "ConditionPathExists=/etc/restic/excludes\nConditionPathExists=/etc/restic/myrepo\n"
+        ));
+        assert!(backup.contains(
+            "--exclude-file=\"/etc/restic/excludes\" --iexclude-file=\"/etc/restic/myrepo\" /home\n"
+        ));
+    }
+
+    #[test]
+    fn files_from() {
+        let config = Config {
+            files_from: vec!["/etc/restic/include list.txt".into()],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "sftp:host:/repo".into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        let backup = String::from_utf8_lossy(&units[0].content);
+        assert!(backup.contains("Description=backup files from /etc/restic/include list.txt to"));
+        assert!(backup.contains("ConditionPathExists=/etc/restic/include list.txt\n"));
+        assert!(backup.contains(
+            r#"ExecStart=restic backup --host="laptop" --files-from="/etc/restic/include list.txt""#
+        ));
+    }
+
+    #[test]
+    fn exclude_caches() {
+        let repository = |name: &str, exclude_caches| RepositoryConfig {
+            name: name.into(),
+            location: format!("/{}", name),
+            exclude_caches,
+            ..Default::default()
+        };
+        let config = Config {
+            source: vec!["/".into()],
+            jobs: vec![crate::config::JobConfig {
+                name: "home".into(),
+                source: vec!["/home".into()],
+                repositories: vec!["a".into()],
+                exclude_caches: true,
+                ..Default::default()
+            }],
+            repositories: vec![repository("a", false), repository("b", true)],
+            ..Default::default()
+        };
+        let planned = plan(&context(), &config);
+        let backup = |name: &str| {
+            let unit = planned.iter().find(|unit| unit.name == name).unwrap();
+            commands(&context(), unit).pop().unwrap().to_string()
+        };
+        assert!(!backup("restic-a-backup.service").contains("--exclude-caches"));
+        assert!(backup("restic-home-a-backup.service").contains("--exclude-caches"));
+        assert!(backup("restic-b-backup.service").contains("--exclude-caches"));
+    }
+
+    #[test]
+    fn command_backups() {
+        let config = Config {
+            exclude_file: vec!["/etc/restic/excludes".into()],
+            jobs: vec![crate::config::JobConfig {
+                name: "postgres".into(),
+                command: vec!["pg_dumpall".into()],
+                stdin_filename: Some("db.sql".into()),
+                repositories: vec!["myrepo".into()],
+                ..Default::default()
+            }],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "sftp:host:/repo".into(),
+                keep_daily: Some(7),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        let content = |name: &str| {
+            let unit = units.iter().find(|unit| unit.name == name).unwrap();
+            String::from_utf8(unit.content.clone()).unwrap()
+        };
+        let backup = content("restic-postgres-myrepo-backup.service");
+        assert!(backup.contains("Description=backup the output of pg_dumpall to sftp:host:/repo\n"));
+        assert!(!backup.contains("ConditionPathExists"));
+        assert!(backup.contains(
+            r#"ExecStart=restic backup --host="laptop" --stdin-from-command --stdin-filename="db.sql" -- pg_dumpall"#
+        ));
+        assert!(content("restic-postgres-myrepo-forget.service").contains(
+            r#"ExecStart=restic forget --host="laptop" --path="/db.sql" --keep-daily="7""#
+        ));
+    }
+
+    #[test]
+    fn dry_run() {
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![RepositoryConfig {
+                name: "staging".into(),
+                location: "sftp:host:/staging".into(),
+                dry_run: true,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        let backup = String::from_utf8_lossy(&units[0].content);
+        assert!(backup.contains(r#"ExecStart=restic backup --dry-run --host="laptop" /"#));
+    }
+
+    #[test]
+    fn exclude_larger_than() {
+        let size = |size: &str| Some(size.parse().unwrap());
+        let config = Config {
+            source: vec!["/".into()],
+            exclude_larger_than: size("10G"),
+            repositories: vec![
+                RepositoryConfig {
+                    name: "nas".into(),
+                    location: "/nas".into(),
+                    ..Default::default()
+                },
+                RepositoryConfig {
+                    name: "offsite".into(),
+                    location: "sftp:offsite:/restic".into(),
+                    exclude_larger_than: size("500M"),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let planned = plan(&context(), &config);
+        let backup = |name: &str| {
+            let unit = planned.iter().find(|unit| unit.name == name).unwrap();
+            commands(&context(), unit).pop().unwrap().to_string()
+        };
+        assert!(backup("restic-nas-backup.service").contains(r#"--exclude-larger-than="10G""#));
+        assert!(
+            backup("restic-offsite-backup.service").contains(r#"--exclude-larger-than="500M" /"#)
+        );
+    }
+
+    #[test]
+    fn require_ac_power() {
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "/repo".into(),
+                require_ac_power: Some(true),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        assert!(String::from_utf8_lossy(&units[0].content).contains("ConditionACPower=true\n"));
+    }
+
+    #[test]
+    fn check_units() {
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "/repo".into(),
+                check: Some(true),
+                check_with_cache: true,
+                check_read_data: true,
+                check_calendar: Some("weekly".parse().unwrap()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        assert_eq!(
+            names(&units),
+            vec![
+                "restic-myrepo-backup.service",
+                "restic-myrepo-check.service",
+                "restic-myrepo-unlock.service"
+            ]
+        );
+        let content = |name: &str| {
+            let unit = units.iter().find(|unit| unit.name == name).unwrap();
+            String::from_utf8(unit.content.clone()).unwrap()
+        };
+        assert!(content("restic-myrepo-check.service")
+            .contains("ExecStart=restic check --with-cache --read-data\n"));
+        assert!(content("restic-myrepo-check.timer").contains("OnCalendar=weekly\n"));
+    }
+
+    #[test]
+    fn check_rotation() {
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "/repo".into(),
+                check: Some(true),
+                check_read_data_subset: Some("1/30".parse().unwrap()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        let unit = units
+            .iter()
+            .find(|unit| unit.name == "restic-myrepo-check.service")
+            .unwrap();
+        let content = String::from_utf8(unit.content.clone()).unwrap();
+        assert!(content.contains(
+            "EnvironmentFile=-%S/restic-generator/restic-myrepo-check.part\n\
+             ExecStartPre=restic unlock\n\
+             ExecStart=restic check --read-data-subset=\"${RESTIC_CHECK_PART}/30\"\n\
+             ExecStartPost=/bin/sh -c 'echo RESTIC_CHECK_PART=$$(( ${RESTIC_CHECK_PART} %% 30 + 1 )) > \"%S/restic-generator/restic-myrepo-check.part\"'\n"
+        ), "{}", content);
+    }
+
+    #[test]
+    fn copy_units() {
+        let repository = |name: &str, location: &str| RepositoryConfig {
+            name: name.into(),
+            location: location.into(),
+            password_file: Some(format!("/etc/restic/{}", name)),
+            ..Default::default()
+        };
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![repository("disk", "/disk"), repository("b2", "b2:bucket")],
+            copies: vec![CopyConfig {
+                from: "disk".into(),
+                to: "b2".into(),
+                calendar: Some("weekly".parse().unwrap()),
+            }],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        let content = |name: &str| {
+            let unit = units.iter().find(|unit| unit.name == name).unwrap();
+            String::from_utf8(unit.content.clone()).unwrap()
+        };
+        let service = content("restic-disk-to-b2-copy.service");
+        assert!(service.contains(
+            "Environment=RESTIC_REPOSITORY=\"b2:bucket\"\n\
+             Environment=RESTIC_PASSWORD_FILE=\"/etc/restic/b2\"\n\
+             Environment=RESTIC_FROM_REPOSITORY=\"/disk\"\n\
+             Environment=RESTIC_FROM_PASSWORD_FILE=\"/etc/restic/disk\"\n"
+        ));
+        assert!(service.contains("ExecStart=restic copy\n"));
+        assert!(content("restic-disk-to-b2-copy.timer").contains("OnCalendar=weekly\n"));
+    }
+
+    #[test]
+    fn copy_from_cloud() {
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![
+                RepositoryConfig {
+                    name: "s3".into(),
+                    location: "s3:s3.amazonaws.com/bucket".into(),
+                    aws_access_key: Some("key".into()),
+                    aws_secret_access_key: Some("secret".into()),
+                    options: [("s3.region".to_string(), "eu-west-1".to_string())].into(),
+                    environment: [("AWS_PROFILE".to_string(), "backup".to_string())].into(),
+                    ..Default::default()
+                },
+                RepositoryConfig {
+                    name: "disk".into(),
+                    location: "/disk".into(),
+                    ..Default::default()
+                },
+            ],
+            copies: vec![CopyConfig {
+                from: "s3".into(),
+                to: "disk".into(),
+                calendar: None,
+            }],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        let unit = units
+            .iter()
+            .find(|unit| unit.name == "restic-s3-to-disk-copy.service")
+            .unwrap();
+        let service = String::from_utf8(unit.content.clone()).unwrap();
+        assert!(service.contains(
+            "Environment=RESTIC_FROM_REPOSITORY=\"s3:s3.amazonaws.com/bucket\"\n\
+             Environment=AWS_ACCESS_KEY=\"key\"\n\
+             Environment=AWS_SECRET_ACCESS_KEY=\"secret\"\n\
+             Environment=AWS_PROFILE=\"backup\"\n"
+        ));
+        assert!(service.contains("ExecStart=restic copy --option=\"s3.region=eu-west-1\"\n"));
+    }
+
+    #[test]
+    fn init_units() {
+        let repository = |name: &str, location: &str| RepositoryConfig {
+            name: name.into(),
+            location: location.into(),
+            init: Some(true),
+            ..Default::default()
+        };
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![repository("disk", "/disk"), repository("b2", "b2:bucket")],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        let content = |name: &str| {
+            let unit = units.iter().find(|unit| unit.name == name).unwrap();
+            String::from_utf8(unit.content.clone()).unwrap()
+        };
+        assert!(!units
+            .iter()
+            .any(|unit| unit.name == "restic-disk-init.timer"));
+        assert!(content("restic-disk-backup.service")
+            .contains("Wants=restic-disk-init.service\nAfter=restic-disk-init.service\n"));
+        let local = content("restic-disk-init.service");
+        assert!(local.contains("ConditionPathExists=!/disk/config\n"));
+        assert!(local.contains("Type=oneshot\nExecStart=restic init\n"));
+        let remote = content("restic-b2-init.service");
+        assert!(remote.contains("ExecCondition=/bin/sh -c \"! restic cat config\"\n"));
+    }
+
+    #[test]
+    fn stats_unit() {
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "/repo".into(),
+                stats: Some(true),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        let content = |name: &str| {
+            let unit = units.iter().find(|unit| unit.name == name).unwrap();
+            String::from_utf8(unit.content.clone()).unwrap()
+        };
+        assert!(content("restic-myrepo-stats.service").contains(
+            "Environment=RESTIC_REPOSITORY=\"/repo\"\nType=oneshot\nStateDirectory=restic-generator\n\
+             ExecStart=/usr/lib/systemd/system-generators/restic-generator stats myrepo \
+             --output %S/restic-generator/restic-myrepo-stats.json --resolved\n"
+        ));
+        assert!(content("restic-myrepo-stats.timer").contains("OnCalendar=weekly\n"));
+    }
+
+    #[test]
+    fn freshness_unit() {
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "/repo".into(),
+                max_age: Some(TimeSpan::new("2d")),
+                options: [("s3.region".to_string(), "eu-west-1".to_string())].into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        let content = |name: &str| {
+            let unit = units.iter().find(|unit| unit.name == name).unwrap();
+            String::from_utf8(unit.content.clone()).unwrap()
+        };
+        let service = content("restic-myrepo-freshness.service");
+        assert!(service.contains("Environment=RESTIC_REPOSITORY=\"/repo\"\n"));
+        assert!(service.contains(
+            "ExecStart=/usr/lib/systemd/system-generators/restic-generator freshness myrepo \
+             --resolved --max-age 2d --host laptop --option s3.region=eu-west-1\n"
+        ));
+        assert!(content("restic-myrepo-freshness.timer").contains("OnCalendar=daily\n"));
+        let mut crontab = Vec::new();
+        crate::crontab::write(&mut crontab, &context(), &config).unwrap();
+        assert!(!String::from_utf8(crontab).unwrap().contains("snapshots"));
+    }
+
+    #[test]
+    fn freshness_hosts() {
+        let job = |name: &str, host: Option<&str>| crate::config::JobConfig {
+            name: name.into(),
+            source: vec![format!("/{}", name)],
+            host: host.map(String::from),
+            repositories: vec!["myrepo".into()],
+            ..Default::default()
+        };
+        let config = Config {
+            host: Some("workstation".into()),
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "/repo".into(),
+                max_age: Some(TimeSpan::new("2d")),
+                ..Default::default()
+            }],
+            jobs: vec![
+                job("home", None),
+                job("srv", Some("server")),
+                job("etc", Some("server")),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            snapshot_hosts(&context(), &config.jobs(), &config.repositories[0]),
+            vec!["workstation", "server"]
+        );
+        let plan = plan(&context(), &config);
+        let freshness = plan
+            .iter()
+            .find(|unit| unit.kind == UnitKind::Freshness)
+            .unwrap();
+        let commands: Vec<_> = commands(&context(), freshness)
+            .iter()
+            .map(Cmd::to_string)
+            .collect();
+        assert_eq!(
+            commands,
+            vec![
+                "restic snapshots --host=\"workstation\" --latest=\"1\" --json",
+                "restic snapshots --host=\"server\" --latest=\"1\" --json",
+            ]
+        );
+        let config = Config {
+            jobs: Vec::new(),
+            source: vec!["/".into()],
+            host: None,
+            ..config
+        };
+        assert_eq!(
+            snapshot_hosts(&context(), &config.jobs(), &config.repositories[0]),
+            vec!["laptop"]
+        );
+    }
+
+    #[test]
+    fn repair_units() {
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "/repo".into(),
+                repair: Some(true),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        let content = |name: &str| {
+            let unit = units.iter().find(|unit| unit.name == name).unwrap();
+            String::from_utf8(unit.content.clone()).unwrap()
+        };
+        let index = content("restic-myrepo-repair-index.service");
+        assert!(index.contains("Environment=RESTIC_REPOSITORY=\"/repo\"\n"));
+        assert!(index.contains("ExecStart=restic repair index\n"));
+        assert!(content("restic-myrepo-repair-snapshots.service")
+            .contains("ExecStart=restic repair snapshots\n"));
+        assert!(content("restic-myrepo-unlock.service")
+            .contains("Type=oneshot\nExecStart=restic unlock\n"));
+        assert!(!units
+            .iter()
+            .any(|unit| unit.name.contains("repair") && unit.name.ends_with(".timer")));
+    }
+
+    #[test]
+    fn lock_policy() {
+        let repository = |name: &str, unlock| RepositoryConfig {
+            name: name.into(),
+            location: format!("/{}", name),
+            unlock,
+            retry_lock: Some(TimeSpan::new("5min")),
+            keep_daily: Some(7),
+            ..Default::default()
+        };
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![
+                repository("stale", None),
+                repository("all", Some(Unlock::All)),
+                repository("never", Some(Unlock::Never)),
+            ],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        let content = |name: &str| {
+            let unit = units.iter().find(|unit| unit.name == name).unwrap();
+            String::from_utf8(unit.content.clone()).unwrap()
+        };
+        assert!(content("restic-stale-prune.service").contains(
+            "ExecStartPre=restic unlock\nExecStart=restic prune --retry-lock=\"300s\"\n"
+        ));
+        assert!(content("restic-all-prune.service").contains(
+            "ExecStartPre=restic unlock --remove-all\nExecStart=restic prune --retry-lock=\"300s\"\n"
+        ));
+        let never = content("restic-never-prune.service");
+        assert!(!never.contains("ExecStartPre"));
+        assert!(never.contains("ExecStart=restic prune --retry-lock=\"300s\"\n"));
+        assert!(
+            content("restic-all-unlock.service").contains("ExecStart=restic unlock --remove-all\n")
+        );
+        assert!(content("restic-never-unlock.service").contains("ExecStart=restic unlock\n"));
+    }
+
+    #[test]
+    fn backend_options() {
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![RepositoryConfig {
+                name: "offsite".into(),
+                location: "rclone:remote:restic".into(),
+                init: Some(true),
+                options: [(
+                    "rclone.args".to_string(),
+                    "serve restic --stdio".to_string(),
+                )]
+                .into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        let content = |name: &str| {
+            let unit = units.iter().find(|unit| unit.name == name).unwrap();
+            String::from_utf8(unit.content.clone()).unwrap()
+        };
+        assert!(content("restic-offsite-backup.service").contains(
+            "ExecStartPre=restic unlock --option=\"rclone.args=serve restic --stdio\"\n\
+             ExecStart=restic backup --host=\"laptop\" / --option=\"rclone.args=serve restic --stdio\"\n"
+        ));
+        assert!(content("restic-offsite-init.service").contains(
+            "ExecCondition=/bin/sh -c \"! restic cat config '--option=rclone.args=serve restic --stdio'\"\n"
+        ));
+    }
+
+    #[test]
+    fn azure_credentials() {
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![RepositoryConfig {
+                name: "azure".into(),
+                location: "azure:backups:/".into(),
+                azure_account_name: Some("myaccount".into()),
+                azure_account_sas: Some("se=2030-01-01T00%3A00Z&sig=abc".into()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        let backup = String::from_utf8(units[0].content.clone()).unwrap();
+        assert!(backup.contains(
+            "Environment=AZURE_ACCOUNT_NAME=\"myaccount\"\n\
+             Environment=AZURE_ACCOUNT_SAS=\"se=2030-01-01T00%%3A00Z&sig=abc\"\n"
+        ));
+    }
+
+    #[test]
+    fn bandwidth_limits() {
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![RepositoryConfig {
+                name: "offsite".into(),
+                location: "sftp:host:/repo".into(),
+                keep_daily: Some(7),
+                limit_upload: Some(1024),
+                limit_download: Some(4096),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let planned = plan(&context(), &config);
+        let command = |kind: UnitKind| {
+            let unit = planned.iter().find(|unit| unit.kind == kind).unwrap();
+            commands(&context(), unit).pop().unwrap().to_string()
+        };
+        let limits = r#"--limit-upload="1024" --limit-download="4096""#;
+        assert!(command(UnitKind::Backup).ends_with(&format!("/ {}", limits)));
+        assert!(command(UnitKind::Prune).ends_with(limits));
+        assert!(!command(UnitKind::Forget).contains("--limit"));
+    }
+
+    #[test]
+    fn rewrite_units() {
+        let config = Config {
+            source: vec!["/home".into()],
+            exclude: vec!["*~".into()],
+            repositories: vec![
+                RepositoryConfig {
+                    name: "myrepo".into(),
+                    location: "/repo".into(),
+                    exclude: vec!["/home/*/.cache".into()],
+                    rewrite: Some(true),
+                    ..Default::default()
+                },
+                RepositoryConfig {
+                    name: "other".into(),
+                    location: "/other".into(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        let rewrite = units
+            .iter()
+            .find(|unit| unit.name == "restic-myrepo-rewrite.service")
+            .unwrap();
+        assert!(String::from_utf8_lossy(&rewrite.content).contains(
+            "ExecStart=restic rewrite --host=\"laptop\" --path=\"/home\" --exclude=\"*~\" \
+             --exclude=\"/home/*/.cache\" --forget\n"
+        ));
+        assert!(!units
+            .iter()
+            .any(|unit| unit.name == "restic-myrepo-rewrite.timer"));
+        assert!(!units
+            .iter()
+            .any(|unit| unit.name == "restic-other-rewrite.service"));
+    }
+
+    #[test]
+    fn rewrite_exclude_options() {
+        let config = Config {
+            source: vec!["/home".into()],
+            repositories: vec![
+                RepositoryConfig {
+                    name: "myrepo".into(),
+                    location: "/repo".into(),
+                    iexclude: vec!["*.ISO".into()],
+                    exclude_file: vec!["/etc/restic/excludes".into()],
+                    iexclude_file: vec!["/etc/restic/iexcludes".into()],
+                    rewrite: Some(true),
+                    ..Default::default()
+                },
+                RepositoryConfig {
+                    name: "caches".into(),
+                    location: "/caches".into(),
+                    exclude_caches: true,
+                    rewrite: Some(true),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        let rewrite = units
+            .iter()
+            .find(|unit| unit.name == "restic-myrepo-rewrite.service")
+            .unwrap();
+        assert!(String::from_utf8_lossy(&rewrite.content).contains(
+            "ExecStart=restic rewrite --host=\"laptop\" --path=\"/home\" --iexclude=\"*.ISO\" \
+             --exclude-file=\"/etc/restic/excludes\" --iexclude-file=\"/etc/restic/iexcludes\" \
+             --forget\n"
+        ));
+        // The caches cannot be told apart in the snapshots
+        assert!(!units
+            .iter()
+            .any(|unit| unit.name == "restic-caches-rewrite.service"));
+    }
+
+    #[test]
+    fn migrate_unit() {
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "/repo".into(),
+                migrate: Some(true),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        let migrate = units
+            .iter()
+            .find(|unit| unit.name == "restic-myrepo-migrate.service")
+            .unwrap();
+        let migrate = String::from_utf8_lossy(&migrate.content);
+        assert!(migrate.contains(
+            "ExecCondition=/bin/sh -c \"restic cat config | grep -q '\\\"version\\\": *1[^0-9]'\"\n"
+        ));
+        assert!(migrate.contains("ExecStart=restic migrate upgrade_repo_v2\n"));
+        assert!(!units
+            .iter()
+            .any(|unit| unit.name == "restic-myrepo-migrate.timer"));
+    }
+
+    #[test]
+    fn mount_unit() {
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "/repo".into(),
+                mountpoint: Some("/mnt/restic".into()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        let unit = units
+            .iter()
+            .find(|unit| unit.name == "restic-myrepo-mount.service")
+            .unwrap();
+        assert!(!units
+            .iter()
+            .any(|unit| unit.name == "restic-myrepo-mount.timer"));
+        let content = String::from_utf8(unit.content.clone()).unwrap();
+        assert!(content.contains(
+            "Type=exec\n\
+             ExecStartPre=mkdir -p /mnt/restic\n\
+             ExecStartPre=restic unlock\n\
+             ExecStart=restic mount /mnt/restic\n\
+             ExecStop=fusermount -u /mnt/restic\n"
+        ));
+    }
+
+    #[test]
+    fn restore_template() {
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "/repo".into(),
+                restore_target: Some("/srv/restore".into()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        let unit = units
+            .iter()
+            .find(|unit| unit.name == "restic-myrepo-restore@.service")
+            .unwrap();
+        let content = String::from_utf8(unit.content.clone()).unwrap();
+        assert!(content.contains("ExecStart=restic restore %i --target=\"/srv/restore\"\n"));
+    }
+
+    #[test]
+    fn cache_cleanup() {
+        let config = Config {
+            source: vec!["/".into()],
+            cache_cleanup: Some(CacheCleanup {
+                max_age: Some(7),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        let content = |name: &str| {
+            let unit = units.iter().find(|unit| unit.name == name).unwrap();
+            String::from_utf8(unit.content.clone()).unwrap()
+        };
+        assert!(content("restic-cache-cleanup.service")
+            .contains("ExecStart=restic cache --cleanup --max-age=\"7\"\n"));
+        assert!(content("restic-cache-cleanup.timer").contains("OnCalendar=weekly\n"));
+    }
+
+    #[test]
+    fn wake_system() {
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "/repo".into(),
+                wake_system: true,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        assert!(String::from_utf8_lossy(&units[1].content).contains("WakeSystem=true\n"));
+    }
+
+    #[test]
+    fn generate_without_forget_policy() {
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "/repo".into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        assert_eq!(
+            names(&units),
+            vec![
+                "restic-myrepo-backup.service",
+                "restic-myrepo-unlock.service"
+            ]
+        );
+    }
+
+    #[test]
+    fn generate_with_forget_policy() {
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "/repo".into(),
+                keep_last: Some(3),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        assert_eq!(
+            names(&units),
+            vec![
+                "restic-myrepo-backup.service",
+                "restic-myrepo-forget.service",
+                "restic-myrepo-prune.service",
+                "restic-myrepo-unlock.service"
+            ]
+        );
+    }
+
+    #[test]
+    fn plan_reports_skipped_units() {
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "/repo".into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let skipped: Vec<_> = plan(&context(), &config)
+            .into_iter()
+            .map(|unit| (unit.name, unit.skipped))
+            .collect();
+        assert_eq!(
+            skipped,
+            vec![
+                ("restic-myrepo-backup.service".to_string(), None),
+                (
+                    "restic-myrepo-forget.service".to_string(),
+                    Some("no forget policy")
+                ),
+                (
+                    "restic-myrepo-prune.service".to_string(),
+                    Some("no forget policy")
+                ),
+                ("restic-myrepo-unlock.service".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn plan_jobs() {
+        let repository = |name: &str| RepositoryConfig {
+            name: name.into(),
+            location: format!("/{}", name),
+            keep_daily: Some(7),
+            ..Default::default()
+        };
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![repository("a"), repository("b")],
+            jobs: vec![crate::config::JobConfig {
+                name: "var".into(),
+                source: vec!["/var/lib".into()],
+                repositories: vec!["b".into()],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        assert_eq!(
+            names(&units),
+            vec![
+                "restic-a-backup.service",
+                "restic-a-forget.service",
+                "restic-a-prune.service",
+                "restic-a-unlock.service",
+                "restic-b-backup.service",
+                "restic-b-forget.service",
+                "restic-var-b-backup.service",
+                "restic-var-b-forget.service",
+                "restic-b-prune.service",
+                "restic-b-unlock.service",
+            ]
+        );
+        let forget = units
+            .iter()
+            .find(|unit| unit.name == "restic-var-b-forget.service")
+            .unwrap();
+        let forget = String::from_utf8(forget.content.clone()).unwrap();
+        assert!(forget.contains(
+            r#"ExecStart=restic forget --host="laptop" --path="/var/lib" --keep-daily="7""#
+        ));
+    }
+
+    #[test]
+    fn job_retention() {
+        let config = Config {
+            repositories: vec![RepositoryConfig {
+                name: "nas".into(),
+                location: "/nas".into(),
+                ..Default::default()
+            }],
+            jobs: vec![
+                crate::config::JobConfig {
+                    name: "home".into(),
+                    source: vec!["/home".into()],
+                    repositories: vec!["nas".into()],
+                    keep_daily: Some(30),
+                    ..Default::default()
+                },
+                crate::config::JobConfig {
+                    name: "cache".into(),
+                    source: vec!["/var/cache".into()],
+                    repositories: vec!["nas".into()],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let plan = plan(&context(), &config);
+        let skipped: Vec<_> = plan
+            .iter()
+            .map(|unit| (unit.name.as_str(), unit.skipped))
+            .collect();
+        assert_eq!(
+            skipped,
+            vec![
+                ("restic-home-nas-backup.service", None),
+                ("restic-home-nas-forget.service", None),
+                ("restic-cache-nas-backup.service", None),
+                ("restic-cache-nas-forget.service", Some("no forget policy")),
+                ("restic-nas-prune.service", None),
+                ("restic-nas-unlock.service", None),
+            ]
+        );
+        assert_eq!(
+            commands(&context(), &plan[1])[1].to_string(),
+            r#"restic forget --host="laptop" --path="/home" --keep-daily="30""#
+        );
+    }
+
+    #[test]
+    fn backup_several_paths() {
+        let config = Config {
+            source: vec!["/home".into(), "/etc".into()],
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "sftp:host:/repo".into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let units = generate(&context(), &config).unwrap();
+        let backup = String::from_utf8(units[0].content.clone()).unwrap();
+        assert!(backup.contains("ConditionPathExists=/home\nConditionPathExists=/etc\n"));
+        assert!(backup.contains(r#"ExecStart=restic backup --host="laptop" /home /etc"#));
+    }
+
+    #[test]
+    fn repository_excludes() {
+        let config = Config {
+            source: vec!["/home".into()],
+            exclude: vec!["*~".into()],
+            repositories: vec![RepositoryConfig {
+                name: "offsite".into(),
+                location: "sftp:host:/repo".into(),
+                exclude: vec!["/home/*/Videos".into()],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let plan = plan(&context(), &config);
+        assert_eq!(
+            commands(&context(), &plan[0])[1].to_string(),
+            r#"restic backup --host="laptop" --exclude="*~" --exclude="/home/*/Videos" /home"#
+        );
+    }
+
+    #[test]
+    fn extra_args() {
+        let config = Config {
+            source: vec!["/home".into()],
+            repositories: vec![RepositoryConfig {
+                name: "offsite".into(),
+                location: "sftp:host:/repo".into(),
+                backup_extra_args: vec!["--tag".into(), "my tag".into()],
+                keep_daily: Some(7),
+                forget_extra_args: vec!["--group-by=host".into()],
+                prune_extra_args: vec!["--max-unused=10%".into()],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let plan = plan(&context(), &config);
+        let commands: Vec<_> = plan
+            .iter()
+            .filter(|unit| unit.kind != UnitKind::Unlock)
+            .map(|unit| commands(&context(), unit)[1].to_string())
+            .collect();
+        assert_eq!(
+            commands,
+            vec![
+                r#"restic backup --host="laptop" /home --tag "my tag""#,
+                r#"restic forget --host="laptop" --path="/home" --keep-daily="7" --group-by=host"#,
+                r#"restic prune --max-unused=10%%"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn disabled_repository() {
+        let config = Config {
+            source: vec!["/".into()],
+            repositories: vec![
+                RepositoryConfig {
+                    name: "offsite".into(),
+                    location: "/mnt/offsite".into(),
+                    enabled: Some(false),
+                    keep_daily: Some(7),
+                    ..Default::default()
+                },
+                RepositoryConfig {
+                    name: "nas".into(),
+                    location: "/mnt/nas".into(),
+                    enabled: Some(true),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let planned = plan(&context(), &config);
+        assert!(planned[..4]
+            .iter()
+            .all(|unit| unit.skipped == Some("disabled")));
+        assert_eq!(planned[4].skipped, None);
+        assert_eq!(
+            names(&generate(&context(), &config).unwrap()),
+            vec!["restic-nas-backup.service", "restic-nas-unlock.service"]
+        );
+    }
+
+    #[test]
+    fn host_filters() {
+        let repository = |name: &str, hosts: &[&str]| RepositoryConfig {
+            name: name.into(),
+            location: format!("/{}", name),
+            keep_daily: Some(7),
+            hosts: hosts.iter().map(|host| host.to_string()).collect(),
+            ..Default::default()
+        };
+        let config = Config {
+            repositories: vec![repository("disk", &["Laptop"]), repository("nas", &["nas"])],
+            jobs: vec![
+                crate::config::JobConfig {
+                    name: "home".into(),
+                    source: vec!["/home".into()],
+                    repositories: vec!["disk".into(), "nas".into()],
+                    hosts: vec!["laptop".into()],
+                    ..Default::default()
+                },
+                crate::config::JobConfig {
+                    name: "srv".into(),
+                    source: vec!["/srv".into()],
+                    repositories: vec!["nas".into()],
+                    hosts: vec!["nas".into()],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let skipped: Vec<_> = plan(&context(), &config)
+            .into_iter()
+            .map(|unit| (unit.name, unit.skipped))
+            .collect();
+        assert_eq!(
+            skipped,
+            vec![
+                ("restic-home-disk-backup.service".to_string(), None),
+                ("restic-home-disk-forget.service".to_string(), None),
+                ("restic-disk-prune.service".to_string(), None),
+                ("restic-disk-unlock.service".to_string(), None),
+                (
+                    "restic-home-nas-backup.service".to_string(),
+                    Some("not for this host")
+                ),
+                (
+                    "restic-home-nas-forget.service".to_string(),
+                    Some("not for this host")
+                ),
+                (
+                    "restic-srv-nas-backup.service".to_string(),
+                    Some("not for this host")
+                ),
+                (
+                    "restic-srv-nas-forget.service".to_string(),
+                    Some("not for this host")
+                ),
+                (
+                    "restic-nas-prune.service".to_string(),
+                    Some("not for this host")
+                ),
+                (
+                    "restic-nas-unlock.service".to_string(),
+                    Some("not for this host")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn repository_without_job() {
+        let config = Config {
+            repositories: vec![RepositoryConfig {
+                name: "myrepo".into(),
+                location: "/repo".into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert!(plan(&context(), &config).is_empty());
+    }
+
+    macro_rules! test_is_local_repository {
+        ($name:ident, $location:expr) => {
+            #[test]
+            fn $name() {
+                assert!(is_local_repository($location));
+            }
+        };
+        (!$name:ident, $location:expr) => {
+            #[test]
+            fn $name() {
+                assert!(!is_local_repository($location));
+            }
+        };
+    }
+
+    test_is_local_repository!(abs_path_is_local, "/media/backup");
+    test_is_local_repository!(systmed_home_is_local, "%h/backup");
+    test_is_local_repository!(!sftp_is_not_local, "sftp:user@host:/srv/restic-repo");
+    test_is_local_repository!(!s3_is_not_local, "s3:s3.amazonaws.com/bucket_name");
+    test_is_local_repository!(!swift_is_not_local, "swift:container_name:/path");
+    test_is_local_repository!(!b2_is_not_local, "b2:bucketname:path/to/repo");
+    test_is_local_repository!(!azure_is_not_local, "azure:foo:/");
+    test_is_local_repository!(!gs_is_not_local, "gs:foo:/");
+    test_is_local_repository!(!rclone_is_not_local, "rclone:foo:bar");
+}
@@ -0,0 +1,478 @@
+use std::{collections::HashSet, fmt};
+
+use crate::{
+    config::{Config, RepositoryConfig},
+    restic,
+};
+
+/// Something wrong with the configuration
+#[derive(Debug, PartialEq, Eq)]
+pub struct Problem {
+    /// The name of the repository concerned, if any
+    pub repository: Option<String>,
+    pub message: String,
+    /// Whether the units can work anyway, e.g. with settings added by a
+    /// drop-in, so that generating them is not stopped
+    pub warning: bool,
+}
+
+impl Problem {
+    fn global(message: impl Into<String>) -> Problem {
+        Problem {
+            repository: None,
+            message: message.into(),
+            warning: false,
+        }
+    }
+
+    fn repository(repository: &RepositoryConfig, message: impl Into<String>) -> Problem {
+        Problem {
+            repository: Some(repository.name.clone()),
+            message: message.into(),
+            warning: false,
+        }
+    }
+
+    fn warning(self) -> Problem {
+        Problem {
+            warning: true,
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.repository {
+            Some(name) => write!(f, "repository {}: {}", name, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Run the semantic checks on a parsed configuration
+pub fn validate(config: &Config) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    // Without jobs, the top-level source is the only thing to back up
+    let lists = !config.files_from.is_empty() || !config.files_from_verbatim.is_empty();
+    if (config.jobs.is_empty() || !config.source.is_empty()) && is_empty(&config.source, lists) {
+        problems.push(Problem::global("source is empty"));
+    }
+    let mut job_names = HashSet::new();
+    for job in config.jobs.iter() {
+        if job.name.is_empty() {
+            problems.push(Problem::global(format!(
+                "job for {} has an empty name",
+                job.source.join(" ")
+            )));
+        } else if !job_names.insert(job.name.as_str()) {
+            problems.push(Problem::global(format!(
+                "job {}: duplicate job name",
+                job.name
+            )));
+        }
+        let lists = !job.files_from.is_empty() || !job.files_from_verbatim.is_empty();
+        if !job.command.is_empty() {
+            if !job.source.is_empty() || lists {
+                problems.push(Problem::global(format!(
+                    "job {}: command and source are both set",
+                    job.name
+                )));
+            }
+        } else if job.stdin_filename.is_some() {
+            problems.push(Problem::global(format!(
+                "job {}: stdin-filename is set without a command",
+                job.name
+            )));
+        } else if is_empty(&job.source, lists) {
+            problems.push(Problem::global(format!(
+                "job {}: source is empty",
+                job.name
+            )));
+        }
+        if job.repositories.is_empty() {
+            problems.push(Problem::global(format!(
+                "job {}: no repositories to back up to",
+                job.name
+            )));
+        }
+        for name in job.repositories.iter() {
+            if !config
+                .repositories
+                .iter()
+                .any(|repository| &repository.name == name)
+            {
+                problems.push(Problem::global(format!(
+                    "job {}: unknown repository {}",
+                    job.name, name
+                )));
+            }
+        }
+    }
+    for copy in config.copies.iter() {
+        if copy.from == copy.to {
+            problems.push(Problem::global(format!(
+                "copy {} to {}: copies a repository to itself",
+                copy.from, copy.to
+            )));
+        }
+        let find = |name: &str| config.repositories.iter().find(|r| r.name == name);
+        if let (Some(from), Some(to)) = (find(&copy.from), find(&copy.to)) {
+            // restic copy reads a single set of backend settings for both
+            let mut shared: Vec<(String, &str, &str)> = Vec::new();
+            let (source, destination) = (shared_environment(from), shared_environment(to));
+            for (name, value) in source.iter() {
+                if let Some((_, other)) = destination.iter().find(|(other, _)| other == name) {
+                    shared.push((format!("environment variable {}", name), value, other));
+                }
+            }
+            for (name, value) in from.options.iter() {
+                if let Some(other) = to.options.get(name) {
+                    shared.push((format!("option {}", name), value, other));
+                }
+            }
+            for (setting, value, other) in shared {
+                if value != other {
+                    problems.push(Problem::global(format!(
+                        "copy {} to {}: the repositories have different values for {}",
+                        copy.from, copy.to, setting
+                    )));
+                }
+            }
+        }
+    }
+    let mut names = HashSet::new();
+    for repository in config.repositories.iter() {
+        if repository.name.is_empty() {
+            problems.push(Problem::global(format!(
+                "repository at {} has an empty name",
+                repository.location
+            )));
+        } else if !names.insert(repository.name.as_str()) {
+            problems.push(Problem::repository(repository, "duplicate repository name"));
+        }
+        match (&repository.password_file, &repository.password_command) {
+            // The password can still come from a drop-in setting
+            // RESTIC_PASSWORD_FILE, or from the environment of the crontab
+            (None, None) => problems.push(
+                Problem::repository(
+                    repository,
+                    "no password source (set password-file or password-command)",
+                )
+                .warning(),
+            ),
+            (Some(_), Some(_)) => problems.push(Problem::repository(
+                repository,
+                "password-file and password-command are both set",
+            )),
+            _ => (),
+        }
+        if repository.azure_account_key.is_some() && repository.azure_account_sas.is_some() {
+            problems.push(Problem::repository(
+                repository,
+                "azure-account-key and azure-account-sas are both set",
+            ));
+        }
+        if let Some(size) = repository.pack_size {
+            if !(4..=128).contains(&size) {
+                problems.push(Problem::repository(
+                    repository,
+                    format!("pack-size {} is not between 4 and 128 MiB", size),
+                ));
+            }
+        }
+        for name in repository.environment.keys() {
+            if !is_variable_name(name) {
+                problems.push(Problem::repository(
+                    repository,
+                    format!("invalid environment variable name {:?}", name),
+                ));
+            }
+        }
+    }
+    problems
+}
+
+/// The environment variables of `repository` that restic copy shares between
+/// the source and the destination
+fn shared_environment(repository: &RepositoryConfig) -> Vec<(String, String)> {
+    let credentials = restic::credentials(repository).into_iter();
+    let credentials = credentials.map(|(name, value)| (name.to_string(), value));
+    let environment = repository.environment.iter();
+    credentials
+        .chain(environment.map(|(name, value)| (name.clone(), value.clone())))
+        .collect()
+}
+
+/// Whether `name` can be the name of an environment variable in systemd
+/// units and shells
+fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Whether a source has blank paths, or none and no files-from lists
+fn is_empty(source: &[String], lists: bool) -> bool {
+    (source.is_empty() && !lists) || source.iter().any(|path| path.trim().is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CopyConfig, JobConfig};
+
+    fn repository(name: &str) -> RepositoryConfig {
+        RepositoryConfig {
+            name: name.into(),
+            location: format!("/{}", name),
+            password_file: Some("/password".into()),
+            ..Default::default()
+        }
+    }
+
+    fn config(repositories: Vec<RepositoryConfig>) -> Config {
+        Config {
+            source: vec!["/".into()],
+            repositories,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn valid_config() {
+        assert_eq!(
+            validate(&config(vec![repository("a"), repository("b")])),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn empty_source() {
+        let config = Config {
+            source: vec![" ".into()],
+            ..Default::default()
+        };
+        assert_eq!(validate(&config), vec![Problem::global("source is empty")]);
+    }
+
+    #[test]
+    fn files_from_source() {
+        let config = Config {
+            files_from: vec!["/etc/restic/include.txt".into()],
+            repositories: vec![repository("a")],
+            ..Default::default()
+        };
+        assert_eq!(validate(&config), vec![]);
+    }
+
+    #[test]
+    fn jobs_replace_source() {
+        let config = Config {
+            jobs: vec![JobConfig {
+                name: "home".into(),
+                source: vec!["/home".into()],
+                repositories: vec!["a".into()],
+                ..Default::default()
+            }],
+            ..config(vec![repository("a")])
+        };
+        let config = Config {
+            source: vec![],
+            ..config
+        };
+        assert_eq!(validate(&config), vec![]);
+    }
+
+    #[test]
+    fn command_jobs() {
+        let job = |name: &str, source: Vec<String>, command: Vec<String>| JobConfig {
+            name: name.into(),
+            source,
+            command,
+            stdin_filename: Some("db.sql".into()),
+            repositories: vec!["a".into()],
+            ..Default::default()
+        };
+        let config = Config {
+            jobs: vec![
+                job("postgres", vec![], vec!["pg_dumpall".into()]),
+                job("both", vec!["/home".into()], vec!["pg_dumpall".into()]),
+                job("neither", vec!["/home".into()], vec![]),
+            ],
+            ..config(vec![repository("a")])
+        };
+        let problems: Vec<_> = validate(&config)
+            .iter()
+            .map(|problem| problem.to_string())
+            .collect();
+        assert_eq!(
+            problems,
+            vec![
+                "job both: command and source are both set",
+                "job neither: stdin-filename is set without a command"
+            ]
+        );
+    }
+
+    #[test]
+    fn job_problems() {
+        let job = |name: &str, repositories: &[&str]| JobConfig {
+            name: name.into(),
+            source: vec!["/home".into()],
+            repositories: repositories.iter().map(|name| name.to_string()).collect(),
+            ..Default::default()
+        };
+        let config = Config {
+            jobs: vec![job("home", &["a", "b"]), job("home", &[])],
+            ..config(vec![repository("a")])
+        };
+        let problems: Vec<_> = validate(&config)
+            .iter()
+            .map(|problem| problem.to_string())
+            .collect();
+        assert_eq!(
+            problems,
+            vec![
+                "job home: unknown repository b",
+                "job home: duplicate job name",
+                "job home: no repositories to back up to",
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_names() {
+        let problems = validate(&config(vec![repository("a"), repository("a")]));
+        assert_eq!(problems.len(), 1);
+        assert_eq!(
+            problems[0].to_string(),
+            "repository a: duplicate repository name"
+        );
+    }
+
+    #[test]
+    fn missing_password() {
+        let repo = RepositoryConfig {
+            password_file: None,
+            ..repository("a")
+        };
+        let problems = validate(&config(vec![repo]));
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].repository.as_deref(), Some("a"));
+        assert!(problems[0].warning);
+    }
+
+    #[test]
+    fn password_command_is_a_password_source() {
+        let repo = RepositoryConfig {
+            password_file: None,
+            password_command: Some("pass restic".into()),
+            ..repository("a")
+        };
+        assert_eq!(validate(&config(vec![repo])), vec![]);
+    }
+
+    #[test]
+    fn conflicting_password_sources() {
+        let repo = RepositoryConfig {
+            password_command: Some("pass restic".into()),
+            ..repository("a")
+        };
+        assert_eq!(
+            validate(&config(vec![repo]))[0].to_string(),
+            "repository a: password-file and password-command are both set"
+        );
+    }
+
+    #[test]
+    fn conflicting_azure_credentials() {
+        let repo = RepositoryConfig {
+            location: "azure:backups:/".into(),
+            azure_account_name: Some("myaccount".into()),
+            azure_account_key: Some("key".into()),
+            azure_account_sas: Some("sas".into()),
+            ..repository("a")
+        };
+        assert_eq!(
+            validate(&config(vec![repo]))[0].to_string(),
+            "repository a: azure-account-key and azure-account-sas are both set"
+        );
+    }
+
+    #[test]
+    fn copy_to_itself() {
+        let config = Config {
+            copies: vec![CopyConfig {
+                from: "a".into(),
+                to: "a".into(),
+                ..Default::default()
+            }],
+            ..config(vec![repository("a")])
+        };
+        assert_eq!(
+            validate(&config)[0].to_string(),
+            "copy a to a: copies a repository to itself"
+        );
+    }
+
+    #[test]
+    fn copy_shared_settings() {
+        let repo = |name: &str, key: &str, region: &str| RepositoryConfig {
+            aws_access_key: Some(key.into()),
+            options: [("s3.region".to_string(), region.to_string())].into(),
+            ..repository(name)
+        };
+        let copy = |from: &str, to: &str| CopyConfig {
+            from: from.into(),
+            to: to.into(),
+            ..Default::default()
+        };
+        let config = Config {
+            copies: vec![copy("a", "b"), copy("a", "c")],
+            ..config(vec![
+                repo("a", "key", "eu-west-1"),
+                repo("b", "key", "eu-west-1"),
+                repo("c", "other", "us-east-1"),
+            ])
+        };
+        let problems = validate(&config);
+        assert_eq!(
+            problems.iter().map(|p| p.to_string()).collect::<Vec<_>>(),
+            vec![
+                "copy a to c: the repositories have different values for environment variable AWS_ACCESS_KEY",
+                "copy a to c: the repositories have different values for option s3.region",
+            ]
+        );
+    }
+
+    #[test]
+    fn pack_sizes() {
+        let repo = |name: &str, pack_size| RepositoryConfig {
+            pack_size: Some(pack_size),
+            ..repository(name)
+        };
+        let problems = validate(&config(vec![repo("a", 64), repo("b", 256)]));
+        assert_eq!(
+            problems.iter().map(|p| p.to_string()).collect::<Vec<_>>(),
+            vec!["repository b: pack-size 256 is not between 4 and 128 MiB"]
+        );
+    }
+
+    #[test]
+    fn environment_names() {
+        let repo = RepositoryConfig {
+            environment: [
+                ("GOGC".to_string(), "20".to_string()),
+                ("HTTPS-PROXY".to_string(), "http://proxy".to_string()),
+            ]
+            .into(),
+            ..repository("a")
+        };
+        let problems = validate(&config(vec![repo]));
+        assert_eq!(
+            problems.iter().map(|p| p.to_string()).collect::<Vec<_>>(),
+            vec![r#"repository a: invalid environment variable name "HTTPS-PROXY""#]
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use anyhow::Context as _;
+use std::{
+    env, fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{self, Command},
+};
+
+use crate::units::Unit;
+
+/// What `systemd-analyze verify` has to say about one unit
+#[derive(Debug, PartialEq, Eq)]
+pub struct Report {
+    pub unit: String,
+    pub success: bool,
+    pub messages: Vec<String>,
+}
+
+/// A temporary directory, removed when dropped
+struct TempDir(PathBuf);
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Write the units to a temporary directory and run `systemd-analyze verify`
+/// on each of them
+pub fn run(units: &[Unit], user: bool) -> anyhow::Result<Vec<Report>> {
+    let dir = TempDir(env::temp_dir().join(format!("restic-generator-verify-{}", process::id())));
+    fs::create_dir_all(&dir.0)
+        .with_context(|| format!("{}: error creating directory", dir.0.display()))?;
+    let mut reports = Vec::new();
+    for unit in units {
+        let path = dir.0.join(&unit.name);
+        fs::write(&path, &unit.content)
+            .with_context(|| format!("{}: error creating file", path.display()))?;
+        let mut cmd = Command::new("systemd-analyze");
+        if user {
+            cmd.arg("--user");
+        }
+        let output = cmd
+            .arg("verify")
+            .arg(&path)
+            .output()
+            .with_context(|| "error running systemd-analyze")?;
+        reports.push(report(
+            &unit.name,
+            output.status.success(),
+            &String::from_utf8_lossy(&output.stderr),
+            &dir.0,
+        ));
+    }
+    Ok(reports)
+}
+
+fn report(unit: &str, success: bool, stderr: &str, dir: &Path) -> Report {
+    // Show the unit names rather than the paths to the temporary files
+    let prefix = format!("{}/", dir.display());
+    let mut messages: Vec<_> = stderr
+        .lines()
+        .map(|line| line.trim().replace(&prefix, ""))
+        .filter(|line| !line.is_empty())
+        .collect();
+    // The ExecStart= and ExecStartPre= lines get the same complaints
+    messages.dedup();
+    Report {
+        unit: unit.to_string(),
+        success,
+        messages,
+    }
+}
+
+pub fn print(out: &mut impl Write, reports: &[Report]) -> anyhow::Result<()> {
+    for report in reports {
+        if report.messages.is_empty() {
+            let status = if report.success { "ok" } else { "failed" };
+            writeln!(out, "{}: {}", report.unit, status)?;
+        }
+        for message in report.messages.iter() {
+            writeln!(out, "{}", message)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_name_the_unit() {
+        let report = report(
+            "restic-myrepo-backup.service",
+            true,
+            "/tmp/dir/restic-myrepo-backup.service:12: Unknown key name 'Nicee' in section 'Service', ignoring.\n\n",
+            Path::new("/tmp/dir"),
+        );
+        assert_eq!(
+            report.messages,
+            vec!["restic-myrepo-backup.service:12: Unknown key name 'Nicee' in section 'Service', ignoring."]
+        );
+    }
+
+    #[test]
+    fn print_reports() {
+        let reports = [
+            Report {
+                unit: "a.service".into(),
+                success: true,
+                messages: vec![],
+            },
+            Report {
+                unit: "b.service".into(),
+                success: false,
+                messages: vec!["b.service: Command restic is not executable".into()],
+            },
+        ];
+        let mut out = Vec::new();
+        print(&mut out, &reports).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "a.service: ok\nb.service: Command restic is not executable\n"
+        );
+    }
+}
@@ -0,0 +1,317 @@
+use assert_cmd::prelude::*; // Add methods on commands
+use std::{error::Error, fs, process::Command};
+use tempfile::TempDir;
+
+fn render(out_dir: &TempDir) -> Result<Command, Box<dyn Error>> {
+    let mut cmd = Command::cargo_bin("restic-generator")?;
+    cmd.arg("-c")
+        .arg("example-config.toml")
+        .arg("render")
+        .arg("--out-dir")
+        .arg(out_dir.path());
+    Ok(cmd)
+}
+
+#[test]
+fn render_writes_units() -> Result<(), Box<dyn Error>> {
+    let out_dir = TempDir::new()?;
+    render(&out_dir)?.assert().success();
+    assert!(out_dir.path().join("restic-myrepo-backup.service").exists());
+    assert!(out_dir
+        .path()
+        .join("restic-s3bucket-backup.service")
+        .exists());
+    Ok(())
+}
+
+#[test]
+fn render_refuses_to_overwrite() -> Result<(), Box<dyn Error>> {
+    let out_dir = TempDir::new()?;
+    fs::write(out_dir.path().join("restic-myrepo-backup.service"), "mine")?;
+    render(&out_dir)?.assert().failure();
+    assert!(!out_dir
+        .path()
+        .join("restic-s3bucket-backup.service")
+        .exists());
+    Ok(())
+}
+
+#[test]
+fn render_skip_existing() -> Result<(), Box<dyn Error>> {
+    let out_dir = TempDir::new()?;
+    let existing = out_dir.path().join("restic-myrepo-backup.service");
+    fs::write(&existing, "mine")?;
+    render(&out_dir)?.arg("--skip-existing").assert().success();
+    assert_eq!(fs::read_to_string(&existing)?, "mine");
+    assert!(out_dir
+        .path()
+        .join("restic-s3bucket-backup.service")
+        .exists());
+    Ok(())
+}
+
+#[test]
+fn render_force() -> Result<(), Box<dyn Error>> {
+    let out_dir = TempDir::new()?;
+    let existing = out_dir.path().join("restic-myrepo-backup.service");
+    fs::write(&existing, "mine")?;
+    render(&out_dir)?.arg("--force").assert().success();
+    assert_ne!(fs::read_to_string(&existing)?, "mine");
+    Ok(())
+}
+
+#[test]
+fn validate_valid_config() -> Result<(), Box<dyn Error>> {
+    let dir = TempDir::new()?;
+    let config = dir.path().join("config.toml");
+    fs::write(
+        &config,
+        r#"
+source = "/"
+
+[[repositories]]
+name = "repo"
+location = "/repo"
+password-file = "/etc/restic/password"
+keep-within = "2y5m7d3h"
+"#,
+    )?;
+    let mut cmd = Command::cargo_bin("restic-generator")?;
+    cmd.arg("-c").arg(&config).arg("validate");
+    cmd.assert().success();
+    Ok(())
+}
+
+#[test]
+fn validate_reports_problems() -> Result<(), Box<dyn Error>> {
+    let dir = TempDir::new()?;
+    let config = dir.path().join("config.toml");
+    fs::write(
+        &config,
+        r#"
+source = ""
+
+[[repositories]]
+name = "repo"
+location = "/repo"
+"#,
+    )?;
+    let mut cmd = Command::cargo_bin("restic-generator")?;
+    cmd.arg("-c").arg(&config).arg("validate");
+    let output = cmd.assert().failure().get_output().stdout.clone();
+    let output = String::from_utf8(output)?;
+    assert!(output.contains("source is empty"));
+    assert!(output.contains("repository repo: no password source"));
+    Ok(())
+}
+
+#[test]
+fn render_fails_on_problems() -> Result<(), Box<dyn Error>> {
+    let dir = TempDir::new()?;
+    let config = dir.path().join("config.toml");
+    let content = r#"
+source = "/"
+
+[[repositories]]
+name = "repo"
+location = "/repo"
+password-file = "/password"
+password-command = "pass restic"
+"#;
+    fs::write(&config, content)?;
+    let render = || -> Result<Command, Box<dyn Error>> {
+        let mut cmd = Command::cargo_bin("restic-generator")?;
+        cmd.arg("-c").arg(&config).arg("render").arg("--stdout");
+        Ok(cmd)
+    };
+    let output = render()?.assert().failure().get_output().stderr.clone();
+    let output = String::from_utf8(output)?;
+    assert!(output.contains("repository repo: password-file and password-command are both set"));
+    fs::write(&config, format!("on-problems = \"warn\"\n{}", content))?;
+    let output = render()?.assert().success().get_output().stderr.clone();
+    assert!(String::from_utf8(output)?.contains("warning: "));
+    Ok(())
+}
+
+#[test]
+fn preview_forget_skips_disabled_repositories() -> Result<(), Box<dyn Error>> {
+    let dir = TempDir::new()?;
+    let config = dir.path().join("config.toml");
+    fs::write(
+        &config,
+        r#"
+source = "/"
+
+[[repositories]]
+name = "repo"
+location = "/repo"
+password-file = "/password"
+keep-daily = 7
+enabled = false
+"#,
+    )?;
+    let mut cmd = Command::cargo_bin("restic-generator")?;
+    cmd.arg("-c").arg(&config).arg("preview-forget").arg("repo");
+    let output = cmd.assert().failure().get_output().stderr.clone();
+    assert!(String::from_utf8(output)?.contains("repository repo has no forget unit (disabled)"));
+    Ok(())
+}
+
+#[test]
+fn invalid_duration_is_an_error() -> Result<(), Box<dyn Error>> {
+    let dir = TempDir::new()?;
+    let config = dir.path().join("config.toml");
+    fs::write(
+        &config,
+        r#"
+source = "/"
+
+[[repositories]]
+name = "repo"
+location = "/repo"
+keep-within = "2x5q"
+"#,
+    )?;
+    let mut cmd = Command::cargo_bin("restic-generator")?;
+    cmd.arg("-c").arg(&config).arg("validate");
+    let output = cmd.assert().failure().get_output().stderr.clone();
+    let output = String::from_utf8(output)?;
+    assert!(output.contains("repositories[0].keep-within at line 7: invalid duration \"2x5q\""));
+    Ok(())
+}
+
+#[test]
+fn render_scripts_are_executable() -> Result<(), Box<dyn Error>> {
+    use std::os::unix::fs::PermissionsExt;
+    let out_dir = TempDir::new()?;
+    render(&out_dir)?
+        .args(["--format", "scripts"])
+        .assert()
+        .success();
+    let script = out_dir.path().join("backup-myrepo.sh");
+    assert_eq!(fs::metadata(&script)?.permissions().mode() & 0o111, 0o111);
+    assert!(out_dir.path().join("prune-myrepo.sh").exists());
+    assert!(!out_dir.path().join("forget-s3bucket.sh").exists());
+    Ok(())
+}
+
+#[test]
+fn generator_writes_manifest() -> Result<(), Box<dyn Error>> {
+    let normal_dir = TempDir::new()?;
+    let mut cmd = Command::cargo_bin("restic-generator")?;
+    cmd.arg("-c")
+        .arg("example-config.toml")
+        .arg(normal_dir.path());
+    cmd.assert().success();
+    let manifest = fs::read_to_string(normal_dir.path().join("restic-generator.manifest.json"))?;
+    assert!(manifest.contains("restic-s3bucket-backup.service"));
+    Ok(())
+}
+
+#[test]
+fn generator_enables_timers() -> Result<(), Box<dyn Error>> {
+    let normal_dir = TempDir::new()?;
+    let mut cmd = Command::cargo_bin("restic-generator")?;
+    cmd.arg("-c")
+        .arg("example-config.toml")
+        .arg(normal_dir.path());
+    cmd.assert().success();
+    let link = normal_dir
+        .path()
+        .join("timers.target.wants/restic-s3bucket-backup.timer");
+    assert_eq!(
+        fs::read_link(&link)?,
+        normal_dir.path().join("restic-s3bucket-backup.timer")
+    );
+    Ok(())
+}
+
+#[test]
+fn sops_encrypted_config() -> Result<(), Box<dyn Error>> {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = TempDir::new()?;
+    let config = dir.path().join("config.yaml");
+    fs::write(
+        &config,
+        "source: ENC[AES256_GCM,data:...]\nsops:\n  version: 3.8.1\n",
+    )?;
+    // A fake sops printing the decrypted configuration
+    let bin = dir.path().join("bin");
+    fs::create_dir(&bin)?;
+    let sops = bin.join("sops");
+    fs::write(
+        &sops,
+        r#"#!/bin/sh
+[ "$1" = --decrypt ] || exit 1
+cat <<EOF
+source: /home
+repositories:
+  - name: nas
+    location: /mnt/nas
+    password-file: /etc/restic/nas
+EOF
+"#,
+    )?;
+    fs::set_permissions(&sops, fs::Permissions::from_mode(0o755))?;
+    let mut cmd = Command::cargo_bin("restic-generator")?;
+    let path = format!("{}:{}", bin.display(), std::env::var("PATH")?);
+    cmd.env("PATH", path)
+        .arg("-c")
+        .arg(&config)
+        .arg("render")
+        .arg("--stdout");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    assert!(String::from_utf8(output)?.contains("/mnt/nas"));
+    Ok(())
+}
+
+#[test]
+fn commands_without_config_need_no_home() -> Result<(), Box<dyn Error>> {
+    for args in [&["schema"][..], &["completions", "bash"]] {
+        Command::cargo_bin("restic-generator")?
+            .arg("--user")
+            .args(args)
+            .env_remove("HOME")
+            .env_remove("XDG_CONFIG_HOME")
+            .env_remove("RESTIC_GENERATOR_CONFIG")
+            .assert()
+            .success();
+    }
+    Ok(())
+}
+
+#[test]
+fn resolved_freshness_reads_no_config() -> Result<(), Box<dyn Error>> {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = TempDir::new()?;
+    // A fake restic listing a snapshot made now, if given the settings
+    let restic = dir.path().join("restic");
+    fs::write(
+        &restic,
+        r#"#!/bin/sh
+[ "$RESTIC_REPOSITORY" = /repo ] || exit 1
+case "$*" in
+  *--host=laptop*--option=s3.region=eu-west-1*) ;;
+  *) exit 1 ;;
+esac
+echo "[{\"time\": \"$(date -u +%Y-%m-%dT%H:%M:%SZ)\", \"short_id\": \"abcdef\"}]"
+"#,
+    )?;
+    fs::set_permissions(&restic, fs::Permissions::from_mode(0o755))?;
+    let path = format!("{}:{}", dir.path().display(), std::env::var("PATH")?);
+    let output = Command::cargo_bin("restic-generator")?
+        .env("PATH", path)
+        .env("RESTIC_REPOSITORY", "/repo")
+        .arg("-c")
+        .arg(dir.path().join("missing.toml"))
+        .args(["freshness", "myrepo", "--resolved", "--max-age", "1d"])
+        .args(["--host", "laptop", "--option", "s3.region=eu-west-1"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert!(String::from_utf8(output)?
+        .starts_with("the latest snapshot of laptop in repository myrepo (abcdef) is "));
+    Ok(())
+}
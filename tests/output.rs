@@ -46,6 +46,12 @@ snapshot_test!(
     "restic-myrepo-prune.service"
 );
 
+snapshot_test!(
+    local_backup_timer,
+    "example-config.toml",
+    "restic-myrepo-backup.timer"
+);
+
 snapshot_test!(
     remote_backup_service,
     "example-config.toml",
@@ -57,3 +63,44 @@ snapshot_test!(
     "example-config.toml",
     "restic-s3bucket-backup.service"
 );
+
+#[test]
+fn stdout_output() -> Result<(), Box<dyn Error>> {
+    let mut cmd = Command::cargo_bin("restic-generator")?;
+    cmd.arg("-c").arg("example-config.toml").arg("--stdout");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    insta::assert_snapshot!(String::from_utf8(output)?);
+    Ok(())
+}
+
+#[test]
+fn crontab_output() -> Result<(), Box<dyn Error>> {
+    let mut cmd = Command::cargo_bin("restic-generator")?;
+    cmd.args([
+        "-c",
+        "example-config.toml",
+        "render",
+        "--stdout",
+        "--format",
+        "crontab",
+    ]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    insta::assert_snapshot!(String::from_utf8(output)?);
+    Ok(())
+}
+
+#[test]
+fn launchd_output() -> Result<(), Box<dyn Error>> {
+    let mut cmd = Command::cargo_bin("restic-generator")?;
+    cmd.args([
+        "-c",
+        "example-config.toml",
+        "render",
+        "--stdout",
+        "--format",
+        "launchd",
+    ]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    insta::assert_snapshot!(String::from_utf8(output)?);
+    Ok(())
+}